@@ -0,0 +1,3 @@
+alter table live_streamers add column split_clock INTEGER;
+alter table live_streamers add column split_clock_offset INTEGER;
+alter table live_streamers add column split_all INTEGER not null default 0;
@@ -0,0 +1,5 @@
+alter table live_streamers add column quality INTEGER;
+alter table live_streamers add column codec TEXT;
+alter table live_streamers add column protocol TEXT;
+alter table live_streamers add column cdn_prefer TEXT not null default '';
+alter table live_streamers add column cdn_blacklist TEXT not null default '';
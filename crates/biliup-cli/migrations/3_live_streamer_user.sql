@@ -0,0 +1 @@
+alter table live_streamers add column user_id INTEGER;
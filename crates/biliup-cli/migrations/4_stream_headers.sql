@@ -0,0 +1 @@
+alter table live_streamers add column headers TEXT not null default '';
@@ -0,0 +1 @@
+alter table live_streamers add column postprocess TEXT not null default '';
@@ -0,0 +1,3 @@
+alter table live_streamers add column min_segment_size INTEGER;
+alter table live_streamers add column min_segment_duration INTEGER;
+alter table live_streamers add column undersized TEXT not null default 'skip';
@@ -0,0 +1,4 @@
+create table if not exists uploaded_recordings
+(
+    path TEXT PRIMARY KEY NOT NULL
+);
@@ -0,0 +1 @@
+alter table live_streamers add column split_on_change INTEGER;
@@ -1,5 +1,10 @@
+use biliup::client::proxy::ProxyRoutes;
+use biliup::client::{ClientConfig, HttpVersion, parse_headers};
+use biliup::downloader::extractor::{Codec, StreamOptions};
+use biliup::downloader::filter::Undersized;
+use biliup::downloader::postprocess::Step;
 use biliup::uploader::bilibili::{Studio, Vid};
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 use std::path::PathBuf;
 
@@ -12,19 +17,93 @@ pub struct Cli {
     #[clap(subcommand)]
     pub command: Commands,
 
-    /// 配置代理
-    #[arg(short, long, default_value = None)]
-    pub proxy: Option<String>,
+    /// 配置代理, 支持 http(s)、socks5 与 socks5h, 可重复.
+    /// 按用途或域名分流: extract=URL (解析直播间), download=URL (下载直播流),
+    /// upload=URL (登录与上传), *.douyu.com=URL, 以 direct 表示直连, 未匹配的请求使用系统代理
+    #[arg(short, long, value_parser = proxy_rule)]
+    pub proxy: Vec<String>,
 
     /// 登录信息文件
     #[arg(short, long, default_value = "cookies.json")]
     pub user_cookie: PathBuf,
 
+    /// 将HTTP请求与响应录制到文件, 用于离线测试或反馈问题
+    #[arg(long, value_name = "FILE", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    /// 使用录制的文件回放HTTP响应, 不访问网络
+    #[arg(long, value_name = "FILE")]
+    pub replay: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub client: ClientArgs,
+
     // #[arg(long, default_value = "sqlx=debug,tower_http=debug,info")]
     #[arg(long, default_value = "tower_http=debug,info")]
     pub rust_log: String,
 }
 
+#[derive(Args)]
+pub struct ClientArgs {
+    /// 替换默认的User-Agent
+    #[arg(long)]
+    pub user_agent: Option<String>,
+
+    /// 连接超时
+    #[arg(long, default_value = "60s")]
+    pub connect_timeout: humantime::Duration,
+
+    /// 读取超时, 默认不限制
+    #[arg(long)]
+    pub read_timeout: Option<humantime::Duration>,
+
+    /// 附加到每个请求的请求头, 如 "Accept-Language: zh-CN", 可重复
+    #[arg(long = "header", value_name = "HEADER", value_parser = name_value)]
+    pub headers: Vec<(String, String)>,
+
+    /// 失败请求的最大重试次数
+    #[arg(long, default_value = "5")]
+    pub http_retries: u32,
+
+    /// 重试间隔的下限与上限, 按指数增长
+    #[arg(long, default_value = "1s")]
+    pub retry_min_interval: humantime::Duration,
+
+    #[arg(long, default_value = "30m")]
+    pub retry_max_interval: humantime::Duration,
+
+    /// 额外信任的PEM格式CA证书, 可重复
+    #[arg(long, value_name = "FILE")]
+    pub ca_cert: Vec<PathBuf>,
+
+    /// HTTP版本
+    #[arg(long, value_enum, default_value = "auto")]
+    pub http_version: HttpVersion,
+}
+
+impl Cli {
+    /// The `--proxy` rules as one [`ProxyRoutes`] string.
+    pub fn proxy(&self) -> Option<String> {
+        (!self.proxy.is_empty()).then(|| self.proxy.join(","))
+    }
+}
+
+impl ClientArgs {
+    pub fn config(&self) -> biliup::error::Result<ClientConfig> {
+        Ok(ClientConfig {
+            user_agent: self.user_agent.clone(),
+            connect_timeout: self.connect_timeout.into(),
+            read_timeout: self.read_timeout.map(Into::into),
+            headers: parse_headers(self.headers.iter().map(|(name, value)| (name, value)))?,
+            max_retries: self.http_retries,
+            min_retry_interval: self.retry_min_interval.into(),
+            max_retry_interval: self.retry_max_interval.into(),
+            ca_certificates: self.ca_cert.clone(),
+            http_version: self.http_version,
+        })
+    }
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     /// 登录B站并保存登录信息
@@ -106,6 +185,7 @@ pub enum Commands {
         url: String,
 
         /// Output filename template. e.p. "./video/%Y-%m-%dT%H_%M_%S{title}"
+        /// Placeholders: {title} {streamer} {platform} {room_id} {uid} {area} {live_start_time} {seq}
         #[arg(short, long, default_value = "{title}")]
         output: String,
 
@@ -116,6 +196,87 @@ pub enum Commands {
         /// 按照时间分割视频
         #[arg(long)]
         split_time: Option<humantime::Duration>,
+
+        /// 按照整点时刻分割视频, 如 1h 为每个整点, 24h 为每天零点, 与其他分割条件的组合见 --split-all
+        #[arg(long)]
+        split_clock: Option<humantime::Duration>,
+
+        /// 整点分割时刻相对零点的偏移, 如 --split-clock 24h --split-clock-offset 4h 为每天4点
+        #[arg(long, requires = "split_clock")]
+        split_clock_offset: Option<humantime::Duration>,
+
+        /// 所有分割条件都满足时才分割视频, 默认为任一条件满足时分割
+        #[arg(long)]
+        split_all: bool,
+
+        /// 每隔指定时间检查直播间标题与分区, 发生变化时分割视频, 新文件名使用新的标题
+        #[arg(long)]
+        split_on_change: Option<humantime::Duration>,
+
+        #[command(flatten)]
+        stream_options: StreamOptions,
+
+        /// 断流后重连的最大连续尝试次数, 0为不重连
+        #[arg(long, default_value = "5")]
+        reconnect_retries: u32,
+
+        /// 磁盘剩余空间低于此值时不开始或停止录制, 如 5G
+        #[arg(long, value_parser = human_size)]
+        min_free_space: Option<u64>,
+
+        /// 录制完成的文件依次执行的后处理, 可多次指定:
+        /// remux:<格式> move:<目录> exec:<命令> sha256
+        /// 命令中可用 {path} {dir} {name} {stem}
+        #[arg(long = "postprocess", value_name = "STEP")]
+        postprocess: Vec<Step>,
+
+        /// 小于此大小的文件按 --undersized 处理, 如 10M
+        #[arg(long, value_parser = human_size)]
+        min_segment_size: Option<u64>,
+
+        /// 短于此时长的文件按 --undersized 处理, 如 1m
+        #[arg(long)]
+        min_segment_duration: Option<humantime::Duration>,
+
+        /// 过小文件的处理方式: 保留(skip), 删除(delete), 合并到下一个文件(merge)
+        #[arg(long, value_enum, default_value = "skip")]
+        undersized: Undersized,
+    },
+    /// 查看直播间信息与可用的直播流, 不录制
+    Info {
+        url: String,
+
+        #[command(flatten)]
+        stream_options: StreamOptions,
+
+        /// 以JSON格式输出
+        #[arg(long)]
+        json: bool,
+
+        /// 连接直播流并读取封装与编码参数
+        #[arg(long)]
+        probe: bool,
+    },
+    /// 下载已投稿的视频
+    Vod {
+        /// vid为稿件 av 或 bv 号
+        vid: Vid,
+
+        /// 分P序号
+        #[arg(long, default_value = "1")]
+        page: usize,
+
+        /// 目标画质qn, 不可用时选择最高画质
+        #[arg(long)]
+        quality: Option<u32>,
+
+        /// 视频编码偏好
+        #[arg(long, value_enum)]
+        codec: Option<Codec>,
+
+        /// Output filename template. Placeholders: {title} {part}
+        #[arg(short, long, default_value = "{title}")]
+        output: String,
     },
     #[cfg(feature = "server")]
     /// 启动web服务，默认端口19159
@@ -127,6 +288,18 @@ pub enum Commands {
         /// Port to use
         #[arg(short, long, default_value = "19159")]
         port: u16,
+
+        /// 磁盘剩余空间低于此值时不开始或停止录制, 如 5G
+        #[arg(long, value_parser = human_size)]
+        min_free_space: Option<u64>,
+
+        /// 空间不足时按时间顺序删除最早的已上传录播
+        #[arg(long, requires = "min_free_space")]
+        delete_uploaded: bool,
+
+        /// 允许通过API设置会执行命令或移动文件的后处理步骤 (exec, move)
+        #[arg(long)]
+        allow_host_steps: bool,
     },
     /// 列出所有已上传的视频
     List {
@@ -174,6 +347,18 @@ pub enum SubmitOption {
     Web,
 }
 
+fn proxy_rule(s: &str) -> Result<String, String> {
+    s.parse::<ProxyRoutes>()?;
+    Ok(s.to_string())
+}
+
+fn name_value(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected NAME: VALUE, got {s}"))?;
+    Ok((name.trim().to_string(), value.trim().to_string()))
+}
+
 fn human_size(s: &str) -> Result<u64, String> {
     let ret = match s.as_bytes() {
         [init @ .., b'K'] => parse_u8(init)? * 1000.0,
@@ -1,5 +1,8 @@
 use anyhow::{Context, Result};
-use biliup::downloader::extractor::find_extractor;
+use biliup::client::{StatefulClient, StatelessClient};
+use biliup::downloader::event::{EventHandler, RecordingEvent};
+use biliup::downloader::extractor::{Reconnect, StreamOptions, find_extractor};
+use biliup::downloader::filter::SegmentFilter;
 use biliup::downloader::flv_parser::{
     CodecId, SoundFormat, TagData, aac_audio_packet_header, avc_video_packet_header, header,
     script_data, tag_data, tag_header,
@@ -7,30 +10,227 @@ use biliup::downloader::flv_parser::{
 use biliup::downloader::flv_writer;
 use biliup::downloader::flv_writer::{FlvTag, TagDataHeader};
 use biliup::downloader::httpflv::map_parse_err;
+use biliup::downloader::postprocess::{PostProcessor, Step};
+use biliup::downloader::storage::DiskGuard;
 use biliup::downloader::util::Segmentable;
+use biliup::downloader::vod::{self, VodOptions};
+use biliup::uploader::bilibili::Vid;
+use biliup::uploader::credential;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
+use reqwest::header::HeaderMap;
 use std::io::{BufReader, BufWriter, ErrorKind, Read};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 use tracing::{error, info, warn};
 
+#[allow(clippy::too_many_arguments)]
 pub async fn download(
     url: &str,
     output: String,
-    split_size: Option<u64>,
-    split_time: Option<humantime::Duration>,
+    segmentable: Segmentable,
+    mut stream_options: StreamOptions,
+    reconnect_retries: u32,
+    watch_interval: Option<Duration>,
+    guard: Option<DiskGuard>,
+    postprocess: Vec<Step>,
+    user_cookie: PathBuf,
+    proxy: Option<&str>,
 ) -> Result<()> {
-    let segmentable = Segmentable::new(split_time.map(|t| t.into()), split_size);
-    let client = Default::default();
+    set_credential(&mut stream_options, &user_cookie, proxy).await;
+    let client = StatelessClient::new(HeaderMap::new(), proxy)?;
     if let Some(extractor) = find_extractor(url) {
-        let mut site = extractor.get_site(url, client).await?;
-        site.download(&output, segmentable, None).await?;
+        let mut site = extractor
+            .get_site_with_options(url, client, &stream_options)
+            .await?;
+        let reconnect = Reconnect {
+            retries: reconnect_retries,
+            watch_interval,
+            ..Reconnect::new(extractor, url, stream_options)
+        };
+        // Merging runs on the post-processing thread too.
+        let merges = segmentable.filter().is_some_and(SegmentFilter::merges);
+        let postprocess =
+            (!postprocess.is_empty() || merges).then(|| PostProcessor::new(postprocess));
+        let result = site
+            .download_with_reconnect(
+                &output,
+                segmentable,
+                Some(log_events()),
+                Some(&reconnect),
+                guard.as_ref(),
+                postprocess.as_ref(),
+            )
+            .await;
+        if let Some(postprocess) = postprocess {
+            tokio::task::spawn_blocking(|| postprocess.finish()).await?;
+        }
+        result?;
     } else {
         warn!("not find extractor for {url}")
     }
     Ok(())
 }
 
+fn log_events() -> EventHandler {
+    Arc::new(|event| {
+        if let RecordingEvent::SegmentClosed(segment) = event {
+            info!(
+                "已保存 {} ({} bytes, {:.1?})",
+                segment.path.display(),
+                segment.size,
+                segment.duration
+            )
+        }
+    })
+}
+
+/// Passes the account in `user_cookie` to the extractors, if it's still valid.
+async fn set_credential(
+    stream_options: &mut StreamOptions,
+    user_cookie: &Path,
+    proxy: Option<&str>,
+) {
+    if !user_cookie.exists() {
+        return;
+    }
+    match credential::login_by_cookies(user_cookie, proxy).await {
+        Ok(bili) => stream_options.credential = Some((&bili.login_info).into()),
+        Err(e) => warn!(
+            "登录信息 {} 无效, 将以游客身份下载: {e}",
+            user_cookie.display()
+        ),
+    }
+}
+
+/// Prints what the extractor resolves for `url`, optionally reading the stream header.
+pub async fn info(
+    url: &str,
+    mut stream_options: StreamOptions,
+    json: bool,
+    probe: bool,
+    user_cookie: PathBuf,
+    proxy: Option<&str>,
+) -> Result<()> {
+    let extractor = find_extractor(url).with_context(|| format!("not find extractor for {url}"))?;
+    set_credential(&mut stream_options, &user_cookie, proxy).await;
+    let client = StatelessClient::new(HeaderMap::new(), proxy)?;
+    let status = extractor
+        .live_status_with_options(url, client.clone(), &stream_options)
+        .await;
+    let mut site = match &status {
+        Ok(status) if status.live => Some(
+            extractor
+                .get_site_with_options(url, client, &stream_options)
+                .await?,
+        ),
+        _ => None,
+    };
+    let stream_probe = match &mut site {
+        Some(site) if probe => Some(site.probe().await?),
+        _ => None,
+    };
+
+    if json {
+        let site = site.as_ref().map(|site| {
+            serde_json::json!({
+                "name": site.name,
+                "title": site.title,
+                "streamer": site.streamer,
+                "room_id": site.room_id,
+                "uid": site.uid,
+                "area": site.area,
+                "cover": site.cover,
+                "live_start_time": site.live_start_time.map(|t| t.to_rfc3339()),
+                "direct_url": site.direct_url,
+                "fallback_urls": site.fallback_urls,
+                "extension": site.extension(),
+                "streams": site.streams,
+            })
+        });
+        let output = serde_json::json!({
+            "url": url,
+            "live": status.as_ref().is_ok_and(|s| s.live),
+            "status": status.as_ref().ok(),
+            "site": site,
+            "probe": stream_probe,
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+
+    match &status {
+        Ok(status) => {
+            println!("Live: {}", status.live);
+            if !status.title.is_empty() {
+                println!("Title: {}", status.title);
+            }
+        }
+        Err(e) => println!("Live: false ({e})"),
+    }
+    let Some(site) = site else {
+        return Ok(());
+    };
+    println!("{site}");
+    println!("Format: {}", site.extension().as_str());
+    for fallback_url in &site.fallback_urls {
+        println!("Fallback url: {fallback_url}");
+    }
+    if !site.streams.is_empty() {
+        println!("Streams:");
+    }
+    for stream in &site.streams {
+        let quality = stream
+            .quality
+            .map(|q| format!(" (quality {q})"))
+            .unwrap_or_default();
+        let codec = stream.codec.map(|c| c.as_str()).unwrap_or("-");
+        let protocol = stream.protocol.map(|p| p.as_str()).unwrap_or("-");
+        println!(
+            "  {}{quality} {protocol} {codec} {}",
+            stream.name,
+            stream.cdns.join(", ")
+        );
+    }
+    if let Some(stream_probe) = stream_probe {
+        print!("{stream_probe}");
+    }
+    Ok(())
+}
+
+pub async fn download_vod(
+    user_cookie: PathBuf,
+    vid: Vid,
+    options: VodOptions,
+    output: &str,
+    proxy: Option<&str>,
+) -> Result<()> {
+    let login = if user_cookie.exists() {
+        credential::login_by_cookies(&user_cookie, proxy)
+            .await
+            .inspect_err(|e| {
+                warn!(
+                    "登录信息 {} 无效, 仅能下载480P及以下画质: {e}",
+                    user_cookie.display()
+                )
+            })
+            .ok()
+    } else {
+        warn!(
+            "未找到登录信息 {}, 仅能下载480P及以下画质",
+            user_cookie.display()
+        );
+        None
+    };
+    let client = match login {
+        Some(bili) => bili.http,
+        None => StatefulClient::new(HeaderMap::new(), proxy)?.http,
+    };
+    vod::download(&client, &vid, &options, output).await?;
+    Ok(())
+}
+
 pub fn generate_json(mut file_name: PathBuf) -> Result<()> {
     // let args: Vec<String> = env::args().collect();
     // let file_name = &args[1];
@@ -5,10 +5,16 @@ mod server;
 mod uploader;
 
 use anyhow::Result;
+use biliup::client::cassette::{Cassette, set_cassette};
+use biliup::client::set_client_config;
+use biliup::downloader::filter::SegmentFilter;
+use biliup::downloader::storage::DiskGuard;
+use biliup::downloader::util::{SegmentPolicy, Segmentable};
+use biliup::downloader::vod::VodOptions;
 use time::macros::format_description;
 
 use crate::cli::{Cli, Commands};
-use crate::downloader::{download, generate_json};
+use crate::downloader::{download, download_vod, generate_json, info};
 use crate::uploader::{append, list, login, renew, show, upload_by_command, upload_by_config};
 
 use clap::Parser;
@@ -43,10 +49,18 @@ async fn main() -> Result<()> {
         .with(tracing_subscriber::fmt::layer().with_timer(timer))
         .init();
 
+    if let Some(path) = &cli.record {
+        set_cassette(Some(Cassette::record(path)?));
+    } else if let Some(path) = &cli.replay {
+        set_cassette(Some(Cassette::replay(path)?));
+    }
+    set_client_config(cli.client.config()?)?;
+    let proxy = cli.proxy();
+
     match cli.command {
-        Commands::Login => login(cli.user_cookie, cli.proxy.as_deref()).await?,
+        Commands::Login => login(cli.user_cookie, proxy.as_deref()).await?,
         Commands::Renew => {
-            renew(cli.user_cookie, cli.proxy.as_deref()).await?;
+            renew(cli.user_cookie, proxy.as_deref()).await?;
         }
         Commands::Upload {
             video_path,
@@ -65,7 +79,7 @@ async fn main() -> Result<()> {
                 limit,
                 retry,
                 submit,
-                cli.proxy.as_deref(),
+                proxy.as_deref(),
             )
             .await?
         }
@@ -73,7 +87,7 @@ async fn main() -> Result<()> {
             video_path: _,
             config: Some(config),
             ..
-        } => upload_by_config(config, cli.user_cookie, cli.proxy.as_deref()).await?,
+        } => upload_by_config(config, cli.user_cookie, proxy.as_deref()).await?,
         Commands::Append {
             video_path,
             vid,
@@ -89,20 +103,110 @@ async fn main() -> Result<()> {
                 line,
                 limit,
                 retry,
-                cli.proxy.as_deref(),
+                proxy.as_deref(),
             )
             .await?
         }
-        Commands::Show { vid } => show(cli.user_cookie, vid, cli.proxy.as_deref()).await?,
+        Commands::Show { vid } => show(cli.user_cookie, vid, proxy.as_deref()).await?,
         Commands::DumpFlv { file_name } => generate_json(file_name)?,
         Commands::Download {
             url,
             output,
             split_size,
             split_time,
-        } => download(&url, output, split_size, split_time).await?,
+            split_clock,
+            split_clock_offset,
+            split_all,
+            split_on_change,
+            stream_options,
+            reconnect_retries,
+            min_free_space,
+            postprocess,
+            min_segment_size,
+            min_segment_duration,
+            undersized,
+        } => {
+            let mut segmentable = segmentable(
+                split_time,
+                split_size,
+                split_clock,
+                split_clock_offset,
+                split_all,
+            );
+            if min_segment_size.is_some() || min_segment_duration.is_some() {
+                segmentable = segmentable.with_filter(SegmentFilter::new(
+                    min_segment_size,
+                    min_segment_duration.map(Into::into),
+                    undersized,
+                ));
+            }
+            download(
+                &url,
+                output,
+                segmentable,
+                stream_options,
+                reconnect_retries,
+                split_on_change.map(Into::into),
+                min_free_space.map(DiskGuard::new),
+                postprocess,
+                cli.user_cookie,
+                proxy.as_deref(),
+            )
+            .await?
+        }
+        Commands::Info {
+            url,
+            stream_options,
+            json,
+            probe,
+        } => {
+            info(
+                &url,
+                stream_options,
+                json,
+                probe,
+                cli.user_cookie,
+                proxy.as_deref(),
+            )
+            .await?
+        }
+        Commands::Vod {
+            vid,
+            page,
+            quality,
+            codec,
+            output,
+        } => {
+            download_vod(
+                cli.user_cookie,
+                vid,
+                VodOptions {
+                    page,
+                    quality,
+                    codec,
+                },
+                &output,
+                proxy.as_deref(),
+            )
+            .await?
+        }
         #[cfg(feature = "server")]
-        Commands::Server { bind, port } => server::run((&bind, port)).await?,
+        Commands::Server {
+            bind,
+            port,
+            min_free_space,
+            delete_uploaded,
+            allow_host_steps,
+        } => {
+            server::run(
+                (&bind, port),
+                min_free_space,
+                delete_uploaded,
+                proxy,
+                allow_host_steps,
+            )
+            .await?
+        }
         Commands::List {
             is_pubing,
             pubed,
@@ -115,7 +219,7 @@ async fn main() -> Result<()> {
                 is_pubing,
                 pubed,
                 not_pubed,
-                cli.proxy.as_deref(),
+                proxy.as_deref(),
                 from_page,
                 max_pages,
             )
@@ -124,3 +228,27 @@ async fn main() -> Result<()> {
     };
     Ok(())
 }
+
+/// Splits on whichever of the configured conditions is met first, or once all are with `all`.
+fn segmentable(
+    split_time: Option<humantime::Duration>,
+    split_size: Option<u64>,
+    split_clock: Option<humantime::Duration>,
+    split_clock_offset: Option<humantime::Duration>,
+    all: bool,
+) -> Segmentable {
+    let policies: Vec<SegmentPolicy> = [
+        split_time.map(|t| SegmentPolicy::Time(t.into())),
+        split_size.map(SegmentPolicy::Size),
+        split_clock.map(|interval| SegmentPolicy::Clock {
+            interval: interval.into(),
+            offset: split_clock_offset.map(Into::into).unwrap_or_default(),
+        }),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    SegmentPolicy::combine(policies, all)
+        .map(Segmentable::with_policy)
+        .unwrap_or_default()
+}
@@ -14,6 +14,7 @@ pub mod infrastructure {
         pub mod live_streamers_repository;
         pub mod upload_records_repository;
         pub mod upload_streamers_repository;
+        pub mod uploaded_recordings_repository;
         pub mod users_repository;
         pub mod videos_repository;
     }
@@ -26,11 +27,21 @@ pub mod infrastructure {
 use anyhow::{Context, Result};
 
 use crate::server::api::router::ApplicationController;
+use crate::server::core::storage::StorageMonitor;
 use crate::server::infrastructure::connection_pool::ConnectionManager;
 use crate::server::infrastructure::service_register::ServiceRegister;
 use std::net::ToSocketAddrs;
 
-pub async fn run(addr: (&str, u16)) -> Result<()> {
+/// `proxy` holds the `ProxyRoutes` of the rooms without their own, `allow_host_steps`
+/// lets the API set post-processing steps that run commands or move files. Recordings
+/// are refused below `min_free` bytes, see [`StorageMonitor::new`].
+pub async fn run(
+    addr: (&str, u16),
+    min_free: Option<u64>,
+    delete_uploaded: bool,
+    proxy: Option<String>,
+    allow_host_steps: bool,
+) -> Result<()> {
     // let config = Arc::new(AppConfig::parse());
 
     tracing::info!(
@@ -40,11 +51,17 @@ pub async fn run(addr: (&str, u16)) -> Result<()> {
         .await
         .expect("could not initialize the database connection pool");
 
-    let service_register = ServiceRegister::new(conn_pool);
+    let service_register = ServiceRegister::new(conn_pool, allow_host_steps);
+    let storage = StorageMonitor::new(
+        min_free,
+        delete_uploaded,
+        service_register.uploaded_recordings_repository.clone(),
+    )
+    .await?;
 
     tracing::info!("migrations successfully ran, initializing axum server...");
     let addr = addr.to_socket_addrs()?.next().unwrap();
-    ApplicationController::serve(&addr, service_register)
+    ApplicationController::serve(&addr, service_register, storage, proxy)
         .await
         .context("could not initialize application routes")?;
     Ok(())
@@ -3,6 +3,7 @@ use crate::server::core::live_streamers::{
     AddLiveStreamerDto, DynLiveStreamersRepository, DynLiveStreamersService, LiveStreamerDto,
     LiveStreamerEntity,
 };
+use crate::server::core::storage::{StorageMonitor, StorageStatus};
 use crate::server::core::upload_streamers::{DynUploadStreamersRepository, StudioEntity};
 use crate::server::core::users::{DynUsersRepository, User};
 use crate::server::errors::AppResult;
@@ -49,12 +50,14 @@ pub async fn delete_streamer_endpoint(
 }
 
 pub async fn update_streamer_endpoint(
-    State(state): State<DynLiveStreamersRepository>,
+    Extension(streamers_service): Extension<DynLiveStreamersService>,
     Extension(download_actor_handle): Extension<DownloadActorHandle>,
     Json(request): Json<LiveStreamerEntity>,
 ) -> AppResult<Json<LiveStreamerDto>> {
-    download_actor_handle.update_streamer(&request.url);
-    Ok(Json(state.update_streamer(request).await?.into_dto()))
+    let url = request.url.clone();
+    let streamer = streamers_service.update_streamer(request).await?;
+    download_actor_handle.update_streamer(&url);
+    Ok(Json(streamer))
 }
 
 pub async fn add_upload_streamer_endpoint(
@@ -110,3 +113,13 @@ pub async fn delete_user_endpoint(
 ) -> AppResult<Json<()>> {
     Ok(Json(state.delete_user(id).await?))
 }
+
+pub async fn get_storage_endpoint(
+    Extension(storage): Extension<StorageMonitor>,
+    Extension(streamers_service): Extension<DynLiveStreamersService>,
+) -> AppResult<Json<StorageStatus>> {
+    let streamers = streamers_service.get_streamers().await?;
+    Ok(Json(storage.status(
+        streamers.iter().map(|streamer| streamer.filename.as_str()),
+    )))
+}
@@ -1,13 +1,15 @@
 use biliup::client::StatelessClient;
+use reqwest::header::HeaderMap;
 
 use crate::server::api::endpoints::{
     add_streamer_endpoint, add_upload_streamer_endpoint, add_user_endpoint,
-    delete_streamer_endpoint, delete_template_endpoint, delete_user_endpoint,
+    delete_streamer_endpoint, delete_template_endpoint, delete_user_endpoint, get_storage_endpoint,
     get_streamer_endpoint, get_streamers_endpoint, get_upload_streamer_endpoint,
     get_upload_streamers_endpoint, get_users_endpoint, update_streamer_endpoint,
     update_template_endpoint,
 };
 use crate::server::core::download_actor::DownloadActorHandle;
+use crate::server::core::storage::StorageMonitor;
 
 use crate::server::infrastructure::service_register::ServiceRegister;
 
@@ -28,14 +30,21 @@ use tracing::info;
 pub struct ApplicationController;
 
 impl ApplicationController {
-    pub async fn serve(addr: &SocketAddr, service_register: ServiceRegister) -> anyhow::Result<()> {
-        let client = StatelessClient::default();
+    pub async fn serve(
+        addr: &SocketAddr,
+        service_register: ServiceRegister,
+        storage: StorageMonitor,
+        proxy: Option<String>,
+    ) -> anyhow::Result<()> {
+        let client = StatelessClient::new(HeaderMap::new(), proxy.as_deref())?;
         let vec = service_register.streamers_service.get_streamers().await?;
         let (main_loop, _) = spawn_main_loop();
         let actor_handle = DownloadActorHandle::new(
             vec,
             client.clone(),
             service_register.streamers_service.clone(),
+            storage.clone(),
+            proxy,
         );
         // build our application with a route
         let app = Router::new()
@@ -60,6 +69,7 @@ impl ApplicationController {
             .route("/v1/upload/streamers", post(add_upload_streamer_endpoint))
             .route("/v1/users", get(get_users_endpoint).post(add_user_endpoint))
             .route("/v1/users/:id", delete(delete_user_endpoint))
+            .route("/v1/storage", get(get_storage_endpoint))
             .route("/bili/archive/pre", get(archive_pre_endpoint))
             .route("/bili/space/myinfo", get(get_myinfo_endpoint))
             .route("/bili/proxy", get(get_proxy_endpoint))
@@ -78,6 +88,7 @@ impl ApplicationController {
             )
             .layer(Extension(service_register.streamers_service.clone()))
             .layer(Extension(actor_handle))
+            .layer(Extension(storage))
             // .layer(Extension(client.clone()))
             .layer(Extension(
                 service_register.upload_streamers_repository.clone(),
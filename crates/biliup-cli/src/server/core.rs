@@ -3,6 +3,7 @@ use serde::{Deserialize, Serialize};
 pub mod download_actor;
 pub mod live_streamers;
 pub mod main_loop;
+pub mod storage;
 pub mod upload_actor;
 pub mod upload_streamers;
 pub mod users;
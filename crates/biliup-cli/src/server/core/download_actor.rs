@@ -2,14 +2,19 @@ use biliup::client::StatelessClient;
 
 use crate::server::core::StreamStatus;
 use crate::server::core::live_streamers::{DynLiveStreamersService, LiveStreamerDto};
+use crate::server::core::storage::{DEFAULT_FILENAME, StorageMonitor};
 use crate::server::core::upload_actor::UploadActorHandle;
 use crate::server::core::util::{AnyMap, Cycle, logging_spawn};
-use biliup::downloader::extractor::{SiteDefinition, find_extractor};
-use biliup::downloader::util::Segmentable;
+use biliup::downloader::event::{EventHandler, RecordingEvent};
+use biliup::downloader::extractor::{DynSiteDefinition, LiveStatus, Reconnect, find_extractor};
+use biliup::downloader::filter::SegmentFilter;
+use biliup::downloader::postprocess::PostProcessor;
 
 use indexmap::indexmap;
 
+use reqwest::header::HeaderMap;
 use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 use std::error::Error;
 use std::ops::DerefMut;
 use std::sync::{Arc, RwLock};
@@ -20,93 +25,262 @@ use tracing::{debug, error};
 
 async fn start_monitor(
     task: Cycle<StreamStatus>,
-    extractor: &(dyn SiteDefinition + Send + Sync),
+    extractor: DynSiteDefinition,
     client: StatelessClient,
     live_streamers_service: DynLiveStreamersService,
+    storage: StorageMonitor,
+    proxy: Option<String>,
 ) {
-    let n = &mut 0;
+    // Clients by proxy, for the rooms with a proxy of their own.
+    let mut clients = HashMap::from([(proxy.clone(), client)]);
     loop {
-        let (url, status) = task.get(n);
-        if status != StreamStatus::Working {
+        // Rooms being recorded are left alone until their recording ends.
+        let urls: Vec<String> = task
+            .get_all()
+            .into_iter()
+            .filter(|(_, status)| *status != StreamStatus::Working)
+            .map(|(url, _)| url)
+            .collect();
+        // The streamers of all rooms, read in one go with the room list.
+        let mut streamers: HashMap<String, LiveStreamerDto> = live_streamers_service
+            .get_streamers()
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .map(|streamer| (streamer.url.clone(), streamer))
+            .collect();
+        // Rooms by proxy, and whether they're checked with the options of their streamer.
+        let mut rooms: HashMap<Option<String>, Vec<(String, bool)>> = HashMap::new();
+        for url in urls {
             task.change(&url, StreamStatus::Inspecting);
+            let streamer = streamers.remove(&url);
+            let room_proxy = streamer
+                .as_ref()
+                .and_then(|streamer| streamer.proxy.clone())
+                .or_else(|| proxy.clone());
+            let with_options = streamer.as_ref().is_some_and(needs_options);
+            rooms
+                .entry(room_proxy)
+                .or_default()
+                .push((url, with_options));
         }
-        match (extractor.get_site(&url, client.clone()).await, status) {
-            (Ok(mut site), StreamStatus::Idle | StreamStatus::Inspecting) => {
-                println!("Idle\n {url} \n{site}");
-                let (filename, split_size, split_time) = if let Ok(LiveStreamerDto {
-                    filename,
-                    split_size,
-                    split_time,
-                    ..
-                }) =
-                    live_streamers_service.get_streamer_by_url(&url).await
-                {
-                    (filename, split_size, split_time.map(Duration::from_secs))
-                } else {
-                    ("./video/%Y-%m-%d/%H_%M_%S{title}".to_string(), None, None)
-                };
-                let live_streamers_service = live_streamers_service.clone();
-                {
-                    let client = client.clone();
-                    let url = url.clone();
-                    let task = task.clone();
-                    logging_spawn(async move {
-                        let hook = live_streamers_service
-                            .get_studio_by_url(&url)
-                            .await
-                            .unwrap_or_default()
-                            .map(|studio| -> Box<dyn Fn(&str) + Send> {
-                                let handle = UploadActorHandle::new(client, studio);
-                                Box::new(move |file_name| {
-                                    if let Ok(metadata) = std::fs::metadata(file_name)
-                                        .map_err(|err| error!("{}", err))
-                                    {
-                                        if metadata.len() > 10 * 1024 * 1024 {
-                                            info!("开始上传: {}", file_name);
-                                            handle.send_file_path(file_name);
-                                        }
-                                    }
-                                })
-                            });
-                        if hook.is_none() {
-                            debug!(url = %url, "upload template not set.");
+        for (proxy, rooms) in rooms {
+            let client = match clients.entry(proxy.clone()) {
+                Entry::Occupied(entry) => entry.get().clone(),
+                Entry::Vacant(entry) => {
+                    match StatelessClient::new(HeaderMap::new(), proxy.as_deref()) {
+                        Ok(client) => entry.insert(client).clone(),
+                        Err(e) => {
+                            error!("{rooms:?}: {e}");
+                            for (url, _) in &rooms {
+                                task.change(url, StreamStatus::Idle);
+                            }
+                            continue;
                         }
-
-                        let segmentable = Segmentable::new(split_time, split_size);
-                        // let segmentable = Segmentable::new( None, Some(16*1024*1024));
-                        site.download(&filename, segmentable, hook).await?;
-                        task.change(&url, StreamStatus::Idle);
-                        Ok::<_, Box<dyn Error + Send + Sync>>(())
-                    });
+                    }
                 }
-                task.change(&url, StreamStatus::Working);
-            }
-            (Ok(_site), StreamStatus::Pending) => {
-                println!("Pending");
+            };
+            let (single, batched): (Vec<_>, Vec<_>) = rooms
+                .into_iter()
+                .partition(|(_, with_options)| *with_options);
+            let batched: Vec<String> = batched.into_iter().map(|(url, _)| url).collect();
+            let mut statuses = if batched.is_empty() {
+                Vec::new()
+            } else {
+                extractor.live_statuses(&batched, client.clone()).await
+            };
+            let mut urls = batched;
+            for (url, _) in single {
+                let stream_options = live_streamers_service
+                    .get_streamer_by_url(&url)
+                    .await
+                    .map(|streamer| streamer.stream_options)
+                    .unwrap_or_default();
+                statuses.push(
+                    extractor
+                        .live_status_with_options(&url, client.clone(), &stream_options)
+                        .await,
+                );
+                urls.push(url);
             }
-            (Ok(_site), StreamStatus::Working) => {
-                debug!("Working");
-            }
-            (Err(e), _) => {
-                task.change(&url, StreamStatus::Idle);
-                debug!(url, "{e}")
+            for (url, status) in urls.into_iter().zip(statuses) {
+                match status {
+                    Ok(LiveStatus { live: true, .. }) => {
+                        start_recording(
+                            url,
+                            &task,
+                            &extractor,
+                            &client,
+                            proxy.as_deref(),
+                            &live_streamers_service,
+                            &storage,
+                        )
+                        .await
+                    }
+                    Ok(_) => task.change(&url, StreamStatus::Idle),
+                    Err(e) => {
+                        task.change(&url, StreamStatus::Idle);
+                        debug!(url, "{e}")
+                    }
+                }
             }
         }
         tokio::time::sleep(Duration::from_secs(30)).await;
     }
 }
 
+/// Whether the room has to be checked with the headers, token or account of `streamer`,
+/// which the batched `live_statuses` doesn't take.
+fn needs_options(streamer: &LiveStreamerDto) -> bool {
+    streamer.user_id.is_some()
+        || !streamer.stream_options.headers.is_empty()
+        || streamer.stream_options.twitch_oauth_token.is_some()
+}
+
+/// Resolves the stream of a room that just went live and records it in the background,
+/// uploading through `proxy`.
+async fn start_recording(
+    url: String,
+    task: &Cycle<StreamStatus>,
+    extractor: &DynSiteDefinition,
+    client: &StatelessClient,
+    proxy: Option<&str>,
+    live_streamers_service: &DynLiveStreamersService,
+    storage: &StorageMonitor,
+) {
+    let streamer = live_streamers_service.get_streamer_by_url(&url).await.ok();
+    let segmentable = streamer
+        .as_ref()
+        .map(LiveStreamerDto::segmentable)
+        .unwrap_or_else(|| LiveStreamerDto::default().segmentable());
+    let stream_options = streamer
+        .as_ref()
+        .map(|streamer| streamer.stream_options.clone())
+        .unwrap_or_default();
+    let mut site = match extractor
+        .get_site_with_options(&url, client.clone(), &stream_options)
+        .await
+    {
+        Ok(site) => site,
+        Err(e) => {
+            task.change(&url, StreamStatus::Idle);
+            debug!(url, "{e}");
+            return;
+        }
+    };
+    println!("Idle\n {url} \n{site}");
+    let (filename, postprocess, split_on_change) = if let Some(LiveStreamerDto {
+        filename,
+        postprocess,
+        split_on_change,
+        ..
+    }) = streamer
+    {
+        (
+            filename,
+            postprocess,
+            split_on_change.map(Duration::from_secs),
+        )
+    } else {
+        (DEFAULT_FILENAME.to_string(), Vec::new(), None)
+    };
+    // Merging runs on the post-processing thread too.
+    let merges = segmentable.filter().is_some_and(SegmentFilter::merges);
+    let postprocess = (!postprocess.is_empty() || merges).then(|| PostProcessor::new(postprocess));
+    let live_streamers_service = live_streamers_service.clone();
+    {
+        let proxy = proxy.map(String::from);
+        let url = url.clone();
+        let task = task.clone();
+        let storage = storage.clone();
+        let reconnect = Reconnect {
+            watch_interval: split_on_change,
+            ..Reconnect::new(extractor.clone(), &url, stream_options)
+        };
+        logging_spawn(async move {
+            let upload = match live_streamers_service
+                .get_studio_by_url(&url)
+                .await
+                .unwrap_or_default()
+            {
+                Some(mut studio) => {
+                    studio.title = site.render(&studio.title);
+                    studio.desc = site.render(&studio.desc);
+                    let delete = postprocess
+                        .as_ref()
+                        .is_some_and(PostProcessor::deletes_after_upload);
+                    let client = StatelessClient::for_upload(proxy.as_deref());
+                    if client.is_err() {
+                        task.change(&url, StreamStatus::Idle);
+                    }
+                    Some(UploadActorHandle::new(
+                        client?,
+                        studio,
+                        proxy,
+                        Some(storage.clone()),
+                        delete,
+                    ))
+                }
+                None => None,
+            };
+            if upload.is_none() {
+                debug!(url = %url, "upload template not set.");
+            }
+            let hook: EventHandler = {
+                let storage = storage.clone();
+                Arc::new(move |event| {
+                    storage.record(event);
+                    if let (Some(handle), RecordingEvent::SegmentClosed(segment)) = (&upload, event)
+                    {
+                        info!("开始上传: {}", segment.path.display());
+                        handle.send_file_path(&segment.path);
+                    }
+                })
+            };
+
+            let result = site
+                .download_with_reconnect(
+                    &filename,
+                    segmentable,
+                    Some(hook),
+                    Some(&reconnect),
+                    storage.guard(),
+                    postprocess.as_ref(),
+                )
+                .await;
+            // Rooms refused for lack of space are tried again on the next round.
+            task.change(&url, StreamStatus::Idle);
+            if let Some(postprocess) = postprocess {
+                tokio::task::spawn_blocking(|| postprocess.finish()).await?;
+            }
+            result?;
+            Ok::<_, Box<dyn Error + Send + Sync>>(())
+        });
+    }
+    task.change(&url, StreamStatus::Working);
+}
+
 #[derive(Clone)]
 struct DownloadActor {
     live_streamers_service: DynLiveStreamersService,
     client: StatelessClient,
+    storage: StorageMonitor,
+    /// `ProxyRoutes` of the rooms without their own, which `client` is routed by.
+    proxy: Option<String>,
 }
 
 impl DownloadActor {
-    fn new(live_streamers_service: DynLiveStreamersService, client: StatelessClient) -> Self {
+    fn new(
+        live_streamers_service: DynLiveStreamersService,
+        client: StatelessClient,
+        storage: StorageMonitor,
+        proxy: Option<String>,
+    ) -> Self {
         Self {
             live_streamers_service,
             client,
+            storage,
+            proxy,
         }
     }
 
@@ -141,8 +315,18 @@ impl DownloadActor {
                 let task = cycle.clone();
                 let client = self.client.clone();
                 let live_streamers_service = self.live_streamers_service.clone();
+                let storage = self.storage.clone();
+                let proxy = self.proxy.clone();
                 let handle = tokio::spawn(async move {
-                    start_monitor(task, extractor, client, live_streamers_service).await
+                    start_monitor(
+                        task,
+                        extractor,
+                        client,
+                        live_streamers_service,
+                        storage,
+                        proxy,
+                    )
+                    .await
                 });
                 (cycle, handle)
             });
@@ -163,8 +347,10 @@ impl DownloadActorHandle {
         list: Vec<LiveStreamerDto>,
         client: StatelessClient,
         live_streamers_service: DynLiveStreamersService,
+        storage: StorageMonitor,
+        proxy: Option<String>,
     ) -> Self {
-        let mut actor = DownloadActor::new(live_streamers_service, client);
+        let mut actor = DownloadActor::new(live_streamers_service, client, storage, proxy);
         let platform_map = Arc::new(RwLock::new(HashMap::default()));
         let platform = Arc::clone(&platform_map);
         // let client_c = client.clone();
@@ -1,10 +1,15 @@
 use crate::server::core::StreamStatus;
 use async_trait::async_trait;
+use biliup::downloader::extractor::StreamOptions;
+use biliup::downloader::filter::{SegmentFilter, Undersized};
+use biliup::downloader::postprocess::Step;
+use biliup::downloader::util::{SegmentPolicy, Segmentable};
 use biliup::uploader::bilibili::Studio;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use std::sync::Arc;
+use std::time::Duration;
 
 /// Similar to above, we want to keep a reference count across threads so we can manage our connection pool.
 pub type DynLiveStreamersRepository = Arc<dyn LiveStreamersRepository + Send + Sync>;
@@ -54,6 +59,32 @@ pub struct LiveStreamerEntity {
     pub split_time: Option<i64>,
     pub split_size: Option<i64>,
     pub upload_id: Option<i64>,
+    pub quality: Option<i64>,
+    pub codec: Option<String>,
+    pub protocol: Option<String>,
+    /// Comma separated CDN hosts.
+    pub cdn_prefer: String,
+    /// Comma separated CDN hosts.
+    pub cdn_blacklist: String,
+    /// Account in `users` whose cookies are used to fetch the stream.
+    pub user_id: Option<i64>,
+    /// Newline separated `Name: value` request headers.
+    pub headers: String,
+    /// Newline separated post-processing steps.
+    pub postprocess: String,
+    pub min_segment_size: Option<i64>,
+    /// Seconds.
+    pub min_segment_duration: Option<i64>,
+    pub undersized: String,
+    /// `ProxyRoutes` of the room.
+    pub proxy: Option<String>,
+    /// Seconds between checks of the title and area.
+    pub split_on_change: Option<i64>,
+    /// Seconds.
+    pub split_clock: Option<i64>,
+    /// Seconds.
+    pub split_clock_offset: Option<i64>,
+    pub split_all: i64,
 }
 
 #[derive(FromRow)]
@@ -84,11 +115,50 @@ impl LiveStreamerEntity {
             filename: self.filename,
             split_time: self.split_time.map(|t| t as u64),
             split_size: self.split_size.map(|s| s as u64),
+            stream_options: StreamOptions {
+                quality: self.quality.map(|q| q as u32),
+                codec: self.codec.and_then(|c| c.parse().ok()),
+                protocol: self.protocol.and_then(|p| p.parse().ok()),
+                cdn_prefer: split_hosts(&self.cdn_prefer),
+                cdn_blacklist: split_hosts(&self.cdn_blacklist),
+                headers: self
+                    .headers
+                    .lines()
+                    .map(str::trim)
+                    .filter(|header| !header.is_empty())
+                    .map(String::from)
+                    .collect(),
+                twitch_oauth_token: None,
+                credential: None,
+            },
+            user_id: self.user_id,
+            postprocess: self
+                .postprocess
+                .lines()
+                .filter_map(|step| step.parse().ok())
+                .collect(),
+            min_segment_size: self.min_segment_size.map(|s| s as u64),
+            min_segment_duration: self.min_segment_duration.map(|t| t as u64),
+            undersized: self.undersized.parse().unwrap_or_default(),
+            proxy: self.proxy,
+            split_on_change: self.split_on_change.map(|t| t as u64),
+            split_clock: self.split_clock.map(|t| t as u64),
+            split_clock_offset: self.split_clock_offset.map(|t| t as u64),
+            split_all: self.split_all != 0,
             status: Default::default(),
         }
     }
 }
 
+fn split_hosts(hosts: &str) -> Vec<String> {
+    hosts
+        .split(',')
+        .map(str::trim)
+        .filter(|host| !host.is_empty())
+        .map(String::from)
+        .collect()
+}
+
 /// A reference counter for our user service allows us safely pass instances user utils
 /// around which themselves depend on the user repostiory, and ultimately, our Posgres connection pool.
 pub type DynLiveStreamersService = Arc<dyn LiveStreamersService + Send + Sync>;
@@ -96,6 +166,7 @@ pub type DynLiveStreamersService = Arc<dyn LiveStreamersService + Send + Sync>;
 #[async_trait]
 pub trait LiveStreamersService {
     async fn add_streamer(&self, request: AddLiveStreamerDto) -> anyhow::Result<LiveStreamerDto>;
+    async fn update_streamer(&self, entity: LiveStreamerEntity) -> anyhow::Result<LiveStreamerDto>;
     async fn get_streamer_by_url(&self, url: &str) -> anyhow::Result<LiveStreamerDto>;
     async fn get_streamer_by_id(&self, id: i64) -> anyhow::Result<LiveStreamerDto>;
     async fn get_streamers(&self) -> anyhow::Result<Vec<LiveStreamerDto>>;
@@ -110,6 +181,21 @@ pub struct AddLiveStreamerDto {
     pub split_time: Option<u64>,
     pub split_size: Option<u64>,
     pub upload_id: Option<i64>,
+    pub user_id: Option<i64>,
+    #[serde(flatten)]
+    pub stream_options: StreamOptions,
+    #[serde(default)]
+    pub postprocess: Vec<Step>,
+    pub min_segment_size: Option<u64>,
+    pub min_segment_duration: Option<u64>,
+    #[serde(default)]
+    pub undersized: Undersized,
+    pub proxy: Option<String>,
+    pub split_on_change: Option<u64>,
+    pub split_clock: Option<u64>,
+    pub split_clock_offset: Option<u64>,
+    #[serde(default)]
+    pub split_all: bool,
 }
 
 #[derive(Serialize, Deserialize, Default, Debug)]
@@ -120,5 +206,61 @@ pub struct LiveStreamerDto {
     pub filename: String,
     pub split_time: Option<u64>,
     pub split_size: Option<u64>,
+    #[serde(flatten)]
+    pub stream_options: StreamOptions,
+    pub user_id: Option<i64>,
+    #[serde(default)]
+    pub postprocess: Vec<Step>,
+    /// Files below this size aren't uploaded, [`DEFAULT_MIN_SEGMENT_SIZE`] if unset.
+    pub min_segment_size: Option<u64>,
+    /// Seconds of stream a file needs to be uploaded.
+    pub min_segment_duration: Option<u64>,
+    #[serde(default)]
+    pub undersized: Undersized,
+    /// [`ProxyRoutes`](biliup::client::proxy::ProxyRoutes) for the requests of the room
+    /// and its uploads, the server-wide settings if unset.
+    pub proxy: Option<String>,
+    /// Seconds between checks of the title and area of the room while recording, a
+    /// change starts a new file. Unchecked if unset.
+    pub split_on_change: Option<u64>,
+    /// Seconds between wall-clock splits since local midnight, see [`SegmentPolicy::Clock`].
+    pub split_clock: Option<u64>,
+    /// Seconds the wall-clock splits are moved by.
+    pub split_clock_offset: Option<u64>,
+    /// Splits once every configured condition is met instead of the first one.
+    #[serde(default)]
+    pub split_all: bool,
     pub status: StreamStatus,
 }
+
+pub const DEFAULT_MIN_SEGMENT_SIZE: u64 = 10 * 1024 * 1024;
+
+impl LiveStreamerDto {
+    /// Splits on the configured time, size and wall-clock conditions, see [`Self::split_all`].
+    pub fn segmentable(&self) -> Segmentable {
+        let policies: Vec<SegmentPolicy> = [
+            self.split_time
+                .map(|t| SegmentPolicy::Time(Duration::from_secs(t))),
+            self.split_size.map(SegmentPolicy::Size),
+            self.split_clock.map(|interval| SegmentPolicy::Clock {
+                interval: Duration::from_secs(interval),
+                offset: Duration::from_secs(self.split_clock_offset.unwrap_or_default()),
+            }),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        SegmentPolicy::combine(policies, self.split_all)
+            .map(Segmentable::with_policy)
+            .unwrap_or_default()
+            .with_filter(self.segment_filter())
+    }
+
+    pub fn segment_filter(&self) -> SegmentFilter {
+        SegmentFilter::new(
+            Some(self.min_segment_size.unwrap_or(DEFAULT_MIN_SEGMENT_SIZE)),
+            self.min_segment_duration.map(Duration::from_secs),
+            self.undersized,
+        )
+    }
+}
@@ -4,14 +4,14 @@ use crate::server::core::upload_actor::UploadActorHandle;
 use crate::server::core::util::{Cycle, logging_spawn};
 use anyhow::Result;
 use biliup::client::StatelessClient;
+use biliup::downloader::event::{EventHandler, RecordingEvent};
 use biliup::downloader::extractor::Site;
-use biliup::downloader::util::Segmentable;
 use std::error::Error;
-use std::time::Duration;
+use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
 use tokio::sync::mpsc::{Receiver, channel};
 use tokio::task::JoinHandle;
-use tracing::{debug, error, info};
+use tracing::{debug, info};
 
 /// This struct is used by client actors to send messages to the main loop. The
 /// message type is `ToServer`.
@@ -71,18 +71,15 @@ async fn recording(
     live_streamers_service: DynLiveStreamersService,
 ) {
     println!("Idle\n {url} \n{site}");
-    let (filename, split_size, split_time) = if let Ok(LiveStreamerDto {
-        filename,
-        split_size,
-        split_time,
-        ..
-    }) =
-        live_streamers_service.get_streamer_by_url(url).await
-    {
-        (filename, split_size, split_time.map(Duration::from_secs))
-    } else {
-        ("./video/%Y-%m-%d/%H_%M_%S{title}".to_string(), None, None)
-    };
+    let streamer = live_streamers_service
+        .get_streamer_by_url(url)
+        .await
+        .unwrap_or_else(|_| LiveStreamerDto {
+            filename: "./video/%Y-%m-%d/%H_%M_%S{title}".to_string(),
+            ..Default::default()
+        });
+    let segmentable = streamer.segmentable();
+    let LiveStreamerDto { filename, .. } = streamer;
 
     logging_spawn({
         // let client = client.clone();
@@ -94,24 +91,20 @@ async fn recording(
                 .get_studio_by_url(&url)
                 .await
                 .unwrap_or_default()
-                .map(|studio| -> Box<dyn Fn(&str) + Send> {
-                    let handle = UploadActorHandle::new(client, studio);
-                    Box::new(move |file_name| {
-                        if let Ok(metadata) =
-                            std::fs::metadata(file_name).map_err(|err| error!("{}", err))
-                        {
-                            if metadata.len() > 10 * 1024 * 1024 {
-                                info!("开始上传: {}", file_name);
-                                handle.send_file_path(file_name);
-                            }
+                .map(|mut studio| -> EventHandler {
+                    studio.title = site.render(&studio.title);
+                    studio.desc = site.render(&studio.desc);
+                    let handle = UploadActorHandle::new(client, studio, None, None, false);
+                    Arc::new(move |event| {
+                        if let RecordingEvent::SegmentClosed(segment) = event {
+                            info!("开始上传: {}", segment.path.display());
+                            handle.send_file_path(&segment.path);
                         }
                     })
                 });
             if hook.is_none() {
                 debug!(url = %url, "upload template not set.");
             }
-            let segmentable = Segmentable::new(split_time, split_size);
-            // let segmentable = Segmentable::new( None, Some(16*1024*1024));
             site.download(&filename, segmentable, hook).await?;
             task.change(&url, StreamStatus::Idle);
             Ok::<_, Box<dyn Error + Send + Sync>>(())
@@ -0,0 +1,188 @@
+use async_trait::async_trait;
+use biliup::downloader::event::RecordingEvent;
+use biliup::downloader::storage::{DiskGuard, available_space};
+use serde::Serialize;
+use std::collections::{BTreeSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+use tracing::warn;
+
+/// Storage events kept for the API.
+const MAX_EVENTS: usize = 100;
+
+/// Where recordings go unless their streamer names a template.
+pub const DEFAULT_FILENAME: &str = "./video/%Y-%m-%d/%H_%M_%S{title}";
+
+pub type DynUploadedRecordingsRepository = Arc<dyn UploadedRecordingsRepository + Send + Sync>;
+
+/// Uploaded recordings the retention policy may delete, kept across restarts.
+#[async_trait]
+pub trait UploadedRecordingsRepository {
+    async fn add(&self, path: &str) -> anyhow::Result<()>;
+    async fn remove(&self, path: &str) -> anyhow::Result<()>;
+    async fn get_all(&self) -> anyhow::Result<Vec<String>>;
+}
+
+/// Shares the [`DiskGuard`] of the server between its recordings, remembers the uploaded
+/// recordings the retention policy may delete and the storage events of late.
+#[derive(Clone)]
+pub struct StorageMonitor {
+    guard: Option<DiskGuard>,
+    uploaded: Arc<Mutex<Vec<PathBuf>>>,
+    repository: DynUploadedRecordingsRepository,
+    runtime: Handle,
+    events: Arc<Mutex<VecDeque<StorageEvent>>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageEvent {
+    pub time: String,
+    #[serde(flatten)]
+    pub event: RecordingEvent,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StorageStatus {
+    pub directories: Vec<DirectoryStatus>,
+    pub min_free: Option<u64>,
+    pub events: Vec<StorageEvent>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DirectoryStatus {
+    /// Directory recordings are written to, below it the template adds its own.
+    pub path: PathBuf,
+    /// Bytes free on its disk.
+    pub available: Option<u64>,
+}
+
+impl StorageMonitor {
+    /// Without `min_free` recordings are never refused. With `delete_uploaded` the oldest
+    /// uploaded recordings are deleted when the space runs out, including those uploaded
+    /// before a restart, which `repository` keeps.
+    pub async fn new(
+        min_free: Option<u64>,
+        delete_uploaded: bool,
+        repository: DynUploadedRecordingsRepository,
+    ) -> anyhow::Result<Self> {
+        let uploaded: Arc<Mutex<Vec<PathBuf>>> = Arc::new(Mutex::new(
+            repository
+                .get_all()
+                .await?
+                .into_iter()
+                .map(PathBuf::from)
+                .collect(),
+        ));
+        let runtime = Handle::current();
+        let guard = min_free.map(|min_free| {
+            let guard = DiskGuard::new(min_free);
+            if !delete_uploaded {
+                return guard;
+            }
+            let uploaded = uploaded.clone();
+            let repository = repository.clone();
+            let runtime = runtime.clone();
+            guard.with_retention(Arc::new(move || {
+                let mut uploaded = uploaded.lock().unwrap();
+                uploaded.retain(|path| {
+                    if path.exists() {
+                        return true;
+                    }
+                    let repository = repository.clone();
+                    let path = path.to_string_lossy().into_owned();
+                    runtime.spawn(async move {
+                        if let Err(e) = repository.remove(&path).await {
+                            warn!("{e:#}");
+                        }
+                    });
+                    false
+                });
+                uploaded.clone()
+            }))
+        });
+        Ok(Self {
+            guard,
+            uploaded,
+            repository,
+            runtime,
+            events: Default::default(),
+        })
+    }
+
+    pub fn guard(&self) -> Option<&DiskGuard> {
+        self.guard.as_ref()
+    }
+
+    /// Marks `path` as uploaded, and so deletable.
+    pub fn uploaded(&self, path: &Path) {
+        self.uploaded.lock().unwrap().push(path.to_path_buf());
+        let repository = self.repository.clone();
+        let path = path.to_string_lossy().into_owned();
+        self.runtime.spawn(async move {
+            if let Err(e) = repository.add(&path).await {
+                warn!("{e:#}");
+            }
+        });
+    }
+
+    /// Keeps the events about the storage.
+    pub fn record(&self, event: &RecordingEvent) {
+        if !matches!(
+            event,
+            RecordingEvent::LowDiskSpace { .. } | RecordingEvent::RecordingDeleted { .. }
+        ) {
+            return;
+        }
+        let mut events = self.events.lock().unwrap();
+        if events.len() >= MAX_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(StorageEvent {
+            time: chrono::Local::now().to_rfc3339(),
+            event: event.clone(),
+        });
+    }
+
+    /// Reports the free space of the directories the filename `templates` record to.
+    pub fn status<'a>(&self, templates: impl IntoIterator<Item = &'a str>) -> StorageStatus {
+        let mut dirs: BTreeSet<_> = templates.into_iter().map(recording_dir).collect();
+        if dirs.is_empty() {
+            dirs.insert(recording_dir(DEFAULT_FILENAME));
+        }
+        let directories = dirs
+            .into_iter()
+            .map(|path| {
+                // The directory itself may only be created by the first recording.
+                let existing = path
+                    .ancestors()
+                    .find(|dir| dir.is_dir())
+                    .unwrap_or(Path::new("."));
+                DirectoryStatus {
+                    available: available_space(existing).ok(),
+                    path,
+                }
+            })
+            .collect();
+        StorageStatus {
+            directories,
+            min_free: self.guard.as_ref().map(|guard| guard.min_free),
+            events: self.events.lock().unwrap().iter().cloned().collect(),
+        }
+    }
+}
+
+/// The directories of `template` up to the first one named after a placeholder.
+fn recording_dir(template: &str) -> PathBuf {
+    let dir: PathBuf = Path::new(template)
+        .parent()
+        .into_iter()
+        .flat_map(Path::components)
+        .take_while(|component| !component.as_os_str().to_string_lossy().contains(['%', '{']))
+        .collect();
+    if dir.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        dir
+    }
+}
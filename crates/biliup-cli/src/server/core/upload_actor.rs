@@ -1,3 +1,4 @@
+use crate::server::core::storage::StorageMonitor;
 use biliup::client::StatelessClient;
 use biliup::error::Kind;
 use biliup::uploader::VideoFile;
@@ -16,6 +17,10 @@ struct UploadActor {
     client: StatelessClient,
     studio: Studio,
     vid: Option<Vid>,
+    /// `ProxyRoutes` of the account requests, as `client` is routed.
+    proxy: Option<String>,
+    storage: Option<StorageMonitor>,
+    delete_after_upload: bool,
 }
 enum ActorMessage {
     Upload { path: PathBuf },
@@ -26,12 +31,18 @@ impl UploadActor {
         studio: Studio,
         client: StatelessClient,
         receiver: mpsc::UnboundedReceiver<ActorMessage>,
+        proxy: Option<String>,
+        storage: Option<StorageMonitor>,
+        delete_after_upload: bool,
     ) -> Self {
         UploadActor {
             receiver,
             client,
             studio,
             vid: None,
+            proxy,
+            storage,
+            delete_after_upload,
         }
     }
 
@@ -76,15 +87,16 @@ impl UploadActor {
     async fn handle_message(&mut self, msg: ActorMessage) -> biliup::error::Result<()> {
         match msg {
             ActorMessage::Upload { path } => {
-                let bili = login_by_cookies("cookies.json", None).await?;
+                let proxy = self.proxy.as_deref();
+                let bili = login_by_cookies("cookies.json", proxy).await?;
                 let videos = self
                     .upload(&[path.as_path()], &bili, Default::default(), 3)
                     .await?;
 
                 if let Some(vid) = &self.vid {
-                    let mut studio = bili.studio_data(vid, None).await?;
+                    let mut studio = bili.studio_data(vid, proxy).await?;
                     studio.videos.extend(videos);
-                    bili.edit(&studio, None).await?;
+                    bili.edit(&studio, proxy).await?;
                 } else {
                     let studio = &mut self.studio;
                     studio.videos.extend(videos);
@@ -105,7 +117,7 @@ impl UploadActor {
                             .unwrap_or("录播")
                             .to_string();
                     }
-                    let result = bili.submit(studio, None).await?;
+                    let result = bili.submit(studio, proxy).await?;
                     self.vid = Some(
                         result
                             .data
@@ -116,6 +128,14 @@ impl UploadActor {
                             .ok_or_else(|| Kind::Custom(format!("{:?}", result)))?,
                     );
                 }
+                if self.delete_after_upload {
+                    match std::fs::remove_file(&path) {
+                        Ok(()) => info!("Deleted uploaded {}", path.display()),
+                        Err(e) => error!("Unable to delete {}: {e}", path.display()),
+                    }
+                } else if let Some(storage) = &self.storage {
+                    storage.uploaded(&path);
+                }
             }
         }
         Ok(())
@@ -139,9 +159,24 @@ pub struct UploadActorHandle {
 }
 
 impl UploadActorHandle {
-    pub fn new(client: StatelessClient, studio: Studio) -> Self {
+    /// Uploaded files are deleted with `delete_after_upload`, or handed to `storage`, if
+    /// any, for its retention policy. The account requests go through `proxy`.
+    pub fn new(
+        client: StatelessClient,
+        studio: Studio,
+        proxy: Option<String>,
+        storage: Option<StorageMonitor>,
+        delete_after_upload: bool,
+    ) -> Self {
         let (sender, receiver) = mpsc::unbounded_channel();
-        let actor = UploadActor::new(studio, client, receiver);
+        let actor = UploadActor::new(
+            studio,
+            client,
+            receiver,
+            proxy,
+            storage,
+            delete_after_upload,
+        );
         tokio::spawn(run_download_actor(actor));
 
         Self { sender }
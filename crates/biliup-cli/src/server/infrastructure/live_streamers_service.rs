@@ -3,39 +3,100 @@ use crate::server::core::live_streamers::{
     LiveStreamersService,
 };
 use crate::server::core::upload_streamers::DynUploadStreamersRepository;
+use crate::server::core::users::DynUsersRepository;
+use anyhow::Context;
 use async_trait::async_trait;
+use biliup::client::proxy::ProxyRoutes;
+use biliup::downloader::postprocess::Step;
 use biliup::uploader::bilibili::Studio;
+use biliup::uploader::credential::LoginInfo;
+use tracing::warn;
 
 #[derive(Clone)]
 pub struct ConduitLiveStreamersService {
     repository: DynLiveStreamersRepository,
     upload_streamers_repository: DynUploadStreamersRepository,
+    users_repository: DynUsersRepository,
+    /// Whether streamers may have `exec` and `move` post-processing steps.
+    allow_host_steps: bool,
 }
 
 impl ConduitLiveStreamersService {
     pub fn new(
         repository: DynLiveStreamersRepository,
         upload_streamers_repository: DynUploadStreamersRepository,
+        users_repository: DynUsersRepository,
+        allow_host_steps: bool,
     ) -> Self {
         Self {
             repository,
             upload_streamers_repository,
+            users_repository,
+            allow_host_steps,
         }
     }
+
+    /// Refuses settings the API mustn't take: invalid proxy rules, and steps running
+    /// commands or moving files on the host unless the server allows them.
+    fn check(&self, postprocess: &[Step], proxy: Option<&str>) -> anyhow::Result<()> {
+        if let Some(proxy) = proxy {
+            proxy.parse::<ProxyRoutes>().map_err(anyhow::Error::msg)?;
+        }
+        if let Some(step) = postprocess.iter().find(|step| step.touches_host())
+            && !self.allow_host_steps
+        {
+            anyhow::bail!("post-processing step `{step}` requires --allow-host-steps");
+        }
+        Ok(())
+    }
+
+    /// Fills in the credential of the account picked for the streamer, downloads continue
+    /// anonymously when its cookie file can't be read.
+    async fn with_credential(&self, mut dto: LiveStreamerDto) -> LiveStreamerDto {
+        let Some(user_id) = dto.user_id else {
+            return dto;
+        };
+        match self.login_info(user_id).await {
+            Ok(login_info) => dto.stream_options.credential = Some((&login_info).into()),
+            Err(e) => warn!("Unable to load user {user_id} for {}: {e:#}", dto.url),
+        }
+        dto
+    }
+
+    async fn login_info(&self, user_id: i64) -> anyhow::Result<LoginInfo> {
+        let user = self.users_repository.get_user_by_id(user_id).await?;
+        let file = std::fs::File::open(&user.value)
+            .with_context(|| format!("unable to open {}", user.value))?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
 }
 
 #[async_trait]
 impl LiveStreamersService for ConduitLiveStreamersService {
     async fn add_streamer(&self, request: AddLiveStreamerDto) -> anyhow::Result<LiveStreamerDto> {
+        self.check(&request.postprocess, request.proxy.as_deref())?;
         Ok(self.repository.create_streamer(request).await?.into_dto())
     }
 
+    async fn update_streamer(&self, entity: LiveStreamerEntity) -> anyhow::Result<LiveStreamerDto> {
+        let postprocess = entity
+            .postprocess
+            .lines()
+            .map(str::parse)
+            .collect::<Result<Vec<Step>, _>>()
+            .map_err(anyhow::Error::msg)?;
+        self.check(&postprocess, entity.proxy.as_deref())?;
+        Ok(self.repository.update_streamer(entity).await?.into_dto())
+    }
+
     async fn get_streamer_by_url(&self, url: &str) -> anyhow::Result<LiveStreamerDto> {
-        Ok(self.repository.get_streamer_by_url(url).await?.into_dto())
+        let dto = self.repository.get_streamer_by_url(url).await?.into_dto();
+        Ok(self.with_credential(dto).await)
     }
 
     async fn get_streamer_by_id(&self, id: i64) -> anyhow::Result<LiveStreamerDto> {
-        Ok(self.repository.get_streamer_by_id(id).await?.into_dto())
+        let dto = self.repository.get_streamer_by_id(id).await?.into_dto();
+        Ok(self.with_credential(dto).await)
     }
 
     async fn get_streamers(&self) -> anyhow::Result<Vec<LiveStreamerDto>> {
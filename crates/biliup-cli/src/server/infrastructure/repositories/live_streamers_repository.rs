@@ -22,19 +22,54 @@ impl LiveStreamersRepository for SqliteLiveStreamersRepository {
     async fn create_streamer(&self, dto: AddLiveStreamerDto) -> anyhow::Result<LiveStreamerEntity> {
         let split_time = dto.split_time.map(|t| t as i64);
         let split_size = dto.split_size.map(|s| s as i64);
+        let options = &dto.stream_options;
+        let quality = options.quality.map(i64::from);
+        let codec = options.codec.map(|c| c.as_str());
+        let protocol = options.protocol.map(|p| p.as_str());
+        let cdn_prefer = options.cdn_prefer.join(",");
+        let cdn_blacklist = options.cdn_blacklist.join(",");
+        let headers = options.headers.join("\n");
+        let postprocess = dto
+            .postprocess
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n");
+        let min_segment_size = dto.min_segment_size.map(|s| s as i64);
+        let min_segment_duration = dto.min_segment_duration.map(|t| t as i64);
+        let undersized = dto.undersized.as_str();
+        let split_on_change = dto.split_on_change.map(|t| t as i64);
+        let split_clock = dto.split_clock.map(|t| t as i64);
+        let split_clock_offset = dto.split_clock_offset.map(|t| t as i64);
         query_as!(
             LiveStreamerEntity,
             r#"
-        insert into live_streamers (url, remark, filename, split_time, split_size, upload_id)
-        values ($1 , $2 , $3, $4 , $5, $6)
-        returning id, url as "url!", remark as "remark!", filename as "filename!", split_time, split_size, upload_id
+        insert into live_streamers (url, remark, filename, split_time, split_size, upload_id, quality, codec, protocol, cdn_prefer, cdn_blacklist, user_id, headers, postprocess, min_segment_size, min_segment_duration, undersized, proxy, split_on_change, split_clock, split_clock_offset, split_all)
+        values ($1 , $2 , $3, $4 , $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22)
+        returning id, url as "url!", remark as "remark!", filename as "filename!", split_time, split_size, upload_id, quality, codec, protocol, cdn_prefer as "cdn_prefer!", cdn_blacklist as "cdn_blacklist!", user_id, headers as "headers!", postprocess as "postprocess!", min_segment_size, min_segment_duration, undersized as "undersized!", proxy, split_on_change, split_clock, split_clock_offset, split_all as "split_all!"
             "#,
             dto.url,
             dto.remark,
             dto.filename,
             split_time,
             split_size,
-            dto.upload_id
+            dto.upload_id,
+            quality,
+            codec,
+            protocol,
+            cdn_prefer,
+            cdn_blacklist,
+            dto.user_id,
+            headers,
+            postprocess,
+            min_segment_size,
+            min_segment_duration,
+            undersized,
+            dto.proxy,
+            split_on_change,
+            split_clock,
+            split_clock_offset,
+            dto.split_all
         )
         .fetch_one(&self.pool)
         .await
@@ -56,7 +91,7 @@ impl LiveStreamersRepository for SqliteLiveStreamersRepository {
         query_as!(
             LiveStreamerEntity,
             r#"
-       select id, url as "url!", remark as "remark!", filename as "filename!", split_time, split_size, upload_id from live_streamers
+       select id, url as "url!", remark as "remark!", filename as "filename!", split_time, split_size, upload_id, quality, codec, protocol, cdn_prefer as "cdn_prefer!", cdn_blacklist as "cdn_blacklist!", user_id, headers as "headers!", postprocess as "postprocess!", min_segment_size, min_segment_duration, undersized as "undersized!", proxy, split_on_change, split_clock, split_clock_offset, split_all as "split_all!" from live_streamers
             "#
         )
         .fetch_all(&self.pool)
@@ -69,7 +104,7 @@ impl LiveStreamersRepository for SqliteLiveStreamersRepository {
             LiveStreamerEntity,
             r#"
         select
-            id, url as "url!", remark as "remark!", filename as "filename!", split_time, split_size, upload_id
+            id, url as "url!", remark as "remark!", filename as "filename!", split_time, split_size, upload_id, quality, codec, protocol, cdn_prefer as "cdn_prefer!", cdn_blacklist as "cdn_blacklist!", user_id, headers as "headers!", postprocess as "postprocess!", min_segment_size, min_segment_duration, undersized as "undersized!", proxy, split_on_change, split_clock, split_clock_offset, split_all as "split_all!"
         from
             live_streamers
         where
@@ -87,7 +122,7 @@ impl LiveStreamersRepository for SqliteLiveStreamersRepository {
             LiveStreamerEntity,
             r#"
         select
-            id, url as "url!", remark as "remark!", filename as "filename!", split_time, split_size, upload_id
+            id, url as "url!", remark as "remark!", filename as "filename!", split_time, split_size, upload_id, quality, codec, protocol, cdn_prefer as "cdn_prefer!", cdn_blacklist as "cdn_blacklist!", user_id, headers as "headers!", postprocess as "postprocess!", min_segment_size, min_segment_duration, undersized as "undersized!", proxy, split_on_change, split_clock, split_clock_offset, split_all as "split_all!"
         from
             live_streamers
         where
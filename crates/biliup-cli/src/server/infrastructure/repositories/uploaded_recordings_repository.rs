@@ -0,0 +1,59 @@
+use crate::server::core::storage::UploadedRecordingsRepository;
+use crate::server::infrastructure::connection_pool::ConnectionPool;
+use anyhow::Context;
+use async_trait::async_trait;
+use sqlx::{query, query_scalar};
+
+#[derive(Clone)]
+pub struct SqliteUploadedRecordingsRepository {
+    pool: ConnectionPool,
+}
+
+impl SqliteUploadedRecordingsRepository {
+    pub fn new(pool: ConnectionPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl UploadedRecordingsRepository for SqliteUploadedRecordingsRepository {
+    async fn add(&self, path: &str) -> anyhow::Result<()> {
+        query!(
+            r#"
+        insert or ignore into uploaded_recordings (path)
+        values ($1)
+            "#,
+            path
+        )
+        .execute(&self.pool)
+        .await
+        .context("an unexpected error occurred while adding the uploaded recording")?;
+        Ok(())
+    }
+
+    async fn remove(&self, path: &str) -> anyhow::Result<()> {
+        query!(
+            r#"
+        delete from uploaded_recordings
+        where path = $1
+            "#,
+            path
+        )
+        .execute(&self.pool)
+        .await
+        .context("an unexpected error occurred while removing the uploaded recording")?;
+        Ok(())
+    }
+
+    async fn get_all(&self) -> anyhow::Result<Vec<String>> {
+        query_scalar!(
+            r#"
+        select path
+        from uploaded_recordings
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("unexpected error while querying for uploaded recordings")
+    }
+}
@@ -65,7 +65,18 @@ impl UsersRepository for SqliteUsersStreamersRepository {
         todo!()
     }
 
-    async fn get_user_by_id(&self, _id: i64) -> anyhow::Result<User> {
-        todo!()
+    async fn get_user_by_id(&self, id: i64) -> anyhow::Result<User> {
+        query_as!(
+            User,
+            r#"
+        select id, name as "name!", value as "value!", platform as "platform!"
+        from users
+        where id = $1
+            "#,
+            id
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("an unexpected error occurred while querying for the user")
     }
 }
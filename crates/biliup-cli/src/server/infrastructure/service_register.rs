@@ -2,6 +2,7 @@ use crate::server::core::live_streamers::{
     DynDownloadRecordsRepository, DynLiveStreamersRepository, DynLiveStreamersService,
     DynVideosRepository,
 };
+use crate::server::core::storage::DynUploadedRecordingsRepository;
 use crate::server::core::upload_streamers::{
     DynUploadRecordsRepository, DynUploadStreamersRepository,
 };
@@ -12,6 +13,7 @@ use crate::server::infrastructure::repositories::download_records_repository::Sq
 use crate::server::infrastructure::repositories::live_streamers_repository::SqliteLiveStreamersRepository;
 use crate::server::infrastructure::repositories::upload_records_repository::SqliteUploadRecordsRepository;
 use crate::server::infrastructure::repositories::upload_streamers_repository::SqliteUploadStreamersRepository;
+use crate::server::infrastructure::repositories::uploaded_recordings_repository::SqliteUploadedRecordingsRepository;
 use crate::server::infrastructure::repositories::users_repository::SqliteUsersStreamersRepository;
 use crate::server::infrastructure::repositories::videos_repository::SqliteVideosRepository;
 use axum::extract::FromRef;
@@ -27,11 +29,13 @@ pub struct ServiceRegister {
     pub upload_records_repository: DynUploadRecordsRepository,
     pub videos_repository: DynVideosRepository,
     pub download_records_repository: DynDownloadRecordsRepository,
+    pub uploaded_recordings_repository: DynUploadedRecordingsRepository,
 }
 
 /// A simple service container responsible for managing the various services our API endpoints will pull from through axum extensions.
 impl ServiceRegister {
-    pub fn new(pool: ConnectionPool) -> Self {
+    /// `allow_host_steps` lets the API set `exec` and `move` post-processing steps.
+    pub fn new(pool: ConnectionPool, allow_host_steps: bool) -> Self {
         info!("initializing utility services...");
 
         info!("utility services initialized, building feature services...");
@@ -51,11 +55,17 @@ impl ServiceRegister {
             Arc::new(SqliteVideosRepository::new(pool.clone())) as DynVideosRepository;
 
         let download_records_repository =
-            Arc::new(SqliteDownloadRecordsRepository::new(pool)) as DynDownloadRecordsRepository;
+            Arc::new(SqliteDownloadRecordsRepository::new(pool.clone()))
+                as DynDownloadRecordsRepository;
+
+        let uploaded_recordings_repository = Arc::new(SqliteUploadedRecordingsRepository::new(pool))
+            as DynUploadedRecordingsRepository;
 
         let streamers_service = Arc::new(ConduitLiveStreamersService::new(
             streamers_repository.clone(),
             upload_streamers_repository.clone(),
+            users_repository.clone(),
+            allow_host_steps,
         )) as DynLiveStreamersService;
         info!("feature services successfully initialized!");
 
@@ -67,6 +77,7 @@ impl ServiceRegister {
             upload_records_repository,
             videos_repository,
             download_records_repository,
+            uploaded_recordings_repository,
         }
     }
 }
@@ -26,7 +26,7 @@ use std::time::Instant;
 use tracing::{info, warn};
 
 pub async fn login(user_cookie: PathBuf, proxy: Option<&str>) -> Result<()> {
-    let client = Credential::new(proxy);
+    let client = Credential::new(proxy)?;
     let selection = Select::with_theme(&ColorfulTheme::default())
         .with_prompt("选择一种登录方式")
         .default(1)
@@ -53,7 +53,7 @@ pub async fn login(user_cookie: PathBuf, proxy: Option<&str>) -> Result<()> {
 }
 
 pub async fn renew(user_cookie: PathBuf, proxy: Option<&str>) -> Result<()> {
-    let client = Credential::new(proxy);
+    let client = Credential::new(proxy)?;
     let mut file = fopen_rw(user_cookie)?;
     let login_info: LoginInfo = serde_json::from_reader(&file)?;
     let new_info = client.renew_tokens(login_info).await?;
@@ -64,6 +64,7 @@ pub async fn renew(user_cookie: PathBuf, proxy: Option<&str>) -> Result<()> {
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn upload_by_command(
     mut studio: Studio,
     user_cookie: PathBuf,
@@ -83,7 +84,7 @@ pub async fn upload_by_command(
             .unwrap();
     }
     cover_up(&mut studio, &bili).await?;
-    studio.videos = upload(&video_path, &bili, line, limit, retry).await?;
+    studio.videos = upload(&video_path, &bili, line, limit, retry, proxy).await?;
 
     // if studio.submit_by_app {
     //     bili.submit_by_app(&studio).await?;
@@ -128,6 +129,7 @@ pub async fn upload_by_config(
                 .and_then(|l| UploadLine::from_str(l, true).ok()),
             config.limit,
             3, // default retry for config-based uploads
+            proxy,
         )
         .await?;
         bilibili.submit_by_app(&studio, proxy).await?;
@@ -145,7 +147,7 @@ pub async fn append(
     proxy: Option<&str>,
 ) -> Result<()> {
     let bilibili = login_by_cookies(user_cookie, proxy).await?;
-    let mut uploaded_videos = upload(&video_path, &bilibili, line, limit, retry).await?;
+    let mut uploaded_videos = upload(&video_path, &bilibili, line, limit, retry, proxy).await?;
     let mut studio = bilibili.studio_data(&vid, proxy).await?;
     studio.videos.append(&mut uploaded_videos);
     bilibili.edit_by_web(&studio).await?;
@@ -228,10 +230,11 @@ pub async fn upload(
     line: Option<UploadLine>,
     limit: usize,
     retry: u32,
+    proxy: Option<&str>,
 ) -> Result<Vec<Video>> {
     info!("number of concurrent futures: {limit}");
     let mut videos = Vec::new();
-    let client = StatelessClient::default();
+    let client = StatelessClient::for_upload(proxy)?;
     let line = match line {
         Some(UploadLine::Bda2) => line::bda2(),
         Some(UploadLine::Ws) => line::ws(),
@@ -0,0 +1,435 @@
+//! Records the HTTP interactions of the clients to a fixture file and serves them back
+//! later, so extractors, uploads and logins can be exercised without the live services.
+//!
+//! Only textual responses (JSON, HTML, playlists, ...) are recorded, media streams are
+//! passed through untouched. Tokens and cookies are redacted before anything is written,
+//! one interaction per line.
+
+use http::Extensions;
+use reqwest::header::{CONTENT_TYPE, HeaderName, HeaderValue};
+use reqwest::{Request, Response, ResponseBuilderExt};
+use reqwest_middleware::{Middleware, Next};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+use url::Url;
+
+static CASSETTE: LazyLock<RwLock<Option<Arc<Cassette>>>> = LazyLock::new(|| RwLock::new(None));
+
+/// Makes every client created afterwards record to or replay from `cassette`.
+pub fn set_cassette(cassette: Option<Cassette>) {
+    *CASSETTE.write().unwrap() = cassette.map(Arc::new);
+}
+
+pub(crate) fn cassette() -> Option<Arc<Cassette>> {
+    CASSETTE.read().unwrap().clone()
+}
+
+/// Query parameters, JSON fields and cookies whose values are never written.
+const SECRETS: &[&str] = &[
+    "access_key",
+    "access_token",
+    "refresh_token",
+    "token",
+    "uptoken",
+    "csrf",
+    "csrf_token",
+    "bili_jct",
+    "sessdata",
+    "dedeuserid__ckmd5",
+    "cookie",
+    "password",
+];
+
+const REDACTED: &str = "REDACTED";
+
+fn is_secret(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SECRETS.contains(&name.as_str()) || name.ends_with("_token")
+}
+
+/// A request/response pair as stored in the fixture file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub method: String,
+    pub url: String,
+    /// Body of the request, empty for streamed ones.
+    #[serde(default)]
+    pub request_body: String,
+    pub status: u16,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    #[serde(default)]
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Record,
+    Replay,
+}
+
+#[derive(Debug)]
+pub struct Cassette {
+    path: PathBuf,
+    mode: Mode,
+    /// The interactions to replay, and whether each was served already.
+    interactions: Mutex<Vec<(Interaction, bool)>>,
+    /// The fixture file recorded interactions are appended to.
+    file: Option<Mutex<File>>,
+}
+
+impl Cassette {
+    /// Writes the interactions to `path`, replacing the file, one line per response.
+    pub fn record(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        let file = File::create(&path)?;
+        Ok(Self {
+            path,
+            mode: Mode::Record,
+            interactions: Mutex::new(Vec::new()),
+            file: Some(Mutex::new(file)),
+        })
+    }
+
+    /// Serves the interactions in `path`, a JSON array or one interaction per line.
+    /// Requests without a recorded response of the same method, url and body fail.
+    pub fn replay(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let bytes = std::fs::read(path.as_ref())?;
+        let interactions: Vec<Interaction> = if bytes.trim_ascii_start().starts_with(b"[") {
+            serde_json::from_slice(&bytes)?
+        } else {
+            serde_json::Deserializer::from_slice(&bytes)
+                .into_iter()
+                .collect::<Result<_, _>>()?
+        };
+        Ok(Self::from_interactions(path.as_ref(), interactions))
+    }
+
+    pub fn from_interactions(path: impl Into<PathBuf>, interactions: Vec<Interaction>) -> Self {
+        Self {
+            path: path.into(),
+            mode: Mode::Replay,
+            interactions: Mutex::new(interactions.into_iter().map(|i| (i, false)).collect()),
+            file: None,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Takes the first unused interaction with the same method, url and body, compared
+    /// as they were written.
+    fn take(&self, method: &str, url: &Url, body: &str) -> Option<Interaction> {
+        let url = redact_url(url);
+        let body = redact_body(body);
+        let mut interactions = self.interactions.lock().unwrap();
+        let position = interactions.iter().position(|(i, used)| {
+            !used && i.method == method && i.url == url && i.request_body == body
+        })?;
+        interactions[position].1 = true;
+        Some(interactions[position].0.clone())
+    }
+
+    fn save(&self, interaction: Interaction) -> std::io::Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let mut line = serde_json::to_vec(&interaction.redacted())?;
+        line.push(b'\n');
+        file.lock().unwrap().write_all(&line)
+    }
+
+    async fn record_response(
+        &self,
+        method: String,
+        request_body: String,
+        response: Response,
+    ) -> reqwest_middleware::Result<Response> {
+        let textual = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|content_type| {
+                ["json", "text", "xml", "javascript", "mpegurl"]
+                    .iter()
+                    .any(|t| content_type.contains(t))
+            });
+        if !textual {
+            return Ok(response);
+        }
+        let url = response.url().clone();
+        let status = response.status();
+        let version = response.version();
+        let headers = response.headers().clone();
+        let body = response.bytes().await?;
+
+        self.save(Interaction {
+            method,
+            url: url.to_string(),
+            request_body,
+            status: status.as_u16(),
+            headers: headers
+                .iter()
+                .filter_map(|(k, v)| Some((k.to_string(), v.to_str().ok()?.to_string())))
+                .collect(),
+            body: String::from_utf8_lossy(&body).into_owned(),
+        })
+        .map_err(|e| reqwest_middleware::Error::Middleware(e.into()))?;
+
+        let mut builder = http::Response::builder()
+            .status(status)
+            .version(version)
+            .url(url);
+        if let Some(h) = builder.headers_mut() {
+            *h = headers;
+        }
+        let response = builder
+            .body(body)
+            .map_err(|e| reqwest_middleware::Error::Middleware(e.into()))?;
+        Ok(Response::from(response))
+    }
+}
+
+impl Interaction {
+    /// The interaction with the values of tokens and cookies replaced.
+    fn redacted(mut self) -> Self {
+        if let Ok(url) = Url::parse(&self.url) {
+            self.url = redact_url(&url);
+        }
+        self.request_body = redact_body(&self.request_body);
+        self.body = redact_body(&self.body);
+        for (name, value) in &mut self.headers {
+            if name.eq_ignore_ascii_case("set-cookie") {
+                *value = redact_cookie(value);
+            }
+        }
+        self
+    }
+}
+
+fn redact_url(url: &Url) -> String {
+    let mut url = url.clone();
+    if let Some(query) = url.query().and_then(redact_query) {
+        url.set_query(Some(&query));
+    }
+    url.to_string()
+}
+
+/// The query or form with the secret values replaced, `None` if there are none.
+fn redact_query(query: &str) -> Option<String> {
+    let pairs: Vec<(String, String)> = url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+    if !pairs.iter().any(|(name, _)| is_secret(name)) {
+        return None;
+    }
+    let mut redacted = url::form_urlencoded::Serializer::new(String::new());
+    for (name, value) in &pairs {
+        let value = if is_secret(name) { REDACTED } else { value };
+        redacted.append_pair(name, value);
+    }
+    Some(redacted.finish())
+}
+
+/// Redacts JSON fields and form values, other bodies are kept as they are.
+fn redact_body(body: &str) -> String {
+    if let Ok(mut json) = serde_json::from_str::<Value>(body) {
+        if redact_json(&mut json) {
+            return json.to_string();
+        }
+        return body.to_string();
+    }
+    if !body.contains(char::is_whitespace)
+        && let Some(form) = redact_query(body)
+    {
+        return form;
+    }
+    body.to_string()
+}
+
+/// Whether anything was redacted. Cookies listed as `{"name": ..., "value": ...}` lose
+/// their values too.
+fn redact_json(value: &mut Value) -> bool {
+    match value {
+        Value::Object(object) => {
+            let mut redacted = false;
+            let cookie = object.contains_key("name") && object.contains_key("value");
+            for (key, value) in object.iter_mut() {
+                if (is_secret(key) || (cookie && key == "value"))
+                    && (value.is_string() || value.is_number())
+                {
+                    *value = Value::from(REDACTED);
+                    redacted = true;
+                } else {
+                    redacted |= redact_json(value);
+                }
+            }
+            redacted
+        }
+        Value::Array(values) => values
+            .iter_mut()
+            .fold(false, |redacted, value| redact_json(value) | redacted),
+        _ => false,
+    }
+}
+
+/// `name=REDACTED` followed by the attributes of the cookie.
+fn redact_cookie(cookie: &str) -> String {
+    let (pair, attributes) = cookie.split_once(';').unwrap_or((cookie, ""));
+    let name = pair.split_once('=').map_or(pair, |(name, _)| name);
+    if attributes.is_empty() {
+        format!("{name}={REDACTED}")
+    } else {
+        format!("{name}={REDACTED};{attributes}")
+    }
+}
+
+impl From<Interaction> for Response {
+    fn from(interaction: Interaction) -> Self {
+        let mut builder = http::Response::builder().status(interaction.status);
+        if let Ok(url) = Url::parse(&interaction.url) {
+            builder = builder.url(url);
+        }
+        for (name, value) in &interaction.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                builder = builder.header(name, value);
+            }
+        }
+        let response = builder
+            .body(interaction.body)
+            .expect("recorded status and headers are valid");
+        Response::from(response)
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for Cassette {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        let method = req.method().to_string();
+        let body = req
+            .body()
+            .and_then(reqwest::Body::as_bytes)
+            .map(|body| String::from_utf8_lossy(body).into_owned())
+            .unwrap_or_default();
+        match self.mode {
+            Mode::Record => {
+                let response = next.run(req, extensions).await?;
+                self.record_response(method, body, response).await
+            }
+            Mode::Replay => self
+                .take(&method, req.url(), &body)
+                .map(Response::from)
+                .ok_or_else(|| {
+                    reqwest_middleware::Error::Middleware(anyhow::anyhow!(
+                        "No recorded response for {method} {} in {}",
+                        redact_url(req.url()),
+                        self.path.display()
+                    ))
+                }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest_middleware::ClientBuilder;
+
+    #[tokio::test]
+    async fn replay_interactions() -> anyhow::Result<()> {
+        let interaction = |url: &str, body: &str| Interaction {
+            method: "GET".to_string(),
+            url: url.to_string(),
+            request_body: String::new(),
+            status: 200,
+            headers: vec![("content-type".to_string(), "application/json".to_string())],
+            body: body.to_string(),
+        };
+        let cassette = Cassette::from_interactions(
+            "fixture.json",
+            vec![
+                interaction("https://example.com/api?ts=1", r#"{"n":1}"#),
+                interaction("https://example.com/api?ts=2", r#"{"n":2}"#),
+            ],
+        );
+        let client = ClientBuilder::new(reqwest::Client::new())
+            .with(cassette)
+            .build();
+
+        let exact: serde_json::Value = client
+            .get("https://example.com/api?ts=2")
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(exact["n"], 2);
+        assert!(
+            client
+                .get("https://example.com/api?ts=3")
+                .send()
+                .await
+                .is_err()
+        );
+        assert!(client.get("https://example.com/api").send().await.is_err());
+        assert!(client.post("https://example.com/api").send().await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn record_without_secrets() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("cassette-{}.jsonl", std::process::id()));
+        let cassette = Cassette::record(&path)?;
+        let interaction = |n: u32| {
+            Interaction {
+            method: "POST".to_string(),
+            url: format!("https://passport.bilibili.com/login?access_key=secret&n={n}"),
+            request_body: "appkey=1&refresh_token=secret".to_string(),
+            status: 200,
+            headers: vec![(
+                "Set-Cookie".to_string(),
+                "SESSDATA=secret; Path=/".to_string(),
+            )],
+            body: r#"{"data":{"token_info":{"access_token":"secret"},"cookies":[{"name":"bili_jct","value":"secret"}]}}"#
+                .to_string(),
+        }
+        };
+        cassette.save(interaction(1))?;
+        cassette.save(interaction(2))?;
+        let written = std::fs::read_to_string(&path);
+        let replayed = Cassette::replay(&path);
+        std::fs::remove_file(&path)?;
+
+        let written = written?;
+        assert_eq!(written.lines().count(), 2);
+        assert!(!written.contains("secret"));
+        let client = ClientBuilder::new(reqwest::Client::new())
+            .with(replayed?)
+            .build();
+        let response = client
+            .post("https://passport.bilibili.com/login?access_key=other&n=2")
+            .body("appkey=1&refresh_token=other")
+            .send()
+            .await?;
+        assert_eq!(
+            response.headers()["set-cookie"],
+            "SESSDATA=REDACTED; Path=/"
+        );
+        let body: serde_json::Value = response.json().await?;
+        assert_eq!(body["data"]["token_info"]["access_token"], REDACTED);
+        assert_eq!(body["data"]["cookies"][0]["value"], REDACTED);
+        Ok(())
+    }
+}
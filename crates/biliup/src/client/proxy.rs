@@ -0,0 +1,351 @@
+//! Routing of requests through different proxies, by what a client is used for and which
+//! host a request goes to.
+
+use crate::error::{Kind, Result};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use url::Url;
+
+/// What a client is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Purpose {
+    /// Room pages and APIs of the live platforms.
+    Extract,
+    /// Streams, playlists and their segments.
+    Download,
+    /// The bilibili account, uploads and submissions.
+    Upload,
+}
+
+impl Purpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Purpose::Extract => "extract",
+            Purpose::Download => "download",
+            Purpose::Upload => "upload",
+        }
+    }
+}
+
+impl FromStr for Purpose {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "extract" => Ok(Purpose::Extract),
+            "download" => Ok(Purpose::Download),
+            "upload" => Ok(Purpose::Upload),
+            _ => Err(format!("unknown purpose: {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    Direct,
+    /// A `http`, `https`, `socks5` or `socks5h` proxy.
+    Proxy(Url),
+}
+
+impl FromStr for Route {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s == "direct" {
+            return Ok(Route::Direct);
+        }
+        let url = Url::parse(s).map_err(|e| format!("invalid proxy {s}: {e}"))?;
+        match url.scheme() {
+            "http" | "https" | "socks5" | "socks5h" if url.has_host() => Ok(Route::Proxy(url)),
+            _ => Err(format!(
+                "invalid proxy {s}, expected direct or a http, https, socks5 or socks5h url"
+            )),
+        }
+    }
+}
+
+impl Display for Route {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Route::Direct => write!(f, "direct"),
+            Route::Proxy(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+/// Where requests go, written as comma separated rules: a proxy url or `direct` for every
+/// request, `extract=`, `download=` or `upload=` followed by one for the clients of that
+/// [`Purpose`], or a host such as `*.douyu.com=` followed by one for the requests to it.
+/// Host rules come before purpose rules, which come before the rule for every request.
+///
+/// A single proxy url, as accepted before, stays a rule for every request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProxyRoutes {
+    /// `None` leaves it to the system proxy settings, with host rules only those of the
+    /// environment variables.
+    default: Option<Route>,
+    purposes: Vec<(Purpose, Route)>,
+    hosts: Vec<(String, Route)>,
+}
+
+impl FromStr for ProxyRoutes {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut routes = ProxyRoutes::default();
+        for rule in s.split(',').map(str::trim).filter(|rule| !rule.is_empty()) {
+            let (key, route) = match rule.split_once('=') {
+                // An `=` in the query of a proxy url doesn't make a rule.
+                Some((key, route)) if !key.contains("://") => (Some(key.trim()), route.trim()),
+                _ => (None, rule),
+            };
+            let route = route.parse()?;
+            match key {
+                None => routes.default = Some(route),
+                Some(key) => match key.parse::<Purpose>() {
+                    Ok(purpose) => routes.purposes.push((purpose, route)),
+                    Err(_) if !key.is_empty() && !key.contains(['/', ':', ' ']) => {
+                        routes.hosts.push((key.to_ascii_lowercase(), route))
+                    }
+                    Err(_) => return Err(format!("invalid proxy rule `{rule}`")),
+                },
+            }
+        }
+        Ok(routes)
+    }
+}
+
+impl ProxyRoutes {
+    pub fn parse(s: &str) -> Result<Self> {
+        s.parse().map_err(Kind::Custom)
+    }
+
+    /// Whether clients of the two purposes may route differently.
+    pub(crate) fn differs(&self, a: Purpose, b: Purpose) -> bool {
+        self.purpose(a) != self.purpose(b)
+    }
+
+    fn purpose(&self, purpose: Purpose) -> Option<&Route> {
+        self.purposes
+            .iter()
+            .rev()
+            .find(|(p, _)| *p == purpose)
+            .map(|(_, route)| route)
+    }
+
+    /// The route of a request of a `purpose` client to `host`. `None` leaves it to the
+    /// system proxy settings, which are read from the `HTTP_PROXY`, `HTTPS_PROXY`,
+    /// `ALL_PROXY` and `NO_PROXY` environment variables once there are host rules.
+    pub fn route(&self, purpose: Purpose, host: &str) -> Option<&Route> {
+        let host = host.to_ascii_lowercase();
+        self.hosts
+            .iter()
+            .rev()
+            .find(|(pattern, _)| matches_host(pattern, &host))
+            .map(|(_, route)| route)
+            .or_else(|| self.purpose(purpose))
+            .or(self.default.as_ref())
+    }
+
+    /// Applies the routes of a `purpose` client to `builder`.
+    pub(crate) fn apply(
+        &self,
+        purpose: Purpose,
+        builder: reqwest::ClientBuilder,
+    ) -> Result<reqwest::ClientBuilder> {
+        if self.hosts.is_empty() {
+            return Ok(match self.route(purpose, "") {
+                None => builder,
+                Some(Route::Direct) => builder.no_proxy(),
+                Some(Route::Proxy(url)) => {
+                    tracing::debug!("使用代理: {url}");
+                    builder.proxy(reqwest::Proxy::all(url.clone())?)
+                }
+            });
+        }
+        // A custom proxy replaces the system proxy, which is kept for the requests no
+        // rule covers.
+        let routes = self.clone();
+        let system = SystemProxy::from_env();
+        Ok(builder.proxy(reqwest::Proxy::custom(move |url| {
+            routes.proxy_for(purpose, url, &system)
+        })))
+    }
+
+    /// The proxy of a request of a `purpose` client to `url`, the one of `system` when no
+    /// rule covers it.
+    fn proxy_for(&self, purpose: Purpose, url: &Url, system: &SystemProxy) -> Option<Url> {
+        match self.route(purpose, url.host_str().unwrap_or_default()) {
+            Some(Route::Proxy(proxy)) => Some(proxy.clone()),
+            Some(Route::Direct) => None,
+            None => system.proxy_for(url),
+        }
+    }
+}
+
+/// The proxies set by the `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY` and `NO_PROXY`
+/// environment variables, as reqwest reads them for clients without proxies of their own.
+#[derive(Debug, Clone, Default)]
+struct SystemProxy {
+    http: Option<Url>,
+    https: Option<Url>,
+    /// Hosts reached directly, `*` for all of them.
+    no_proxy: Vec<String>,
+}
+
+impl SystemProxy {
+    fn from_env() -> Self {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let value = |name: &str| {
+            var(name)
+                .or_else(|| var(&name.to_ascii_lowercase()))
+                .filter(|value| !value.trim().is_empty())
+        };
+        let proxy = |name: &str| {
+            let value = value(name)?;
+            let value = value.trim();
+            Url::parse(value)
+                .ok()
+                .filter(Url::has_host)
+                .or_else(|| Url::parse(&format!("http://{value}")).ok())
+        };
+        let all = proxy("ALL_PROXY");
+        Self {
+            http: proxy("HTTP_PROXY").or_else(|| all.clone()),
+            https: proxy("HTTPS_PROXY").or(all),
+            no_proxy: value("NO_PROXY")
+                .map(|hosts| {
+                    hosts
+                        .split(',')
+                        .map(|host| host.trim().trim_start_matches('.').to_ascii_lowercase())
+                        .filter(|host| !host.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    fn proxy_for(&self, url: &Url) -> Option<Url> {
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        if self.no_proxy.iter().any(|pattern| {
+            pattern == "*"
+                || host == *pattern
+                || host
+                    .strip_suffix(pattern.as_str())
+                    .is_some_and(|sub| sub.ends_with('.'))
+        }) {
+            return None;
+        }
+        match url.scheme() {
+            "https" => self.https.clone(),
+            _ => self.http.clone(),
+        }
+    }
+}
+
+/// `*.example.com` matches `example.com` and its subdomains, other patterns only the host
+/// itself.
+fn matches_host(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => {
+            host == domain
+                || host
+                    .strip_suffix(domain)
+                    .is_some_and(|sub| sub.ends_with('.'))
+        }
+        None => host == pattern,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_route() {
+        let routes: ProxyRoutes =
+            "socks5h://127.0.0.1:1080, download=direct, upload=http://up:8080, *.douyu.com=http://dy:1"
+                .parse()
+                .unwrap();
+        let route = |purpose, host| routes.route(purpose, host).map(Route::to_string);
+        assert_eq!(
+            route(Purpose::Extract, "live.bilibili.com").as_deref(),
+            Some("socks5h://127.0.0.1:1080")
+        );
+        assert_eq!(
+            route(Purpose::Download, "cn-gotcha.com").as_deref(),
+            Some("direct")
+        );
+        assert_eq!(
+            route(Purpose::Upload, "upos.com").as_deref(),
+            Some("http://up:8080/")
+        );
+        assert_eq!(
+            route(Purpose::Download, "www.DOUYU.com").as_deref(),
+            Some("http://dy:1/")
+        );
+        assert_eq!(
+            route(Purpose::Extract, "douyu.com").as_deref(),
+            Some("http://dy:1/")
+        );
+        assert_eq!(
+            route(Purpose::Extract, "notdouyu.com").as_deref(),
+            Some("socks5h://127.0.0.1:1080")
+        );
+
+        let single: ProxyRoutes = "http://127.0.0.1:7890/?a=b".parse().unwrap();
+        assert_eq!(
+            single
+                .route(Purpose::Upload, "")
+                .map(Route::to_string)
+                .as_deref(),
+            Some("http://127.0.0.1:7890/?a=b")
+        );
+        assert_eq!(
+            ProxyRoutes::default().route(Purpose::Extract, "a.com"),
+            None
+        );
+        assert!("ftp://127.0.0.1".parse::<ProxyRoutes>().is_err());
+        assert!("a/b=direct".parse::<ProxyRoutes>().is_err());
+    }
+
+    #[test]
+    fn unmatched_hosts_use_system_proxy() {
+        let system = SystemProxy::from_vars(|name| match name {
+            "HTTPS_PROXY" => Some("http://system:3128".to_string()),
+            "all_proxy" => Some("socks5h://all:1080".to_string()),
+            "NO_PROXY" => Some("localhost, .internal.test".to_string()),
+            _ => None,
+        });
+        let routes: ProxyRoutes = "*.douyu.com=http://dy:1, *.huya.com=direct"
+            .parse()
+            .unwrap();
+        let proxy = |url: &str| {
+            routes
+                .proxy_for(Purpose::Extract, &Url::parse(url).unwrap(), &system)
+                .map(String::from)
+        };
+        assert_eq!(
+            proxy("https://www.douyu.com/1").as_deref(),
+            Some("http://dy:1/")
+        );
+        assert_eq!(proxy("https://www.huya.com/1"), None);
+        assert_eq!(
+            proxy("https://live.bilibili.com/1").as_deref(),
+            Some("http://system:3128/")
+        );
+        assert_eq!(
+            proxy("http://live.bilibili.com/1").as_deref(),
+            Some("socks5h://all:1080")
+        );
+        assert_eq!(proxy("https://cdn.internal.test/live.flv"), None);
+        assert_eq!(proxy("http://localhost/live.flv"), None);
+        assert_eq!(
+            SystemProxy::from_vars(|_| None).proxy_for(&Url::parse("https://a.com").unwrap()),
+            None
+        );
+    }
+}
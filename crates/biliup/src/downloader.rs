@@ -3,21 +3,35 @@ use flv_parser::header;
 use nom::Err;
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use std::collections::HashMap;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 use crate::downloader::util::{LifecycleFile, Segmentable};
 
 use crate::client::StatelessClient;
-use crate::downloader::extractor::CallbackFn;
+use crate::downloader::event::{EventHandler, RecordingEvent};
+use crate::downloader::sidecar::Sidecar;
 use std::str::FromStr;
 
+mod dash;
 pub mod error;
+pub mod event;
 pub mod extractor;
+pub mod filter;
 pub mod flv_parser;
 pub mod flv_writer;
+pub mod fmp4;
 mod hls;
 pub mod httpflv;
+pub mod postprocess;
+pub mod probe;
+mod rtmp;
+pub mod sidecar;
+pub mod storage;
+pub mod template;
+#[cfg(test)]
+mod testing;
 pub mod util;
+pub mod vod;
 
 #[tokio::main]
 pub async fn download(
@@ -25,10 +39,10 @@ pub async fn download(
     headers: HeaderMap,
     file_name: &str,
     segment: Segmentable,
-    file_name_hook: Option<CallbackFn>,
+    events: Option<EventHandler>,
     proxy: Option<&str>,
 ) -> anyhow::Result<()> {
-    let client = StatelessClient::new(headers, proxy);
+    let client = StatelessClient::new(headers, proxy)?;
     let response = client.retryable(url).await?;
     let mut connection = Connection::new(response);
     // let buf = &mut [0u8; 9];
@@ -43,16 +57,31 @@ pub async fn download(
         Ok((_i, header)) => {
             debug!("header: {header:#?}");
             info!("Downloading {}...", url);
-            let file = LifecycleFile::new(file_name, "flv", file_name_hook);
-            httpflv::download(connection, file, segment).await;
+            let mut file = LifecycleFile::new(file_name, "flv", events.clone());
+            file.filter = segment.filter().cloned();
+            file.sidecar = Some(Sidecar::direct(url));
+            let result = httpflv::parse_flv(connection, file, segment).await;
+            if let Some(events) = &events {
+                events(&RecordingEvent::ended(&result));
+            }
+            match result {
+                Ok(_) => info!("Done... {}", file_name),
+                Err(e) => warn!("{e}"),
+            }
         }
         Err(Err::Incomplete(needed)) => {
             error!("needed: {needed:?}")
         }
         Err(e) => {
             error!("{e}");
-            let file = LifecycleFile::new(file_name, "ts", file_name_hook);
-            hls::download(url, &client, file, segment).await?;
+            let mut file = LifecycleFile::new(file_name, "ts", events.clone());
+            file.filter = segment.filter().cloned();
+            file.sidecar = Some(Sidecar::direct(url));
+            let result = hls::download(url, &client, file, segment).await;
+            if let Some(events) = &events {
+                events(&RecordingEvent::ended(&result));
+            }
+            result?;
         }
     }
     Ok(())
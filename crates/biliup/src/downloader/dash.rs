@@ -0,0 +1,777 @@
+//! Records MPEG-DASH streams addressed by segment templates. The video and audio
+//! representations are written to fragmented MP4 files of their own, which are muxed
+//! into one with [`fmp4::mux`] whenever a file is finished.
+
+use crate::client::StatelessClient;
+use crate::downloader::error::{Error, Result};
+use crate::downloader::event::{RecordingEvent, Resolution, SplitReason};
+use crate::downloader::fmp4;
+use crate::downloader::hls::download_to_file;
+use crate::downloader::util::{LifecycleFile, Segmentable};
+use bytes::Bytes;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use roxmltree::Node;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{debug, error, info};
+use url::Url;
+
+/// A live stream without new segments for this long has ended.
+const STALL_TIMEOUT: Duration = Duration::from_secs(60);
+/// Segments before the live edge a new recording starts with.
+const LIVE_EDGE_SEGMENTS: u64 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ContentType {
+    Video,
+    Audio,
+    Other,
+}
+
+pub(crate) struct Manifest {
+    /// A live stream, whose manifest is fetched again for new segments.
+    pub(crate) dynamic: bool,
+    update_period: Option<Duration>,
+    availability_start: Option<DateTime<Utc>>,
+    duration: Option<Duration>,
+    /// Representations of the last period.
+    pub(crate) representations: Vec<Representation>,
+}
+
+pub(crate) struct Representation {
+    pub(crate) id: String,
+    pub(crate) content_type: ContentType,
+    pub(crate) bandwidth: u64,
+    /// RFC 6381 codec string.
+    pub(crate) codecs: Option<String>,
+    pub(crate) resolution: Option<Resolution>,
+    base_url: Url,
+    template: Template,
+    /// Start of the period in the presentation.
+    period_start: Duration,
+}
+
+/// A `SegmentTemplate`, with the attributes inherited from the enclosing elements.
+#[derive(Clone, Default)]
+struct Template {
+    initialization: Option<String>,
+    media: Option<String>,
+    start_number: Option<u64>,
+    timescale: Option<u64>,
+    duration: Option<u64>,
+    presentation_time_offset: Option<u64>,
+    timeline: Vec<TimelineEntry>,
+}
+
+/// An `S` element of a `SegmentTimeline`.
+#[derive(Clone)]
+struct TimelineEntry {
+    time: Option<u64>,
+    duration: u64,
+    repeat: i64,
+}
+
+struct Segment {
+    /// Start time for timelines, the number otherwise.
+    key: u64,
+    url: Url,
+    duration: Duration,
+}
+
+impl Manifest {
+    pub(crate) fn parse(text: &str, url: &Url) -> Result<Manifest> {
+        let invalid = |e: String| Error::Custom(format!("Invalid DASH manifest: {e}"));
+        let document = roxmltree::Document::parse(text).map_err(|e| invalid(e.to_string()))?;
+        let mpd = document.root_element();
+        if mpd.tag_name().name() != "MPD" {
+            return Err(invalid(format!("unexpected <{}>", mpd.tag_name().name())));
+        }
+        let period = children(mpd, "Period")
+            .last()
+            .ok_or_else(|| invalid("no Period".to_string()))?;
+        let period_url = base_url(period, &base_url(mpd, url)?)?;
+        let period_template = template(period, &Template::default());
+        let period_start = period
+            .attribute("start")
+            .and_then(parse_duration)
+            .unwrap_or_default();
+        let mut representations = Vec::new();
+        for set in children(period, "AdaptationSet") {
+            let set_url = base_url(set, &period_url)?;
+            let set_template = template(set, &period_template);
+            for representation in children(set, "Representation") {
+                let attribute = |name| {
+                    representation
+                        .attribute(name)
+                        .or_else(|| set.attribute(name))
+                };
+                let template = template(representation, &set_template);
+                if template.media.is_none() {
+                    debug!("DASH representation without a SegmentTemplate");
+                    continue;
+                }
+                let mime_type = attribute("mimeType").unwrap_or_default();
+                let content_type = set
+                    .attribute("contentType")
+                    .unwrap_or_else(|| mime_type.split('/').next().unwrap_or_default());
+                let number = |name| attribute(name).and_then(|v: &str| v.parse().ok());
+                representations.push(Representation {
+                    id: representation
+                        .attribute("id")
+                        .unwrap_or_default()
+                        .to_string(),
+                    content_type: match content_type {
+                        "video" => ContentType::Video,
+                        "audio" => ContentType::Audio,
+                        _ => ContentType::Other,
+                    },
+                    bandwidth: number("bandwidth").unwrap_or_default(),
+                    codecs: attribute("codecs").map(String::from),
+                    resolution: number("width")
+                        .zip(number("height"))
+                        .map(|(width, height)| Resolution {
+                            width: width as u32,
+                            height: height as u32,
+                        }),
+                    base_url: base_url(representation, &set_url)?,
+                    template,
+                    period_start,
+                });
+            }
+        }
+        if representations.is_empty() {
+            return Err(Error::Custom(
+                "No representation with a SegmentTemplate in the DASH manifest".to_string(),
+            ));
+        }
+        Ok(Manifest {
+            dynamic: mpd.attribute("type") == Some("dynamic"),
+            update_period: mpd
+                .attribute("minimumUpdatePeriod")
+                .and_then(parse_duration),
+            availability_start: mpd.attribute("availabilityStartTime").and_then(parse_date),
+            duration: mpd
+                .attribute("mediaPresentationDuration")
+                .and_then(parse_duration),
+            representations,
+        })
+    }
+
+    /// The video and the audio representation with the highest bandwidth, or the best
+    /// one of any type when there are neither.
+    pub(crate) fn select(&self) -> Vec<&Representation> {
+        let best = |content_type| {
+            self.representations
+                .iter()
+                .filter(|r| r.content_type == content_type)
+                .max_by_key(|r| r.bandwidth)
+        };
+        let selected: Vec<_> = [best(ContentType::Video), best(ContentType::Audio)]
+            .into_iter()
+            .flatten()
+            .collect();
+        if selected.is_empty() {
+            return self
+                .representations
+                .iter()
+                .max_by_key(|r| r.bandwidth)
+                .into_iter()
+                .collect();
+        }
+        selected
+    }
+}
+
+impl Representation {
+    fn init_url(&self) -> Result<Option<Url>> {
+        match &self.template.initialization {
+            Some(init) => Ok(Some(self.base_url.join(&self.expand(init, 0, 0))?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The segments listed by the timeline, or the ones complete at `now` according to
+    /// the segment duration, only the latest of which are kept by live streams.
+    fn segments(&self, manifest: &Manifest, now: DateTime<Utc>) -> Result<Vec<Segment>> {
+        let template = &self.template;
+        let media = template.media.as_deref().unwrap_or_default();
+        let timescale = template.timescale.unwrap_or(1).max(1) as f64;
+        let start_number = template.start_number.unwrap_or(1);
+        let mut segments = Vec::new();
+        if !template.timeline.is_empty() {
+            let (mut time, mut number) = (0, start_number);
+            for (i, entry) in template.timeline.iter().enumerate() {
+                time = entry.time.unwrap_or(time);
+                let repeat = match (entry.repeat, template.timeline.get(i + 1)) {
+                    (repeat, _) if repeat >= 0 => repeat as u64,
+                    // Repeated up to the next entry.
+                    (
+                        _,
+                        Some(TimelineEntry {
+                            time: Some(next), ..
+                        }),
+                    ) if entry.duration > 0 => {
+                        (next.saturating_sub(time) / entry.duration).saturating_sub(1)
+                    }
+                    _ => 0,
+                };
+                for _ in 0..=repeat {
+                    segments.push(Segment {
+                        key: time,
+                        url: self.base_url.join(&self.expand(media, number, time))?,
+                        duration: Duration::from_secs_f64(entry.duration as f64 / timescale),
+                    });
+                    time += entry.duration;
+                    number += 1;
+                }
+            }
+            return Ok(segments);
+        }
+
+        let duration = template
+            .duration
+            .filter(|d| *d > 0)
+            .ok_or_else(|| Error::Custom(format!("No segment duration for {}", self.id)))?;
+        let seconds = duration as f64 / timescale;
+        let numbers = if manifest.dynamic {
+            let start = manifest.availability_start.ok_or_else(|| {
+                Error::Custom("Live DASH manifest without availabilityStartTime".to_string())
+            })?;
+            let elapsed = (now - start)
+                .to_std()
+                .unwrap_or_default()
+                .saturating_sub(self.period_start);
+            // A segment can be fetched once it ended.
+            let end = start_number + (elapsed.as_secs_f64() / seconds) as u64;
+            end.saturating_sub(LIVE_EDGE_SEGMENTS).max(start_number)..end
+        } else {
+            let total = manifest.duration.unwrap_or_default();
+            let count = total.saturating_sub(self.period_start).as_secs_f64() / seconds;
+            start_number..start_number + count.ceil() as u64
+        };
+        for number in numbers {
+            let time = (number - start_number) * duration
+                + template.presentation_time_offset.unwrap_or_default();
+            segments.push(Segment {
+                key: number,
+                url: self.base_url.join(&self.expand(media, number, time))?,
+                duration: Duration::from_secs_f64(seconds),
+            });
+        }
+        Ok(segments)
+    }
+
+    /// Fills in the `$RepresentationID$`, `$Number$`, `$Time$` and `$Bandwidth$`
+    /// identifiers of `template`, with an optional `%0<width>d` format.
+    fn expand(&self, template: &str, number: u64, time: u64) -> String {
+        let mut expanded = String::new();
+        for (i, part) in template.split('$').enumerate() {
+            if i % 2 == 0 {
+                expanded.push_str(part);
+                continue;
+            }
+            let (name, format) = part.split_once('%').unwrap_or((part, ""));
+            let value = match name {
+                "" => {
+                    expanded.push('$');
+                    continue;
+                }
+                "RepresentationID" => {
+                    expanded.push_str(&self.id);
+                    continue;
+                }
+                "Number" => number,
+                "Time" => time,
+                "Bandwidth" => self.bandwidth,
+                _ => {
+                    expanded.push_str(&format!("${part}$"));
+                    continue;
+                }
+            };
+            let width = format
+                .strip_prefix('0')
+                .and_then(|f| f.strip_suffix('d'))
+                .and_then(|w| w.parse().ok())
+                .unwrap_or(0);
+            expanded.push_str(&format!("{value:0width$}"));
+        }
+        expanded
+    }
+}
+
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == name)
+}
+
+/// `parent` resolved against the `BaseURL` of `node`, if it has one.
+fn base_url(node: Node, parent: &Url) -> Result<Url> {
+    match children(node, "BaseURL").next().and_then(|n| n.text()) {
+        Some(base) => Ok(parent.join(base.trim())?),
+        None => Ok(parent.clone()),
+    }
+}
+
+/// The `SegmentTemplate` of `node` on top of the one of its parent.
+fn template(node: Node, parent: &Template) -> Template {
+    let Some(element) = children(node, "SegmentTemplate").next() else {
+        return parent.clone();
+    };
+    let number = |name| element.attribute(name).and_then(|v: &str| v.parse().ok());
+    let timeline: Vec<_> = children(element, "SegmentTimeline")
+        .flat_map(|timeline| children(timeline, "S"))
+        .map(|s| TimelineEntry {
+            time: s.attribute("t").and_then(|v| v.parse().ok()),
+            duration: s.attribute("d").and_then(|v| v.parse().ok()).unwrap_or(0),
+            repeat: s.attribute("r").and_then(|v| v.parse().ok()).unwrap_or(0),
+        })
+        .collect();
+    Template {
+        initialization: element
+            .attribute("initialization")
+            .map(String::from)
+            .or_else(|| parent.initialization.clone()),
+        media: element
+            .attribute("media")
+            .map(String::from)
+            .or_else(|| parent.media.clone()),
+        start_number: number("startNumber").or(parent.start_number),
+        timescale: number("timescale").or(parent.timescale),
+        duration: number("duration").or(parent.duration),
+        presentation_time_offset: number("presentationTimeOffset")
+            .or(parent.presentation_time_offset),
+        timeline: if timeline.is_empty() {
+            parent.timeline.clone()
+        } else {
+            timeline
+        },
+    }
+}
+
+/// ISO 8601 durations as used by DASH, such as `PT1H2M3.5S` or `P1DT12H`.
+fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim().strip_prefix('P')?;
+    let (date, time) = s.split_once('T').unwrap_or((s, ""));
+    let mut seconds = 0.;
+    for (part, units) in [
+        (
+            date,
+            &[('Y', 365. * 86400.), ('M', 30. * 86400.), ('D', 86400.)][..],
+        ),
+        (time, &[('H', 3600.), ('M', 60.), ('S', 1.)][..]),
+    ] {
+        let mut rest = part;
+        for (unit, factor) in units {
+            if let Some((value, tail)) = rest.split_once(*unit) {
+                seconds += value.parse::<f64>().ok()? * factor;
+                rest = tail;
+            }
+        }
+        if !rest.is_empty() {
+            return None;
+        }
+    }
+    Some(Duration::from_secs_f64(seconds))
+}
+
+/// `xs:dateTime`, taken as UTC without a time zone.
+fn parse_date(s: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(s)
+        .map(|date| date.with_timezone(&Utc))
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f").map(|d| d.and_utc()))
+        .ok()
+}
+
+/// Appends the segments of the manifest at `url` to `dash_file` until the stream ends,
+/// starting with `mpd` if it was fetched already. Segments up to the last one of
+/// each track, written by an earlier connection to the same stream, are skipped.
+pub(crate) async fn download_to(
+    url: &str,
+    mut mpd: Option<Bytes>,
+    client: &StatelessClient,
+    dash_file: &mut DashFile,
+    splitting: &mut Segmentable,
+) -> Result<()> {
+    info!("Downloading {}...", url);
+    let manifest_url = Url::parse(url)?;
+    let mut idle = Duration::ZERO;
+    loop {
+        let bytes = match mpd.take() {
+            Some(bytes) => bytes,
+            None => client.retryable(url).await?.bytes().await?,
+        };
+        let text = String::from_utf8_lossy(&bytes);
+        let manifest = Manifest::parse(&text, &manifest_url)?;
+        let selected = manifest.select();
+        dash_file.select(&selected)?;
+        let mut written = 0;
+        for (index, representation) in selected.iter().enumerate() {
+            let segments = representation.segments(&manifest, Utc::now())?;
+            written += dash_file
+                .append(
+                    index,
+                    representation,
+                    &segments,
+                    manifest.dynamic,
+                    client,
+                    splitting,
+                )
+                .await?;
+        }
+        if written > 0 {
+            idle = Duration::ZERO;
+            dash_file.file.duration = splitting.duration();
+            dash_file.file.check_space()?;
+            if splitting.needed() {
+                dash_file.file.split_reason = SplitReason::Policy;
+                splitting.rename_next(&mut dash_file.file);
+                dash_file.create_new()?;
+                splitting.reset();
+            }
+        }
+        if !manifest.dynamic {
+            info!("Static DASH manifest, all segments written");
+            break;
+        }
+        let wait = manifest
+            .update_period
+            .unwrap_or(Duration::from_secs(2))
+            .clamp(Duration::from_secs(1), Duration::from_secs(10));
+        if written == 0 {
+            idle += wait;
+            if idle >= STALL_TIMEOUT {
+                info!("No new segments for {idle:?} - stream finished");
+                break;
+            }
+        }
+        tokio::time::sleep(wait).await;
+    }
+    info!("Done...");
+    Ok(())
+}
+
+/// The representations being recorded, each to a file of its own, and the file they're
+/// muxed into.
+pub(crate) struct DashFile {
+    pub(crate) file: LifecycleFile,
+    tracks: Vec<Track>,
+    written: bool,
+    /// Number of media segments written over all files.
+    pub(crate) segments: u64,
+}
+
+struct Track {
+    /// Id of the representation.
+    id: String,
+    content_type: ContentType,
+    init: Option<Bytes>,
+    path: PathBuf,
+    out: BufWriter<File>,
+    /// Key of the last segment written, kept across reconnects.
+    last: Option<u64>,
+}
+
+impl DashFile {
+    pub(crate) fn new(mut file: LifecycleFile) -> std::io::Result<Self> {
+        file.create()?;
+        Ok(Self {
+            file,
+            tracks: Vec::new(),
+            written: false,
+            segments: 0,
+        })
+    }
+
+    /// Records `representations` from now on, starting a new file if media of others
+    /// was written already.
+    fn select(&mut self, representations: &[&Representation]) -> Result<()> {
+        if self
+            .tracks
+            .iter()
+            .map(|t| t.id.as_str())
+            .eq(representations.iter().map(|r| r.id.as_str()))
+        {
+            return Ok(());
+        }
+        info!(
+            "DASH representations: {:?}",
+            representations.iter().map(|r| &r.id).collect::<Vec<_>>()
+        );
+        if self.written {
+            self.file.split_reason = SplitReason::StreamChanged;
+            self.mux();
+            self.file.rename();
+            self.file.create()?;
+            self.written = false;
+        } else if self.tracks.len() > 1 {
+            for track in &self.tracks {
+                let _ = fs::remove_file(&track.path);
+            }
+        }
+        let previous = std::mem::take(&mut self.tracks);
+        let muxed = representations.len() > 1;
+        for representation in representations {
+            let path = track_path(&self.file.path, representation.content_type, muxed);
+            self.tracks.push(Track {
+                id: representation.id.clone(),
+                content_type: representation.content_type,
+                init: None,
+                out: BufWriter::new(File::create(&path)?),
+                path,
+                // The same timeline continues in the other representations.
+                last: previous
+                    .iter()
+                    .find(|t| t.content_type == representation.content_type)
+                    .and_then(|t| t.last),
+            });
+            match representation.content_type {
+                ContentType::Audio => self.file.audio_codec = representation.codecs.clone(),
+                _ => {
+                    self.file.video_codec = representation.codecs.clone();
+                    self.file.resolution = representation.resolution;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the segments after the last one of the track at `index`, returns how many.
+    async fn append(
+        &mut self,
+        index: usize,
+        representation: &Representation,
+        segments: &[Segment],
+        dynamic: bool,
+        client: &StatelessClient,
+        splitting: &mut Segmentable,
+    ) -> Result<u64> {
+        let track = &mut self.tracks[index];
+        let mut last = track.last;
+        if last.is_some_and(|last| segments.last().is_some_and(|s| s.key < last)) {
+            info!("DASH stream of {} started over", track.id);
+            last = None;
+        }
+        let skip = match last {
+            Some(last) => segments.iter().take_while(|s| s.key <= last).count(),
+            None if dynamic => segments.len().saturating_sub(LIVE_EDGE_SEGMENTS as usize),
+            None => 0,
+        };
+        let mut written = 0;
+        for segment in &segments[skip..] {
+            if track.init.is_none()
+                && let Some(url) = representation.init_url()?
+            {
+                let init = client.retryable(url.as_str()).await?.bytes().await?;
+                track.out.write_all(&init)?;
+                track.init = Some(init);
+            }
+            let length = download_to_file(segment.url.clone(), client, &mut track.out).await?;
+            splitting.increase_size(length);
+            // The first track, the video if there is one, decides the duration.
+            if index == 0 {
+                splitting.increase_time(segment.duration);
+            }
+            track.last = Some(segment.key);
+            self.written = true;
+            self.segments += 1;
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    fn create_new(&mut self) -> std::io::Result<()> {
+        if self.mux() {
+            self.file.rename();
+        }
+        self.file.create()?;
+        self.written = false;
+        let muxed = self.tracks.len() > 1;
+        for track in &mut self.tracks {
+            track.path = track_path(&self.file.path, track.content_type, muxed);
+            track.out = BufWriter::new(File::create(&track.path)?);
+            if let Some(init) = &track.init {
+                track.out.write_all(init)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Muxes the tracks into the current file, a single track is written to it directly.
+    /// Returns false and removes the files if no media was written to any track.
+    fn mux(&mut self) -> bool {
+        for track in &mut self.tracks {
+            if let Err(e) = track.out.flush() {
+                error!("flush {} {e}", track.path.display())
+            }
+        }
+        if !self.written {
+            for track in &self.tracks {
+                let _ = fs::remove_file(&track.path);
+            }
+            let _ = fs::remove_file(&self.file.path);
+            return false;
+        }
+        let result = match &self.tracks[..] {
+            [video, audio] => fmp4::mux(&video.path, &audio.path, &self.file.path),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            error!("mux {} {e}", self.file.path.display());
+            self.file.emit(RecordingEvent::Error {
+                message: format!("Unable to mux {}: {e}", self.file.path.display()),
+            });
+        }
+        if self.tracks.len() > 1 {
+            for track in &self.tracks {
+                let _ = fs::remove_file(&track.path);
+            }
+        }
+        true
+    }
+}
+
+/// Where a track is written, the file itself unless it gets muxed with another.
+fn track_path(path: &Path, content_type: ContentType, muxed: bool) -> PathBuf {
+    if !muxed {
+        return path.to_path_buf();
+    }
+    let mut name = path.as_os_str().to_owned();
+    name.push(match content_type {
+        ContentType::Video => ".video",
+        ContentType::Audio => ".audio",
+        ContentType::Other => ".other",
+    });
+    PathBuf::from(name)
+}
+
+impl Drop for DashFile {
+    fn drop(&mut self) {
+        if self.mux() {
+            self.file.close()
+        } else {
+            self.file.flush_filter()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader::testing::{TempDir, collect_events};
+    use anyhow::Result;
+
+    const LIVE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="dynamic" minimumUpdatePeriod="PT2S"
+     availabilityStartTime="2024-01-01T00:00:00Z">
+  <BaseURL>https://cdn.example.com/live/</BaseURL>
+  <Period id="1" start="PT0S">
+    <AdaptationSet mimeType="video/mp4" codecs="avc1.64001f">
+      <SegmentTemplate timescale="90000" initialization="$RepresentationID$/init.mp4"
+                       media="$RepresentationID$/$Time$.m4s" startNumber="1">
+        <SegmentTimeline>
+          <S t="900000" d="180000" r="2"/>
+          <S d="90000"/>
+        </SegmentTimeline>
+      </SegmentTemplate>
+      <Representation id="720p" bandwidth="3000000" width="1280" height="720"/>
+      <Representation id="1080p" bandwidth="6000000" width="1920" height="1080"/>
+    </AdaptationSet>
+    <AdaptationSet contentType="audio" mimeType="audio/mp4">
+      <SegmentTemplate timescale="48000" duration="96000" startNumber="10"
+                       initialization="a/init.mp4" media="a/$Number%05d$.m4s"/>
+      <Representation id="aac" bandwidth="128000" codecs="mp4a.40.2"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+
+    #[test]
+    fn parse_live_manifest() -> Result<()> {
+        let url = Url::parse("https://example.com/room/manifest.mpd")?;
+        let manifest = Manifest::parse(LIVE, &url)?;
+        assert!(manifest.dynamic);
+        assert_eq!(manifest.update_period, Some(Duration::from_secs(2)));
+
+        let selected = manifest.select();
+        let [video, audio] = selected[..] else {
+            panic!("expected video and audio");
+        };
+        assert_eq!(video.id, "1080p");
+        assert_eq!(video.codecs.as_deref(), Some("avc1.64001f"));
+        assert_eq!(video.resolution.map(|r| r.height), Some(1080));
+        assert_eq!(
+            video.init_url()?.map(String::from).as_deref(),
+            Some("https://cdn.example.com/live/1080p/init.mp4")
+        );
+        let segments = video.segments(&manifest, Utc::now())?;
+        let times: Vec<_> = segments.iter().map(|s| s.key).collect();
+        assert_eq!(times, [900000, 1080000, 1260000, 1440000]);
+        assert_eq!(
+            segments[3].url.as_str(),
+            "https://cdn.example.com/live/1080p/1440000.m4s"
+        );
+        assert_eq!(segments[3].duration, Duration::from_secs(1));
+
+        // Two second segments since midnight, the last three complete ones at 00:01:00.
+        let now = DateTime::parse_from_rfc3339("2024-01-01T00:01:00Z")?.with_timezone(&Utc);
+        let segments = audio.segments(&manifest, now)?;
+        let urls: Vec<_> = segments.iter().map(|s| s.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://cdn.example.com/live/a/00037.m4s",
+                "https://cdn.example.com/live/a/00038.m4s",
+                "https://cdn.example.com/live/a/00039.m4s",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn durations_and_identifiers() {
+        assert_eq!(
+            parse_duration("PT1H2M3.5S"),
+            Some(Duration::from_secs_f64(3723.5))
+        );
+        assert_eq!(parse_duration("P1DT1S"), Some(Duration::from_secs(86401)));
+        assert_eq!(parse_duration("1S"), None);
+        assert!(parse_date("2024-01-01T00:00:00").is_some());
+
+        let url = Url::parse("https://example.com/manifest.mpd").unwrap();
+        let manifest = Manifest::parse(LIVE, &url).unwrap();
+        let video = &manifest.representations[0];
+        assert_eq!(
+            video.expand(
+                "$RepresentationID$-$Bandwidth$-$Number%03d$-$$-$Other$",
+                7,
+                0
+            ),
+            "720p-3000000-007-$-$Other$"
+        );
+    }
+
+    #[test]
+    fn nothing_written_leaves_no_file() -> Result<()> {
+        let dir = TempDir::new("dash")?;
+        let (handler, events) = collect_events();
+        let file = LifecycleFile::new(&dir.join("out").to_string_lossy(), "mp4", Some(handler));
+        let manifest = Manifest::parse(LIVE, &Url::parse("https://example.com/live.mpd")?)?;
+        let mut dash_file = DashFile::new(file)?;
+        dash_file.select(&manifest.select())?;
+        dash_file.create_new()?;
+        drop(dash_file);
+
+        assert_eq!(fs::read_dir(dir.path())?.count(), 0);
+        let events = events.lock().unwrap();
+        assert!(
+            !events
+                .iter()
+                .any(|e| matches!(e, RecordingEvent::SegmentClosed(_)))
+        );
+        Ok(())
+    }
+}
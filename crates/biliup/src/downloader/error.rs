@@ -17,6 +17,9 @@ pub enum Error {
     #[error(transparent)]
     ReqwestError(#[from] reqwest::Error),
 
+    #[error(transparent)]
+    ReqwestMiddleware(#[from] reqwest_middleware::Error),
+
     #[error(transparent)]
     UrlParseError(#[from] url::ParseError),
 
@@ -0,0 +1,122 @@
+//! What happens to a recording, reported to an [`EventHandler`] so that callers can
+//! upload finished files, keep track of the recording or surface failures.
+
+use crate::downloader::filter::Undersized;
+use chrono::{DateTime, Local};
+use serde::{Serialize, Serializer};
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub type EventHandler = Arc<dyn Fn(&RecordingEvent) + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RecordingEvent {
+    /// A new file is being written, under its temporary `.part` name.
+    FileOpened {
+        path: PathBuf,
+    },
+    /// A file is complete and got its final name.
+    SegmentClosed(SegmentInfo),
+    /// A file is complete but below the minimum size or duration of the
+    /// [`SegmentFilter`](super::filter::SegmentFilter), and isn't reported as closed.
+    SegmentUndersized {
+        #[serde(flatten)]
+        segment: SegmentInfo,
+        action: Undersized,
+    },
+    RecordingEnded {
+        reason: EndReason,
+    },
+    /// The retention policy of the [`DiskGuard`](super::storage::DiskGuard) deleted an
+    /// older recording to make room.
+    RecordingDeleted {
+        path: PathBuf,
+        size: u64,
+    },
+    /// Less than `min_free` bytes are left on the disk of `path`, the recording stops.
+    LowDiskSpace {
+        path: PathBuf,
+        available: u64,
+        min_free: u64,
+    },
+    /// A failure the recording may recover from, such as a dropped connection.
+    Error {
+        message: String,
+    },
+}
+
+impl RecordingEvent {
+    pub fn ended<E: Display>(result: &Result<(), E>) -> Self {
+        let reason = match result {
+            Ok(()) => EndReason::StreamEnded,
+            Err(e) => EndReason::Failed {
+                message: e.to_string(),
+            },
+        };
+        RecordingEvent::RecordingEnded { reason }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SegmentInfo {
+    pub path: PathBuf,
+    /// Bytes in the file.
+    pub size: u64,
+    #[serde(serialize_with = "rfc3339")]
+    pub started_at: DateTime<Local>,
+    #[serde(serialize_with = "rfc3339")]
+    pub ended_at: DateTime<Local>,
+    /// Stream time covered by the file.
+    #[serde(serialize_with = "seconds")]
+    pub duration: Duration,
+    pub reason: SplitReason,
+    /// RFC 6381 codec strings, as far as the container tells.
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub resolution: Option<Resolution>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Why a file was closed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitReason {
+    /// The [`SegmentPolicy`](super::util::SegmentPolicy) asked for a new file.
+    Policy,
+    /// The title or area of the room changed.
+    Metadata,
+    /// The codec parameters changed or the playlist announced a discontinuity.
+    StreamChanged,
+    /// The recording ended.
+    #[default]
+    End,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndReason {
+    /// The stream ended, or went away and couldn't be resumed.
+    StreamEnded,
+    Failed {
+        message: String,
+    },
+}
+
+pub(crate) fn rfc3339<S: Serializer>(
+    time: &DateTime<Local>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&time.to_rfc3339())
+}
+
+fn seconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
@@ -1,24 +1,143 @@
 use crate::downloader;
-use crate::downloader::httpflv::Connection;
-use crate::downloader::util::{LifecycleFile, Segmentable};
-use crate::downloader::{hls, httpflv};
+use crate::downloader::dash::{self, DashFile};
+use crate::downloader::event::{EventHandler, RecordingEvent};
+use crate::downloader::hls;
+use crate::downloader::hls::TsFile;
+use crate::downloader::httpflv::{Connection, FlvRecorder};
+use crate::downloader::postprocess::PostProcessor;
+use crate::downloader::probe::{self, StreamProbe};
+use crate::downloader::rtmp;
+use crate::downloader::sidecar::Sidecar;
+use crate::downloader::storage::DiskGuard;
+use crate::downloader::template;
+use crate::downloader::util::{LifecycleFile, Segmentable, SplitHandle};
 use async_trait::async_trait;
-use reqwest::header::{ACCEPT_ENCODING, HeaderValue};
+use chrono::{DateTime, Local};
+use reqwest::header::{ACCEPT_ENCODING, COOKIE, HeaderValue};
+use serde::{Deserialize, Serialize};
 use std::any::Any;
+use std::cmp::Reverse;
 use std::fmt::{Display, Formatter};
-use tracing::info;
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock, RwLock};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+use url::Url;
 
 use crate::client::StatelessClient;
+use crate::uploader::credential::LoginInfo;
 
 mod bilibili;
+mod direct;
+mod douyin;
 mod douyu;
 mod huya;
+mod kuaishou;
+mod twitch;
 
-const EXTRACTORS: [&(dyn SiteDefinition + Send + Sync); 3] = [
-    &bilibili::BiliLive {},
-    &huya::HuyaLive {},
-    &douyu::DouyuLive,
-];
+pub use twitch::TwitchLive;
+
+/// Priority of the built-in extractors. Registrations with a higher priority are consulted first.
+pub const DEFAULT_PRIORITY: i32 = 0;
+
+/// Priority of the extractor for direct stream urls, consulted after the platform ones.
+pub const FALLBACK_PRIORITY: i32 = -100;
+
+pub type DynSiteDefinition = Arc<dyn SiteDefinition + Send + Sync>;
+
+/// User agent of a desktop Chrome, for sites that serve other clients a different page.
+pub(crate) const BROWSER_UA: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/116.0.0.0 Safari/537.36";
+
+static EXTRACTORS: LazyLock<RwLock<Registry>> = LazyLock::new(|| RwLock::new(Registry::builtin()));
+
+/// Extractors ordered by priority, highest first.
+struct Registry(Vec<Registration>);
+
+struct Registration {
+    name: String,
+    priority: i32,
+    extractor: DynSiteDefinition,
+}
+
+impl Registration {
+    fn new(name: &str, priority: i32, extractor: DynSiteDefinition) -> Self {
+        Self {
+            name: name.to_string(),
+            priority,
+            extractor,
+        }
+    }
+}
+
+impl Registry {
+    fn builtin() -> Self {
+        Self(vec![
+            Registration::new(
+                "bilibili",
+                DEFAULT_PRIORITY,
+                Arc::new(bilibili::BiliLive::default()),
+            ),
+            Registration::new("huya", DEFAULT_PRIORITY, Arc::new(huya::HuyaLive {})),
+            Registration::new("douyu", DEFAULT_PRIORITY, Arc::new(douyu::DouyuLive)),
+            Registration::new("douyin", DEFAULT_PRIORITY, Arc::new(douyin::DouyinLive)),
+            Registration::new(
+                "kuaishou",
+                DEFAULT_PRIORITY,
+                Arc::new(kuaishou::KuaishouLive),
+            ),
+            Registration::new("twitch", DEFAULT_PRIORITY, Arc::new(TwitchLive)),
+            Registration::new("direct", FALLBACK_PRIORITY, Arc::new(direct::DirectUrl)),
+        ])
+    }
+
+    fn register(
+        &mut self,
+        name: &str,
+        priority: i32,
+        extractor: DynSiteDefinition,
+    ) -> Option<DynSiteDefinition> {
+        let registration = Registration::new(name, priority, extractor);
+        let previous = match self.0.iter_mut().find(|r| r.name == name) {
+            Some(existing) => Some(std::mem::replace(existing, registration).extractor),
+            None => {
+                self.0.push(registration);
+                None
+            }
+        };
+        // Stable sort keeps registration order among extractors of equal priority.
+        self.0.sort_by_key(|r| Reverse(r.priority));
+        previous
+    }
+
+    fn unregister(&mut self, name: &str) -> Option<DynSiteDefinition> {
+        let index = self.0.iter().position(|r| r.name == name)?;
+        Some(self.0.remove(index).extractor)
+    }
+
+    fn list(&self) -> Vec<ExtractorInfo> {
+        self.0
+            .iter()
+            .map(|r| ExtractorInfo {
+                name: r.name.clone(),
+                priority: r.priority,
+            })
+            .collect()
+    }
+
+    fn find(&self, url: &str) -> Option<DynSiteDefinition> {
+        self.0
+            .iter()
+            .find(|r| r.extractor.can_handle_url(url))
+            .map(|r| r.extractor.clone())
+    }
+}
+
+/// A registered extractor as reported by [`extractors`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractorInfo {
+    pub name: String,
+    pub priority: i32,
+}
 
 #[async_trait]
 pub trait SiteDefinition {
@@ -27,13 +146,281 @@ pub trait SiteDefinition {
 
     async fn get_site(&self, url: &str, client: StatelessClient) -> super::error::Result<Site>;
 
+    /// Like [`SiteDefinition::get_site`], but honours the stream selection in `options`
+    /// where the site supports it.
+    async fn get_site_with_options(
+        &self,
+        url: &str,
+        client: StatelessClient,
+        _options: &StreamOptions,
+    ) -> super::error::Result<Site> {
+        self.get_site(url, client).await
+    }
+
+    /// Checks whether the room at `url` is live without resolving the stream, it's
+    /// polled for every room the server watches. The default resolves the whole stream
+    /// with [`SiteDefinition::get_site`], where an error means the room is offline, so
+    /// sites with a room info API override it; all the built-in platforms do.
+    async fn live_status(
+        &self,
+        url: &str,
+        client: StatelessClient,
+    ) -> super::error::Result<LiveStatus> {
+        let site = self.get_site(url, client).await?;
+        Ok(LiveStatus {
+            live: true,
+            title: site.title,
+            area: site.area,
+            cover: site.cover,
+        })
+    }
+
+    /// Like [`SiteDefinition::live_status`], but made with the cookies or token in
+    /// `options` where the site needs them.
+    async fn live_status_with_options(
+        &self,
+        url: &str,
+        client: StatelessClient,
+        _options: &StreamOptions,
+    ) -> super::error::Result<LiveStatus> {
+        self.live_status(url, client).await
+    }
+
+    /// [`SiteDefinition::live_status`] of many rooms, in the order of `urls`. Sites
+    /// able to check rooms in a single request override it.
+    async fn live_statuses(
+        &self,
+        urls: &[String],
+        client: StatelessClient,
+    ) -> Vec<super::error::Result<LiveStatus>> {
+        let mut statuses = Vec::with_capacity(urls.len());
+        for url in urls {
+            statuses.push(self.live_status(url, client.clone()).await);
+        }
+        statuses
+    }
+
     fn as_any(&self) -> &dyn Any;
 }
 
+/// Whether a room is live, as reported by [`SiteDefinition::live_status`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LiveStatus {
+    pub live: bool,
+    pub title: String,
+    pub area: String,
+    pub cover: String,
+}
+
+/// One of the streams a room offers, besides the one selected in [`Site::direct_url`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct StreamVariant {
+    /// Quality as named by the site, e.g. 原画.
+    pub name: String,
+    /// Value of [`StreamOptions::quality`] selecting this stream.
+    pub quality: Option<u32>,
+    pub codec: Option<Codec>,
+    pub protocol: Option<Protocol>,
+    /// CDN hosts or lines serving the stream.
+    pub cdns: Vec<String>,
+}
+
+/// Stream selection preferences passed to [`SiteDefinition::get_site_with_options`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::Args))]
+pub struct StreamOptions {
+    /// 目标画质, 如B站的qn, 不可用时选择最高画质
+    #[cfg_attr(feature = "cli", clap(long))]
+    #[serde(default)]
+    pub quality: Option<u32>,
+
+    /// 视频编码偏好
+    #[cfg_attr(feature = "cli", clap(long, value_enum))]
+    #[serde(default)]
+    pub codec: Option<Codec>,
+
+    /// 直播流协议
+    #[cfg_attr(feature = "cli", clap(long, value_enum))]
+    #[serde(default)]
+    pub protocol: Option<Protocol>,
+
+    /// 优先使用的CDN节点, 按顺序匹配域名
+    #[cfg_attr(feature = "cli", clap(long = "cdn"))]
+    #[serde(default)]
+    pub cdn_prefer: Vec<String>,
+
+    /// 禁止使用的CDN节点, 匹配域名
+    #[cfg_attr(feature = "cli", clap(long))]
+    #[serde(default)]
+    pub cdn_blacklist: Vec<String>,
+
+    /// 直链的附加请求头, 格式为 "Name: value". 其中的Cookie也用于需要登录的站点, 如快手
+    #[cfg_attr(feature = "cli", clap(long = "header"))]
+    #[serde(default)]
+    pub headers: Vec<String>,
+
+    /// Twitch的auth-token, 用于录制订阅限定的直播
+    #[cfg_attr(feature = "cli", clap(long))]
+    #[serde(skip)]
+    pub twitch_oauth_token: Option<String>,
+
+    /// Account to use for sites that serve better streams or more rooms after login.
+    #[cfg_attr(feature = "cli", clap(skip))]
+    #[serde(skip)]
+    pub credential: Option<Credential>,
+}
+
+impl StreamOptions {
+    /// Whether `host` is not excluded by `cdn_blacklist`.
+    pub fn cdn_allowed(&self, host: &str) -> bool {
+        !self.cdn_blacklist.iter().any(|h| host.contains(h.as_str()))
+    }
+
+    /// Position of `host` in `cdn_prefer`, hosts not listed rank last.
+    pub fn cdn_rank(&self, host: &str) -> usize {
+        self.cdn_prefer
+            .iter()
+            .position(|h| host.contains(h.as_str()))
+            .unwrap_or(self.cdn_prefer.len())
+    }
+
+    /// The `Cookie` header for requests to `url`: the cookies of `credential` when `url` is
+    /// under its domains, or else the one given in `headers`.
+    pub fn cookies_for(&self, url: &str) -> Option<&str> {
+        self.credential
+            .as_ref()
+            .and_then(|credential| credential.cookies_for(url))
+            .or_else(|| {
+                self.headers
+                    .iter()
+                    .filter_map(|header| header.split_once(':'))
+                    .find(|(name, _)| name.trim().eq_ignore_ascii_case("cookie"))
+                    .map(|(_, value)| value.trim())
+            })
+    }
+}
+
+/// Cookies of a logged in account, only sent to the sites under `domains`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Credential {
+    pub domains: Vec<String>,
+    pub cookies: String,
+}
+
+impl Credential {
+    /// The cookies if `url` belongs to one of `domains`.
+    pub fn cookies_for(&self, url: &str) -> Option<&str> {
+        let url = url::Url::parse(url).ok()?;
+        let host = url.host_str()?;
+        self.domains
+            .iter()
+            .map(|domain| domain.trim_start_matches('.'))
+            .any(|domain| host == domain || host.ends_with(&format!(".{domain}")))
+            .then_some(self.cookies.as_str())
+    }
+}
+
+impl From<&LoginInfo> for Credential {
+    fn from(login_info: &LoginInfo) -> Self {
+        let cookie_info = &login_info.cookie_info;
+        let cookies = cookie_info["cookies"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|c| Some(format!("{}={}", c["name"].as_str()?, c["value"].as_str()?)))
+            .collect::<Vec<_>>()
+            .join("; ");
+        let mut domains: Vec<String> = cookie_info["domains"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|d| d.as_str().map(String::from))
+            .collect();
+        if domains.is_empty() {
+            domains.push(".bilibili.com".to_string());
+        }
+        Self { domains, cookies }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    Avc,
+    Hevc,
+}
+
+impl Codec {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Codec::Avc => "avc",
+            Codec::Hevc => "hevc",
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "avc" => Ok(Codec::Avc),
+            "hevc" => Ok(Codec::Hevc),
+            _ => Err(format!("unknown codec: {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    HttpFlv,
+    HlsTs,
+    HlsFmp4,
+}
+
+impl Protocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::HttpFlv => "http_flv",
+            Protocol::HlsTs => "hls_ts",
+            Protocol::HlsFmp4 => "hls_fmp4",
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "http_flv" => Ok(Protocol::HttpFlv),
+            "hls_ts" => Ok(Protocol::HlsTs),
+            "hls_fmp4" => Ok(Protocol::HlsFmp4),
+            _ => Err(format!("unknown protocol: {s}")),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Site {
     pub name: &'static str,
     pub title: String,
     pub direct_url: String,
+    /// Urls of other CDNs tried in order when `direct_url` can't be connected.
+    pub fallback_urls: Vec<String>,
+    /// Every stream offered by the room, may be empty when the site doesn't list them.
+    pub streams: Vec<StreamVariant>,
+    /// Nickname of the streamer.
+    pub streamer: String,
+    pub room_id: String,
+    pub uid: String,
+    /// Area or category of the live room.
+    pub area: String,
+    pub cover: String,
+    pub live_start_time: Option<DateTime<Local>>,
     extension: Extension,
     client: StatelessClient,
 }
@@ -42,48 +429,637 @@ impl Display for Site {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "Name: {}", self.name)?;
         writeln!(f, "Title: {}", self.title)?;
+        writeln!(f, "Streamer: {} (uid {})", self.streamer, self.uid)?;
+        writeln!(f, "Room: {}", self.room_id)?;
+        writeln!(f, "Area: {}", self.area)?;
+        if let Some(live_start_time) = self.live_start_time {
+            writeln!(f, "Live since: {live_start_time}")?;
+        }
         write!(f, "Direct url: {}", self.direct_url)
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Extension {
     Flv,
     Ts,
+    Fmp4,
+    Dash,
+}
+
+impl Extension {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Extension::Flv => "flv",
+            Extension::Ts => "ts",
+            Extension::Fmp4 => "fmp4",
+            Extension::Dash => "dash",
+        }
+    }
 }
 
-pub type CallbackFn = Box<dyn Fn(&str) + Send>;
+/// How [`Site::download_with_reconnect`] resolves the stream again after the connection
+/// dropped.
+#[derive(Clone)]
+pub struct Reconnect {
+    pub extractor: DynSiteDefinition,
+    /// Url of the live room.
+    pub url: String,
+    pub options: StreamOptions,
+    /// Consecutive failed attempts before the recording ends, the count is reset once
+    /// a new connection delivered data.
+    pub retries: u32,
+    /// Wait before each attempt.
+    pub delay: Duration,
+    /// Checks the status of the room this often while recording and starts a new file
+    /// once the title or area changed.
+    pub watch_interval: Option<Duration>,
+}
+
+impl Reconnect {
+    pub fn new(extractor: DynSiteDefinition, url: &str, options: StreamOptions) -> Self {
+        Self {
+            extractor,
+            url: url.to_string(),
+            options,
+            retries: 5,
+            delay: Duration::from_secs(5),
+            watch_interval: None,
+        }
+    }
+
+    /// Splits through `split` whenever the title or area of the room differs from the
+    /// ones of `site`, naming the next file after `template`, and keeps `sidecar` up to
+    /// date.
+    async fn watch(
+        self,
+        mut site: Site,
+        interval: Duration,
+        template: String,
+        split: SplitHandle,
+        sidecar: Sidecar,
+    ) {
+        loop {
+            tokio::time::sleep(interval).await;
+            let status = match self
+                .extractor
+                .live_status_with_options(&self.url, site.client.clone(), &self.options)
+                .await
+            {
+                Ok(status) if status.live => status,
+                Ok(_) => continue,
+                Err(e) => {
+                    debug!("Unable to refresh {}: {e}", self.url);
+                    continue;
+                }
+            };
+            if status.title != site.title || status.area != site.area {
+                info!(
+                    "{} changed to {} ({}), starting a new file",
+                    self.url, status.title, status.area
+                );
+                site.title = status.title;
+                site.area = status.area;
+                split.start_new(site.render_file_name(&template));
+                sidecar.room_changed(&site);
+            }
+        }
+    }
+}
+
+/// Aborts the task once the recording ends.
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
 
 impl Site {
+    /// Substitutes the stream metadata placeholders `{title}`, `{streamer}`, `{platform}`,
+    /// `{room_id}`, `{uid}`, `{area}`, `{cover}` and `{live_start_time}` in `template`.
+    pub fn render(&self, template: &str) -> String {
+        template::render(template, |name| self.placeholder(name))
+    }
+
+    /// Like [`Site::render`], with the values made safe for file names and their `%` and
+    /// braces escaped, for templates that go to [`LifecycleFile`].
+    pub fn render_file_name(&self, template: &str) -> String {
+        template::render(template, |name| {
+            let value = template::sanitize(&self.placeholder(name)?);
+            Some(template::escape(&value).replace('%', "%%"))
+        })
+    }
+
+    fn placeholder(&self, name: &str) -> Option<String> {
+        Some(match name {
+            "title" => self.title.clone(),
+            "streamer" => self.streamer.clone(),
+            "platform" => self.name.to_string(),
+            "room_id" => self.room_id.clone(),
+            "uid" => self.uid.clone(),
+            "area" => self.area.clone(),
+            "cover" => self.cover.clone(),
+            "live_start_time" => self
+                .live_start_time
+                .map(|t| t.format("%Y-%m-%dT%H_%M_%S").to_string())
+                .unwrap_or_default(),
+            _ => return None,
+        })
+    }
+
+    pub fn extension(&self) -> Extension {
+        self.extension
+    }
+
+    /// Connects to the stream and reads its header and first codec parameters, without
+    /// recording anything.
+    pub async fn probe(&mut self) -> downloader::error::Result<StreamProbe> {
+        let connection = self.connect().await?;
+        match self.extension {
+            Extension::Flv => probe::flv(connection).await,
+            Extension::Ts | Extension::Fmp4 => probe::hls(&connection.bytes().await?),
+            Extension::Dash => {
+                let url = Url::parse(&self.direct_url)?;
+                probe::dash(&connection.bytes().await?, &url)
+            }
+        }
+    }
+
     pub async fn download(
         &mut self,
         fmt_file_name: &str,
         segment: Segmentable,
-        hook: Option<CallbackFn>,
+        events: Option<EventHandler>,
+    ) -> downloader::error::Result<()> {
+        self.download_with_reconnect(fmt_file_name, segment, events, None, None, None)
+            .await
+    }
+
+    /// Like [`Site::download`], but when the connection drops the stream url is resolved
+    /// again with `reconnect` and the recording continues in the same file, as long as
+    /// the room is still live and the retry budget isn't used up. With
+    /// [`Reconnect::watch_interval`] set, a change of the title or area splits the
+    /// recording at the next keyframe. `events` hears about every file and, last, how
+    /// the recording ended. `guard` stops the recording when the disk runs full.
+    pub async fn download_with_reconnect(
+        &mut self,
+        template: &str,
+        segment: Segmentable,
+        events: Option<EventHandler>,
+        reconnect: Option<&Reconnect>,
+        guard: Option<&DiskGuard>,
+        postprocess: Option<&PostProcessor>,
     ) -> downloader::error::Result<()> {
-        let fmt_file_name = fmt_file_name.replace("{title}", &self.title);
+        let result = self
+            .record(
+                template,
+                segment,
+                events.clone(),
+                reconnect,
+                guard,
+                postprocess,
+            )
+            .await;
+        if let Some(events) = &events {
+            events(&RecordingEvent::ended(&result));
+        }
+        result
+    }
+
+    async fn record(
+        &mut self,
+        template: &str,
+        segment: Segmentable,
+        events: Option<EventHandler>,
+        reconnect: Option<&Reconnect>,
+        guard: Option<&DiskGuard>,
+        postprocess: Option<&PostProcessor>,
+    ) -> downloader::error::Result<()> {
+        let fmt_file_name = self.render_file_name(template);
+        let client = self.client.clone();
+        let sidecar = Sidecar::new(self, reconnect.map(|reconnect| reconnect.url.as_str()));
+        let _watcher = reconnect.and_then(|reconnect| {
+            let interval = reconnect.watch_interval?;
+            Some(AbortOnDrop(tokio::spawn(reconnect.clone().watch(
+                self.clone(),
+                interval,
+                template.to_string(),
+                segment.split_handle(),
+                sidecar.clone(),
+            ))))
+        });
         self.client
             .headers
             .append(ACCEPT_ENCODING, HeaderValue::from_static("gzip, deflate"));
         info!("{}", self);
+        let connection = self.connect().await?;
+        sidecar.update(self);
+        let mut attempts = 0;
         match self.extension {
             Extension::Flv => {
-                let file = LifecycleFile::new(&fmt_file_name, "flv", hook);
-                let response = self.client.retryable(&self.direct_url).await?;
-                let mut connection = Connection::new(response);
-                connection.read_frame(9).await?;
-                httpflv::parse_flv(connection, file, segment).await?
+                let mut file = LifecycleFile::new(&fmt_file_name, "flv", events.clone());
+                file.guard = guard.cloned();
+                file.postprocess = postprocess.cloned();
+                file.filter = segment.filter().cloned();
+                file.sidecar = Some(sidecar.clone());
+                let mut connection = connection;
+                // FLV header and the first previous tag size.
+                connection.read_frame(9 + 4).await?;
+                let mut recorder = FlvRecorder::new(file, segment)?;
+                loop {
+                    let received = recorder.tags;
+                    let result = recorder.record(connection).await;
+                    let Some(reconnect) = reconnect else {
+                        return result;
+                    };
+                    if recorder.tags > received {
+                        attempts = 0;
+                    }
+                    let mut result = result;
+                    connection = loop {
+                        let Some(mut connection) = self
+                            .reconnect(&client, reconnect, &mut attempts, result, &events)
+                            .await?
+                        else {
+                            return Ok(());
+                        };
+                        sidecar.update(self);
+                        match connection.read_frame(9 + 4).await {
+                            Ok(_) => break connection,
+                            Err(e) => result = Err(e),
+                        }
+                    };
+                }
+            }
+            Extension::Ts | Extension::Fmp4 => {
+                let extension = match self.extension {
+                    Extension::Fmp4 => "mp4",
+                    _ => "ts",
+                };
+                let mut file = LifecycleFile::new(&fmt_file_name, extension, events.clone());
+                file.guard = guard.cloned();
+                file.postprocess = postprocess.cloned();
+                file.filter = segment.filter().cloned();
+                file.sidecar = Some(sidecar.clone());
+                let mut ts_file = TsFile::new(file)?;
+                let mut segment = segment;
+                // The playlist the connection was checked with.
+                let mut fetched = connection.bytes().await.ok();
+                loop {
+                    let received = ts_file.segments;
+                    let result = hls::download_to(
+                        &self.direct_url,
+                        fetched.take(),
+                        &self.client,
+                        &mut ts_file,
+                        &mut segment,
+                    )
+                    .await;
+                    let Some(reconnect) = reconnect else {
+                        return result;
+                    };
+                    if ts_file.segments > received {
+                        attempts = 0;
+                    }
+                    let Some(connection) = self
+                        .reconnect(&client, reconnect, &mut attempts, result, &events)
+                        .await?
+                    else {
+                        return Ok(());
+                    };
+                    fetched = connection.bytes().await.ok();
+                    sidecar.update(self);
+                }
+            }
+            Extension::Dash => {
+                let mut file = LifecycleFile::new(&fmt_file_name, "mp4", events.clone());
+                file.guard = guard.cloned();
+                file.postprocess = postprocess.cloned();
+                file.filter = segment.filter().cloned();
+                file.sidecar = Some(sidecar.clone());
+                let mut dash_file = DashFile::new(file)?;
+                let mut segment = segment;
+                // The manifest the connection was checked with.
+                let mut fetched = connection.bytes().await.ok();
+                loop {
+                    let received = dash_file.segments;
+                    let result = dash::download_to(
+                        &self.direct_url,
+                        fetched.take(),
+                        &self.client,
+                        &mut dash_file,
+                        &mut segment,
+                    )
+                    .await;
+                    let Some(reconnect) = reconnect else {
+                        return result;
+                    };
+                    if dash_file.segments > received {
+                        attempts = 0;
+                    }
+                    let Some(connection) = self
+                        .reconnect(&client, reconnect, &mut attempts, result, &events)
+                        .await?
+                    else {
+                        return Ok(());
+                    };
+                    fetched = connection.bytes().await.ok();
+                    sidecar.update(self);
+                }
+            }
+        }
+    }
+    /// Resolves the stream again after the connection ended with `result`, waiting
+    /// `reconnect.delay` before each attempt. Once `reconnect.retries` consecutive
+    /// attempts failed, `result` is returned instead, and `None` once the room is offline.
+    async fn reconnect(
+        &mut self,
+        client: &StatelessClient,
+        reconnect: &Reconnect,
+        attempts: &mut u32,
+        result: downloader::error::Result<()>,
+        events: &Option<EventHandler>,
+    ) -> downloader::error::Result<Option<Connection>> {
+        match &result {
+            Ok(()) => info!("{} stream ended: {}", self.name, self.direct_url),
+            // Reconnecting won't make room on the disk.
+            Err(downloader::error::Error::IOError(e))
+                if e.kind() == std::io::ErrorKind::StorageFull =>
+            {
+                return result.map(|_| None);
+            }
+            Err(e) => {
+                warn!("{} stream dropped: {e}", self.name);
+                if let Some(events) = events {
+                    events(&RecordingEvent::Error {
+                        message: format!("{} stream dropped: {e}", self.name),
+                    });
+                }
+            }
+        }
+        while *attempts < reconnect.retries {
+            *attempts += 1;
+            tokio::time::sleep(reconnect.delay).await;
+            // A room that went offline ends the recording, only drops are retried.
+            if let Ok(status) = reconnect
+                .extractor
+                .live_status_with_options(&reconnect.url, client.clone(), &reconnect.options)
+                .await
+                && !status.live
+            {
+                info!("{} went offline: {}", self.name, reconnect.url);
+                return Ok(None);
+            }
+            info!(
+                "Reconnecting {} ({}/{})",
+                reconnect.url, attempts, reconnect.retries
+            );
+            let site = match reconnect
+                .extractor
+                .get_site_with_options(&reconnect.url, client.clone(), &reconnect.options)
+                .await
+            {
+                Ok(site) => site,
+                Err(e) => {
+                    warn!("Unable to resolve {}: {e}", reconnect.url);
+                    continue;
+                }
+            };
+            *self = Site {
+                extension: self.extension,
+                ..site
+            };
+            self.client
+                .headers
+                .append(ACCEPT_ENCODING, HeaderValue::from_static("gzip, deflate"));
+            if let Ok(connection) = self.connect().await {
+                return Ok(Some(connection));
             }
-            Extension::Ts => {
-                let file = LifecycleFile::new(&fmt_file_name, "ts", hook);
-                hls::download(&self.direct_url, &self.client, file, segment).await?
+        }
+        result.map(|_| None)
+    }
+
+    /// Connects to `direct_url`, failing over to `fallback_urls` in order. The url
+    /// that worked becomes the new `direct_url`. `rtmp://` and `rtmps://` urls are
+    /// played over RTMP, everything else is requested over HTTP.
+    async fn connect(&mut self) -> downloader::error::Result<Connection> {
+        let mut urls = std::iter::once(self.direct_url.clone()).chain(self.fallback_urls.clone());
+        let mut last_err = None;
+        while let Some(url) = urls.next() {
+            let connection = if rtmp::is_rtmp(&url) {
+                rtmp::connect(&url).await.map(Connection::rtmp)
+            } else {
+                self.client
+                    .retryable(&url)
+                    .await
+                    .map(Connection::new)
+                    .map_err(Into::into)
+            };
+            match connection {
+                Ok(connection) => {
+                    self.direct_url = url;
+                    self.fallback_urls = urls.collect();
+                    return Ok(connection);
+                }
+                Err(e) => {
+                    warn!("Unable to connect to {url}: {e}");
+                    last_err = Some(e);
+                }
             }
         }
-        Ok(())
+        Err(last_err.expect("direct url is always tried"))
     }
 }
 
-pub fn find_extractor(url: &str) -> Option<&'static (dyn SiteDefinition + Send + Sync)> {
+/// Registers `extractor` under `name`, replacing and returning any extractor
+/// already registered with that name (including the built-in ones).
+pub fn register_extractor(
+    name: &str,
+    priority: i32,
+    extractor: impl SiteDefinition + Send + Sync + 'static,
+) -> Option<DynSiteDefinition> {
     EXTRACTORS
-        .into_iter()
-        .find(|&extractor| extractor.can_handle_url(url))
+        .write()
+        .unwrap()
+        .register(name, priority, Arc::new(extractor))
+}
+
+/// Removes the extractor registered under `name`.
+pub fn unregister_extractor(name: &str) -> Option<DynSiteDefinition> {
+    EXTRACTORS.write().unwrap().unregister(name)
+}
+
+/// Lists the registered extractors in the order they are consulted.
+pub fn extractors() -> Vec<ExtractorInfo> {
+    EXTRACTORS.read().unwrap().list()
+}
+
+/// Adds the cookies of the credential in `options` to a request for `url`.
+pub(crate) fn with_credential(
+    request: reqwest_middleware::RequestBuilder,
+    url: &str,
+    options: &StreamOptions,
+) -> reqwest_middleware::RequestBuilder {
+    match options.cookies_for(url) {
+        Some(cookies) => request.header(COOKIE, cookies),
+        None => request,
+    }
+}
+
+/// Reads a JSON string or number as text, sites are not consistent about ids.
+pub(crate) fn json_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Number(n) => n.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Converts a unix timestamp in seconds reported by a site to local time.
+pub(crate) fn local_time(secs: i64) -> Option<DateTime<Local>> {
+    (secs > 0)
+        .then(|| DateTime::from_timestamp(secs, 0))
+        .flatten()
+        .map(|t| t.with_timezone(&Local))
+}
+
+pub fn find_extractor(url: &str) -> Option<DynSiteDefinition> {
+    EXTRACTORS.read().unwrap().find(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Dummy(&'static str);
+
+    #[async_trait]
+    impl SiteDefinition for Dummy {
+        fn can_handle_url(&self, url: &str) -> bool {
+            url.contains(self.0)
+        }
+
+        async fn get_site(
+            &self,
+            url: &str,
+            _client: StatelessClient,
+        ) -> super::super::error::Result<Site> {
+            Err(super::super::error::Error::Custom(url.to_string()))
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn register_with_priority() {
+        let mut registry = Registry::builtin();
+        let url = "https://example.test/registry";
+        assert!(registry.find(url).is_none());
+
+        let low = Arc::new(Dummy("example.test"));
+        assert!(registry.register("test-low", -1, low).is_none());
+        let high = Arc::new(Dummy("example.test/registry"));
+        assert!(registry.register("test-high", 10, high).is_none());
+        let names: Vec<_> = registry.list().into_iter().map(|e| e.name).collect();
+        let high = names.iter().position(|n| n == "test-high").unwrap();
+        let low = names.iter().position(|n| n == "test-low").unwrap();
+        let builtin = names.iter().position(|n| n == "bilibili").unwrap();
+        assert!(high < builtin && builtin < low);
+
+        let found = registry.find(url).unwrap();
+        assert!(found.as_any().downcast_ref::<Dummy>().unwrap().0 == "example.test/registry");
+
+        let replaced = registry.register("test-high", 10, Arc::new(Dummy("nothing")));
+        assert!(replaced.is_some());
+        let found = registry.find(url).unwrap();
+        assert!(found.as_any().downcast_ref::<Dummy>().unwrap().0 == "example.test");
+
+        assert!(registry.unregister("test-high").is_some());
+        assert!(registry.unregister("test-low").is_some());
+        assert!(registry.unregister("test-low").is_none());
+        assert!(registry.find(url).is_none());
+
+        let direct = registry.find("https://example.test/live.flv").unwrap();
+        assert!(direct.as_any().is::<direct::DirectUrl>());
+    }
+
+    #[test]
+    fn credential_domains() {
+        let options = StreamOptions {
+            credential: Some(Credential {
+                domains: vec![".bilibili.com".to_string()],
+                cookies: "SESSDATA=1".to_string(),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.cookies_for("https://api.live.bilibili.com/xlive"),
+            Some("SESSDATA=1")
+        );
+        assert_eq!(
+            options.cookies_for("https://bilibili.com/"),
+            Some("SESSDATA=1")
+        );
+        assert_eq!(options.cookies_for("https://notbilibili.com/"), None);
+        assert_eq!(options.cookies_for("https://www.douyu.com/"), None);
+    }
+
+    #[test]
+    fn header_cookies() {
+        let options = StreamOptions {
+            headers: vec![
+                "Referer: https://live.kuaishou.com/".to_string(),
+                "cookie: did=web_1; userId=2".to_string(),
+            ],
+            credential: Some(Credential {
+                domains: vec![".bilibili.com".to_string()],
+                cookies: "SESSDATA=1".to_string(),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.cookies_for("https://live.kuaishou.com/"),
+            Some("did=web_1; userId=2")
+        );
+        assert_eq!(
+            options.cookies_for("https://api.live.bilibili.com/"),
+            Some("SESSDATA=1")
+        );
+    }
+
+    #[test]
+    fn render_metadata() {
+        let site = Site {
+            name: "test",
+            title: "title".to_string(),
+            direct_url: "".to_string(),
+            fallback_urls: Vec::new(),
+            streams: Vec::new(),
+            streamer: "streamer".to_string(),
+            room_id: "1".to_string(),
+            uid: "2".to_string(),
+            area: "area".to_string(),
+            cover: "".to_string(),
+            live_start_time: None,
+            extension: Extension::Flv,
+            client: Default::default(),
+        };
+        assert_eq!(
+            site.render("./{streamer}/{room_id}-{uid}/{area}_{title}{live_start_time}"),
+            "./streamer/1-2/area_title"
+        );
+        let site = Site {
+            title: "50% off: a/b {seq}".to_string(),
+            ..site
+        };
+        assert_eq!(
+            site.render_file_name("%Y/{platform}_{title}_{seq}"),
+            "%Y/test_50%% off_ a_b {{seq}}_{seq}"
+        );
+    }
 }
@@ -1,12 +1,56 @@
 use crate::client::StatelessClient;
 use crate::downloader::error::{Error, Result};
-use crate::downloader::extractor::{Extension, Site, SiteDefinition};
+use crate::downloader::extractor::{
+    Codec, Extension, LiveStatus, Protocol, Site, SiteDefinition, StreamOptions, StreamVariant,
+    json_text, local_time, with_credential,
+};
 use async_trait::async_trait;
 use reqwest::header::{HeaderMap, HeaderValue, REFERER};
 use serde_json::Value;
 use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Mutex;
 
-pub struct BiliLive {}
+#[derive(Default)]
+pub struct BiliLive {
+    /// Uids of the streamers by room id as found in urls, the batched status API
+    /// only takes uids.
+    uids: Mutex<HashMap<u32, u64>>,
+}
+
+impl BiliLive {
+    async fn uid(&self, url: &str, client: &StatelessClient) -> Result<u64> {
+        let rid = room_id(url)?;
+        if let Some(uid) = self.uids.lock().unwrap().get(&rid) {
+            return Ok(*uid);
+        }
+        let info = self.room_info(rid, client).await?;
+        info["uid"]
+            .as_u64()
+            .ok_or_else(|| Error::Custom(format!("No uid: {info}")))
+    }
+
+    /// Basic room info, much cheaper than `getInfoByRoom`.
+    async fn room_info(&self, rid: u32, client: &StatelessClient) -> Result<Value> {
+        let mut info: Value = client
+            .http
+            .get(format!(
+                "https://api.live.bilibili.com/room/v1/Room/get_info?room_id={rid}"
+            ))
+            .send()
+            .await?
+            .json()
+            .await?;
+        if info["code"] != 0 {
+            return Err(Error::Custom(format!("{}", info["message"])));
+        }
+        let info = info["data"].take();
+        if let Some(uid) = info["uid"].as_u64() {
+            self.uids.lock().unwrap().insert(rid, uid);
+        }
+        Ok(info)
+    }
+}
 
 #[async_trait]
 impl SiteDefinition for BiliLive {
@@ -16,18 +60,22 @@ impl SiteDefinition for BiliLive {
             .is_match(url)
     }
 
-    async fn get_site(&self, url: &str, mut client: StatelessClient) -> Result<Site> {
-        let rid: u32 = match regex::Regex::new(r"/(\d+)").unwrap().captures(url) {
-            Some(captures) => captures[1].parse().unwrap(),
-            _ => {
-                return Err(Error::Custom(format!("Wrong url: {url}")));
-            }
-        };
-        let mut room_info: Value = client
-            .client
-            .get(format!(
-                "https://api.live.bilibili.com/xlive/web-room/v1/index/getInfoByRoom?room_id={rid}"
-            ))
+    async fn get_site(&self, url: &str, client: StatelessClient) -> Result<Site> {
+        self.get_site_with_options(url, client, &StreamOptions::default())
+            .await
+    }
+
+    async fn get_site_with_options(
+        &self,
+        url: &str,
+        mut client: StatelessClient,
+        options: &StreamOptions,
+    ) -> Result<Site> {
+        let rid = room_id(url)?;
+        let info_url = format!(
+            "https://api.live.bilibili.com/xlive/web-room/v1/index/getInfoByRoom?room_id={rid}"
+        );
+        let mut room_info: Value = with_credential(client.http.get(&info_url), &info_url, options)
             .send()
             .await?
             .json()
@@ -43,47 +91,25 @@ impl SiteDefinition for BiliLive {
             return Err(Error::Custom(format!("Not online: {url}")));
         }
 
-        let params = [
-            ("room_id", &*vid.to_string()),
-            ("qn", "10000"),
-            ("platform", "web"),
-            ("codec", "0,1"),
-            ("protocol", "0,1"),
-            ("format", "0,1,2"),
-            ("ptype", "8"),
-            ("dolby", "5"),
-        ];
-        let room_play_info: Value = client
-            .client
-            .get("https://api.live.bilibili.com/xlive/web-room/v2/index/getRoomPlayInfo")
-            .query(&params)
-            .send()
-            .await?
-            .json()
-            .await?;
-
-        if room_play_info["code"] != 0 {
-            return Err(Error::Custom(room_play_info["msg"].to_string()));
+        let mut room_play_info = room_play_info(&client, &vid, target_qn(options), options).await?;
+        if let Some(qn) = fallback_qn(&room_play_info, options)? {
+            room_play_info = self::room_play_info(&client, &vid, qn, options).await?;
         }
-        let direct_url = room_play_info["data"]["playurl_info"]["playurl"]["stream"]
+        let selected = select_codec(&room_play_info, options)?;
+        let direct_url = selected.codec["url_info"]
             .as_array()
-            .and_then(|v| {
-                v.iter()
-                    .filter_map(|v| v["format"].as_array())
-                    .flatten()
-                    .find(|v| v["format_name"] == "flv")
-            })
-            .and_then(|v| {
-                let url_info = v["codec"][0]["url_info"]
-                    .as_array()
-                    .and_then(|info| {
-                        info.iter()
-                            .find(|i| !i["host"].to_string().contains(".mcdn."))
+            .and_then(|info| {
+                info.iter()
+                    .filter(|i| options.cdn_allowed(i["host"].as_str().unwrap_or_default()))
+                    .min_by_key(|i| {
+                        let host = i["host"].as_str().unwrap_or_default();
+                        (options.cdn_rank(host), host.contains(".mcdn."))
                     })
-                    .unwrap_or(&v["codec"][0]["url_info"][0]);
+            })
+            .and_then(|url_info| {
                 if let (Some(host), Some(base_url), Some(extra)) = (
                     url_info["host"].as_str(),
-                    v["codec"][0]["base_url"].as_str(),
+                    selected.codec["base_url"].as_str(),
                     url_info["extra"].as_str(),
                 ) {
                     Some(format!("{host}{base_url}{extra}"))
@@ -92,6 +118,11 @@ impl SiteDefinition for BiliLive {
                 }
             })
             .ok_or_else(|| Error::Custom(format!("{room_play_info}")))?;
+        let extension = match selected.format_name {
+            "ts" => Extension::Ts,
+            "fmp4" => Extension::Fmp4,
+            _ => Extension::Flv,
+        };
         let mut header_map = HeaderMap::new();
         header_map.insert(
             REFERER,
@@ -101,19 +132,383 @@ impl SiteDefinition for BiliLive {
             REFERER,
             HeaderValue::from_static("https://live.bilibili.com"),
         );
+        let room = &room_info["data"]["room_info"];
         return Ok(Site {
             name: "bilibili",
-            title: room_info["data"]["room_info"]["title"]
+            title: room["title"].as_str().unwrap().to_string(),
+            direct_url,
+            fallback_urls: Vec::new(),
+            streams: streams(&room_play_info),
+            streamer: room_info["data"]["anchor_info"]["base_info"]["uname"]
                 .as_str()
-                .unwrap()
+                .unwrap_or_default()
                 .to_string(),
-            direct_url,
-            extension: Extension::Flv,
+            room_id: json_text(&vid),
+            uid: json_text(&room["uid"]),
+            area: room["area_name"].as_str().unwrap_or_default().to_string(),
+            cover: room["cover"].as_str().unwrap_or_default().to_string(),
+            live_start_time: room["live_start_time"].as_i64().and_then(local_time),
+            extension,
             client,
         });
     }
 
+    async fn live_status(&self, url: &str, client: StatelessClient) -> Result<LiveStatus> {
+        let info = self.room_info(room_id(url)?, &client).await?;
+        Ok(LiveStatus {
+            live: info["live_status"] == 1,
+            title: info["title"].as_str().unwrap_or_default().to_string(),
+            area: info["area_name"].as_str().unwrap_or_default().to_string(),
+            cover: info["user_cover"].as_str().unwrap_or_default().to_string(),
+        })
+    }
+
+    async fn live_statuses(
+        &self,
+        urls: &[String],
+        client: StatelessClient,
+    ) -> Vec<Result<LiveStatus>> {
+        let mut uids = Vec::with_capacity(urls.len());
+        for url in urls {
+            uids.push(self.uid(url, &client).await);
+        }
+        let known: Vec<u64> = uids
+            .iter()
+            .filter_map(|uid| uid.as_ref().ok().copied())
+            .collect();
+        match status_info_by_uids(&client, &known).await {
+            Ok(data) => statuses(uids, &data),
+            Err(e) => {
+                let message = e.to_string();
+                uids.iter()
+                    .map(|_| Err(Error::Custom(message.clone())))
+                    .collect()
+            }
+        }
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
 }
+
+fn room_id(url: &str) -> Result<u32> {
+    regex::Regex::new(r"/(\d+)")
+        .unwrap()
+        .captures(url)
+        .and_then(|captures| captures[1].parse().ok())
+        .ok_or_else(|| Error::Custom(format!("Wrong url: {url}")))
+}
+
+/// Live status of many streamers in one request, keyed by uid.
+async fn status_info_by_uids(client: &StatelessClient, uids: &[u64]) -> Result<Value> {
+    let mut result: Value = client
+        .http
+        .post("https://api.live.bilibili.com/room/v1/Room/get_status_info_by_uids")
+        .json(&serde_json::json!({ "uids": uids }))
+        .send()
+        .await?
+        .json()
+        .await?;
+    if result["code"] != 0 {
+        return Err(Error::Custom(format!("{}", result["message"])));
+    }
+    Ok(result["data"].take())
+}
+
+/// Looks up each uid in `get_status_info_by_uids` data, streamers missing there are offline.
+fn statuses(uids: Vec<Result<u64>>, data: &Value) -> Vec<Result<LiveStatus>> {
+    uids.into_iter()
+        .map(|uid| {
+            let info = &data[uid?.to_string()];
+            Ok(LiveStatus {
+                live: info["live_status"] == 1,
+                title: info["title"].as_str().unwrap_or_default().to_string(),
+                area: info["area_v2_name"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                cover: info["cover_from_user"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Every quality of every format and codec in `getRoomPlayInfo`. The hosts are the ones
+/// serving the current quality, other qualities usually share them.
+fn streams(room_play_info: &Value) -> Vec<StreamVariant> {
+    let playurl = &room_play_info["data"]["playurl_info"]["playurl"];
+    let name = |qn: u64| {
+        playurl["g_qn_desc"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|desc| desc["qn"] == qn)
+            .and_then(|desc| desc["desc"].as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let mut streams = Vec::new();
+    for stream in playurl["stream"].as_array().into_iter().flatten() {
+        for format in stream["format"].as_array().into_iter().flatten() {
+            let protocol = match (
+                stream["protocol_name"].as_str(),
+                format["format_name"].as_str(),
+            ) {
+                (Some("http_stream"), Some("flv")) => Some(Protocol::HttpFlv),
+                (Some("http_hls"), Some("ts")) => Some(Protocol::HlsTs),
+                (Some("http_hls"), Some("fmp4")) => Some(Protocol::HlsFmp4),
+                _ => None,
+            };
+            for codec in format["codec"].as_array().into_iter().flatten() {
+                let cdns: Vec<String> = codec["url_info"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|i| i["host"].as_str().map(String::from))
+                    .collect();
+                for qn in codec["accept_qn"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_u64)
+                {
+                    streams.push(StreamVariant {
+                        name: name(qn),
+                        quality: Some(qn as u32),
+                        codec: codec["codec_name"].as_str().and_then(|c| c.parse().ok()),
+                        protocol,
+                        cdns: cdns.clone(),
+                    });
+                }
+            }
+        }
+    }
+    streams
+}
+
+fn target_qn(options: &StreamOptions) -> u32 {
+    options.quality.unwrap_or(10000)
+}
+
+async fn room_play_info(
+    client: &StatelessClient,
+    room_id: &Value,
+    qn: u32,
+    options: &StreamOptions,
+) -> Result<Value> {
+    let params = [
+        ("room_id", &*room_id.to_string()),
+        ("qn", &*qn.to_string()),
+        ("platform", "web"),
+        ("codec", "0,1"),
+        ("protocol", "0,1"),
+        ("format", "0,1,2"),
+        ("ptype", "8"),
+        ("dolby", "5"),
+    ];
+    let url = "https://api.live.bilibili.com/xlive/web-room/v2/index/getRoomPlayInfo";
+    let room_play_info: Value = with_credential(client.http.get(url), url, options)
+        .query(&params)
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    if room_play_info["code"] != 0 {
+        return Err(Error::Custom(room_play_info["msg"].to_string()));
+    }
+    Ok(room_play_info)
+}
+
+/// The quality to request again if the served one is not the one wanted:
+/// the target if listed in accept_qn, otherwise the best one listed.
+fn fallback_qn(room_play_info: &Value, options: &StreamOptions) -> Result<Option<u32>> {
+    let codec = select_codec(room_play_info, options)?.codec;
+    let target = target_qn(options);
+    let accept_qn: Vec<u32> = codec["accept_qn"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|qn| qn.as_u64().map(|qn| qn as u32))
+        .collect();
+    let qn = if accept_qn.contains(&target) {
+        target
+    } else {
+        match accept_qn.into_iter().max() {
+            Some(qn) => qn,
+            None => return Ok(None),
+        }
+    };
+    Ok((codec["current_qn"].as_u64() != Some(u64::from(qn))).then_some(qn))
+}
+
+struct SelectedCodec<'a> {
+    format_name: &'a str,
+    codec: &'a Value,
+}
+
+/// Picks the stream format and codec closest to `options`, preferring http_flv and avc.
+fn select_codec<'a>(
+    room_play_info: &'a Value,
+    options: &StreamOptions,
+) -> Result<SelectedCodec<'a>> {
+    let formats: Vec<(&str, &Value)> = room_play_info["data"]["playurl_info"]["playurl"]["stream"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|stream| {
+            let protocol_name = stream["protocol_name"].as_str()?;
+            Some(
+                stream["format"]
+                    .as_array()?
+                    .iter()
+                    .map(move |f| (protocol_name, f)),
+            )
+        })
+        .flatten()
+        .collect();
+    let preferred = match options.protocol {
+        Some(Protocol::HlsTs) => ("http_hls", "ts"),
+        Some(Protocol::HlsFmp4) => ("http_hls", "fmp4"),
+        Some(Protocol::HttpFlv) | None => ("http_stream", "flv"),
+    };
+    let format = [
+        preferred,
+        ("http_stream", "flv"),
+        ("http_hls", "ts"),
+        ("http_hls", "fmp4"),
+    ]
+    .into_iter()
+    .find_map(|(protocol_name, format_name)| {
+        formats
+            .iter()
+            .find(|(p, f)| *p == protocol_name && f["format_name"] == format_name)
+    })
+    .map(|(_, format)| *format)
+    .ok_or_else(|| Error::Custom(format!("{room_play_info}")))?;
+    let codecs = format["codec"]
+        .as_array()
+        .filter(|codecs| !codecs.is_empty())
+        .ok_or_else(|| Error::Custom(format!("{room_play_info}")))?;
+    let codec_name = options.codec.unwrap_or(Codec::Avc).as_str();
+    Ok(SelectedCodec {
+        format_name: format["format_name"].as_str().unwrap_or_default(),
+        codec: codecs
+            .iter()
+            .find(|c| c["codec_name"] == codec_name)
+            .unwrap_or(&codecs[0]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::cassette::Cassette;
+    use serde_json::json;
+
+    #[test]
+    fn batched_statuses() {
+        let data = json!({
+            "1": {"live_status": 1, "title": "live", "cover_from_user": "cover"},
+            "2": {"live_status": 2, "title": "round", "cover_from_user": ""},
+        });
+        let statuses = statuses(
+            vec![
+                Ok(1),
+                Ok(2),
+                Ok(3),
+                Err(Error::Custom("Wrong url".to_string())),
+            ],
+            &data,
+        );
+        let live: Vec<_> = statuses
+            .iter()
+            .map(|s| s.as_ref().map(|s| s.live).ok())
+            .collect();
+        assert_eq!(live, [Some(true), Some(false), Some(false), None]);
+        assert_eq!(statuses[0].as_ref().unwrap().title, "live");
+        assert_eq!(statuses[0].as_ref().unwrap().cover, "cover");
+        assert_eq!(
+            room_id("https://live.bilibili.com/21452505?x=1").ok(),
+            Some(21452505)
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_room() -> Result<()> {
+        let cassette = Cassette::replay(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/bilibili_live.json"
+        ))?;
+        let client =
+            StatelessClient::with_cassette(HeaderMap::new(), None, Some(cassette.into())).unwrap();
+        let site = BiliLive::default()
+            .get_site("https://live.bilibili.com/21452505", client)
+            .await?;
+        assert_eq!(site.title, "测试直播");
+        assert_eq!(site.streamer, "主播");
+        assert_eq!(site.extension, Extension::Flv);
+        assert_eq!(
+            site.direct_url,
+            "https://d1--cn-gotcha01.bilivideo.com/live-bvc/live_21452505.flv?expires=1700003600"
+        );
+        let names: Vec<_> = site.streams.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["原画", "蓝光"]);
+        assert_eq!(site.streams[1].quality, Some(400));
+        assert_eq!(site.streams[1].codec, Some(Codec::Avc));
+        assert_eq!(
+            site.streams[1].cdns,
+            ["https://d1--cn-gotcha01.bilivideo.com"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn select_stream() {
+        let codec = |name: &str| {
+            json!({
+                "codec_name": name,
+                "current_qn": 250,
+                "accept_qn": [250, 150],
+                "base_url": "/live.flv?",
+                "url_info": [
+                    {"host": "https://a.mcdn.bilivideo.cn", "extra": "a"},
+                    {"host": "https://b.bilivideo.com", "extra": "b"},
+                ],
+            })
+        };
+        let info = json!({"data": {"playurl_info": {"playurl": {"stream": [
+            {"protocol_name": "http_stream", "format": [
+                {"format_name": "flv", "codec": [codec("avc")]},
+            ]},
+            {"protocol_name": "http_hls", "format": [
+                {"format_name": "fmp4", "codec": [codec("avc"), codec("hevc")]},
+            ]},
+        ]}}}});
+
+        let selected = select_codec(&info, &StreamOptions::default()).unwrap();
+        assert_eq!(selected.format_name, "flv");
+        assert_eq!(fallback_qn(&info, &StreamOptions::default()).unwrap(), None);
+
+        let options = StreamOptions {
+            quality: Some(150),
+            codec: Some(Codec::Hevc),
+            protocol: Some(Protocol::HlsFmp4),
+            ..Default::default()
+        };
+        let selected = select_codec(&info, &options).unwrap();
+        assert_eq!(selected.format_name, "fmp4");
+        assert_eq!(selected.codec["codec_name"], "hevc");
+        assert_eq!(fallback_qn(&info, &options).unwrap(), Some(150));
+
+        let options = StreamOptions {
+            protocol: Some(Protocol::HlsTs),
+            ..Default::default()
+        };
+        assert_eq!(select_codec(&info, &options).unwrap().format_name, "flv");
+    }
+}
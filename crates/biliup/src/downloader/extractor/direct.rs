@@ -0,0 +1,192 @@
+use crate::client::StatelessClient;
+use crate::downloader::error::{Error, Result};
+use crate::downloader::extractor::{Extension, LiveStatus, Site, SiteDefinition, StreamOptions};
+use crate::downloader::rtmp;
+use async_trait::async_trait;
+use reqwest::header::{CONTENT_TYPE, COOKIE, HeaderName, HeaderValue};
+use std::any::Any;
+use url::Url;
+
+/// Plain stream urls that no platform extractor claims, the format is told by the
+/// content and the url's extension.
+pub struct DirectUrl;
+
+#[async_trait]
+impl SiteDefinition for DirectUrl {
+    fn can_handle_url(&self, url: &str) -> bool {
+        let Ok(url) = Url::parse(url) else {
+            return false;
+        };
+        match url.scheme() {
+            "rtmp" | "rtmps" => true,
+            "http" | "https" => [".flv", ".m3u8", ".mpd"]
+                .iter()
+                .any(|ext| url.path().ends_with(ext)),
+            _ => false,
+        }
+    }
+
+    async fn get_site(&self, url: &str, client: StatelessClient) -> Result<Site> {
+        self.get_site_with_options(url, client, &StreamOptions::default())
+            .await
+    }
+
+    async fn get_site_with_options(
+        &self,
+        url: &str,
+        mut client: StatelessClient,
+        options: &StreamOptions,
+    ) -> Result<Site> {
+        let parsed = Url::parse(url)?;
+        for header in &options.headers {
+            let (name, value) = header
+                .split_once(':')
+                .ok_or_else(|| Error::Custom(format!("Invalid header: {header}")))?;
+            client.headers.insert(
+                HeaderName::from_bytes(name.trim().as_bytes())
+                    .map_err(|e| Error::Custom(format!("Invalid header {header}: {e}")))?,
+                HeaderValue::from_str(value.trim())
+                    .map_err(|e| Error::Custom(format!("Invalid header {header}: {e}")))?,
+            );
+        }
+        if let Some(cookies) = options.cookies_for(url) {
+            client.headers.insert(
+                COOKIE,
+                HeaderValue::from_str(cookies).map_err(|e| Error::Custom(e.to_string()))?,
+            );
+        }
+
+        let extension = if rtmp::is_rtmp(url) {
+            // Played as FLV, checked by starting to play it.
+            rtmp::connect(url).await?;
+            Extension::Flv
+        } else {
+            let mut response = client.retryable(url).await?;
+            let content_type = response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+            // The start of the body is enough to tell the formats apart.
+            let prefix = response.chunk().await?.unwrap_or_default();
+            sniff(parsed.path(), &content_type, &prefix)?
+        };
+
+        let title = parsed
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .and_then(|name| name.rsplit_once('.').map(|(stem, _)| stem))
+            .unwrap_or_default()
+            .to_string();
+        Ok(Site {
+            name: "direct",
+            title,
+            direct_url: url.to_string(),
+            fallback_urls: Vec::new(),
+            streams: Vec::new(),
+            streamer: parsed.host_str().unwrap_or_default().to_string(),
+            room_id: String::new(),
+            uid: String::new(),
+            area: String::new(),
+            cover: String::new(),
+            live_start_time: None,
+            extension,
+            client,
+        })
+    }
+
+    /// Probes the stream like [`SiteDefinition::get_site_with_options`], with the headers
+    /// and cookies a protected url needs.
+    async fn live_status_with_options(
+        &self,
+        url: &str,
+        client: StatelessClient,
+        options: &StreamOptions,
+    ) -> Result<LiveStatus> {
+        let site = self.get_site_with_options(url, client, options).await?;
+        Ok(LiveStatus {
+            live: true,
+            title: site.title,
+            area: site.area,
+            cover: site.cover,
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Tells the stream format from the first bytes of the response, falling back to the
+/// content type and then the extension of `path`.
+fn sniff(path: &str, content_type: &str, prefix: &[u8]) -> Result<Extension> {
+    if prefix.starts_with(b"FLV") {
+        return Ok(Extension::Flv);
+    }
+    if prefix.starts_with(b"#EXTM3U") {
+        return Ok(hls_extension(prefix));
+    }
+    if prefix.windows(4).any(|w| w == b"<MPD") {
+        return Ok(Extension::Dash);
+    }
+    let content_type = content_type.to_ascii_lowercase();
+    if content_type.contains("dash+xml") || path.ends_with(".mpd") {
+        return Ok(Extension::Dash);
+    }
+    if content_type.contains("mpegurl") || path.ends_with(".m3u8") {
+        return Ok(hls_extension(prefix));
+    }
+    if content_type.contains("flv") || path.ends_with(".flv") {
+        return Ok(Extension::Flv);
+    }
+    Err(Error::Custom(format!("Unknown stream format: {path}")))
+}
+
+/// fMP4 media playlists announce their initialization section with `#EXT-X-MAP`.
+fn hls_extension(playlist: &[u8]) -> Extension {
+    if playlist.windows(11).any(|w| w == b"#EXT-X-MAP:") {
+        Extension::Fmp4
+    } else {
+        Extension::Ts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniff_formats() {
+        let direct = DirectUrl;
+        assert!(direct.can_handle_url("https://example.com/live/room.flv?token=1"));
+        assert!(direct.can_handle_url("http://example.com/index.m3u8"));
+        assert!(direct.can_handle_url("rtmp://example.com/live/room"));
+        assert!(!direct.can_handle_url("https://example.com/room/1"));
+
+        assert_eq!(
+            sniff("/live", "", b"FLV\x01\x05").ok(),
+            Some(Extension::Flv)
+        );
+        assert_eq!(
+            sniff("/live", "", b"#EXTM3U\n#EXT-X-MAP:URI=\"init.mp4\"").ok(),
+            Some(Extension::Fmp4)
+        );
+        assert_eq!(
+            sniff("/live", "application/vnd.apple.mpegurl", b"").ok(),
+            Some(Extension::Ts)
+        );
+        assert_eq!(sniff("/live.flv", "", b"").ok(), Some(Extension::Flv));
+        assert_eq!(
+            sniff(
+                "/live",
+                "",
+                b"<?xml version=\"1.0\"?>\n<MPD type=\"dynamic\">"
+            )
+            .ok(),
+            Some(Extension::Dash)
+        );
+        assert_eq!(sniff("/live.mpd", "", b"<?xml").ok(), Some(Extension::Dash));
+        assert!(sniff("/live", "text/html", b"<html>").is_err());
+    }
+}
@@ -0,0 +1,440 @@
+use crate::client::StatelessClient;
+use crate::downloader::error::{Error, Result};
+use crate::downloader::extractor::{
+    BROWSER_UA, Extension, LiveStatus, Protocol, Site, SiteDefinition, StreamOptions,
+    StreamVariant, json_text, local_time,
+};
+use async_trait::async_trait;
+use reqwest::Response;
+use reqwest::header::{COOKIE, HeaderValue, REFERER, SET_COOKIE, USER_AGENT};
+use serde_json::Value;
+use std::any::Any;
+
+const LIVE_URL: &str = "https://live.douyin.com/";
+
+/// Pull url keys of `flv_pull_url` and `hls_pull_url_map`, best first.
+const PULL_URL_KEYS: [&str; 4] = ["FULL_HD1", "HD1", "SD1", "SD2"];
+
+pub struct DouyinLive;
+
+#[async_trait]
+impl SiteDefinition for DouyinLive {
+    fn can_handle_url(&self, url: &str) -> bool {
+        regex::Regex::new(r"(?:https?://)?(?:live|v)\.douyin\.com")
+            .unwrap()
+            .is_match(url)
+    }
+
+    async fn get_site(&self, url: &str, client: StatelessClient) -> Result<Site> {
+        self.get_site_with_options(url, client, &StreamOptions::default())
+            .await
+    }
+
+    async fn get_site_with_options(
+        &self,
+        url: &str,
+        mut client: StatelessClient,
+        options: &StreamOptions,
+    ) -> Result<Site> {
+        let (web_rid, mut enter) = enter(url, &mut client, options).await?;
+        let room = enter["data"]["data"][0].take();
+        // 2: 直播中, 4: 未开播
+        if room["status"] != 2 {
+            return Err(Error::Custom(format!("Not online: {url}")));
+        }
+        let (direct_url, extension) = select_stream(&room["stream_url"], options)
+            .ok_or_else(|| Error::Custom(format!("No stream url: {}", room["stream_url"])))?;
+
+        let user = &enter["data"]["user"];
+        Ok(Site {
+            name: "douyin",
+            title: room["title"].as_str().unwrap_or_default().to_string(),
+            direct_url,
+            fallback_urls: Vec::new(),
+            streams: streams(&room["stream_url"]),
+            streamer: room["owner"]["nickname"]
+                .as_str()
+                .or_else(|| user["nickname"].as_str())
+                .unwrap_or_default()
+                .to_string(),
+            room_id: web_rid,
+            uid: json_text(&room["owner"]["id_str"]),
+            area: enter["data"]["partition_road_map"]["partition"]["title"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            cover: room["cover"]["url_list"][0]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            live_start_time: room["create_time"].as_i64().and_then(local_time),
+            extension,
+            client,
+        })
+    }
+
+    async fn live_status(&self, url: &str, client: StatelessClient) -> Result<LiveStatus> {
+        self.live_status_with_options(url, client, &StreamOptions::default())
+            .await
+    }
+
+    async fn live_status_with_options(
+        &self,
+        url: &str,
+        mut client: StatelessClient,
+        options: &StreamOptions,
+    ) -> Result<LiveStatus> {
+        let (_, enter) = enter(url, &mut client, options).await?;
+        let room = &enter["data"]["data"][0];
+        Ok(LiveStatus {
+            live: room["status"] == 2,
+            title: room["title"].as_str().unwrap_or_default().to_string(),
+            area: enter["data"]["partition_road_map"]["partition"]["title"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            cover: room["cover"]["url_list"][0]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// The `web_rid` of the room at `url` and its `room/web/enter` info, with the headers
+/// and cookies the api needs set on `client`.
+async fn enter(
+    url: &str,
+    client: &mut StatelessClient,
+    options: &StreamOptions,
+) -> Result<(String, Value)> {
+    client
+        .headers
+        .insert(USER_AGENT, HeaderValue::from_static(BROWSER_UA));
+    client
+        .headers
+        .insert(REFERER, HeaderValue::from_static(LIVE_URL));
+    let web_rid = web_rid(url, client).await?;
+    let ttwid = ttwid(client).await?;
+    let cookies = match options.cookies_for(LIVE_URL) {
+        Some(cookies) => format!("ttwid={ttwid}; {cookies}"),
+        None => format!("ttwid={ttwid}"),
+    };
+    client.headers.insert(
+        COOKIE,
+        HeaderValue::from_str(&cookies).map_err(|e| Error::Custom(e.to_string()))?,
+    );
+
+    let enter: Value = client
+        .http
+        .get("https://live.douyin.com/webcast/room/web/enter/")
+        .headers(client.headers.clone())
+        .query(&[
+            ("aid", "6383"),
+            ("app_name", "douyin_web"),
+            ("live_id", "1"),
+            ("device_platform", "web"),
+            ("language", "zh-CN"),
+            ("browser_language", "zh-CN"),
+            ("browser_platform", "Win32"),
+            ("browser_name", "Chrome"),
+            ("browser_version", "116.0.0.0"),
+            ("web_rid", &web_rid),
+        ])
+        .send()
+        .await?
+        .json()
+        .await?;
+    if enter["status_code"] != 0 {
+        return Err(Error::Custom(format!("{}", enter["data"]["prompts"])));
+    }
+    Ok((web_rid, enter))
+}
+
+/// Resolves the `web_rid` shown in `live.douyin.com/<web_rid>`, following share links.
+async fn web_rid(url: &str, client: &StatelessClient) -> Result<String> {
+    let live = regex::Regex::new(r"live\.douyin\.com/(\d+)").unwrap();
+    if let Some(captures) = live.captures(url) {
+        return Ok(captures[1].to_string());
+    }
+    // v.douyin.com 分享链接重定向至 live.douyin.com 或 webcast.amemv.com/douyin/webcast/reflow/<room_id>
+    let response = client
+        .http
+        .get(url)
+        .headers(client.headers.clone())
+        .send()
+        .await?;
+    let location = response.url().as_str();
+    if let Some(captures) = live.captures(location) {
+        return Ok(captures[1].to_string());
+    }
+    let room_id = match regex::Regex::new(r"/reflow/(\d+)")
+        .unwrap()
+        .captures(location)
+    {
+        Some(captures) => captures[1].to_string(),
+        None => return Err(Error::Custom(format!("Wrong url: {url}"))),
+    };
+    let sec_user_id = response
+        .url()
+        .query_pairs()
+        .find(|(k, _)| k == "sec_user_id")
+        .map(|(_, v)| v.into_owned())
+        .unwrap_or_default();
+    let reflow: Value = client
+        .http
+        .get("https://webcast.amemv.com/webcast/room/reflow/info/")
+        .headers(client.headers.clone())
+        .query(&[
+            ("type_id", "0"),
+            ("live_id", "1"),
+            ("app_id", "1128"),
+            ("room_id", &room_id),
+            ("sec_user_id", &sec_user_id),
+        ])
+        .send()
+        .await?
+        .json()
+        .await?;
+    reflow["data"]["room"]["owner"]["web_rid"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| Error::Custom(format!("Wrong url: {url}")))
+}
+
+/// The room api requires a `ttwid` cookie, which live.douyin.com only hands out
+/// once the `__ac_nonce` challenge cookie is sent back.
+async fn ttwid(client: &StatelessClient) -> Result<String> {
+    let response = client
+        .http
+        .get(LIVE_URL)
+        .headers(client.headers.clone())
+        .send()
+        .await?;
+    if let Some(ttwid) = set_cookie(&response, "ttwid") {
+        return Ok(ttwid);
+    }
+    let nonce = set_cookie(&response, "__ac_nonce")
+        .ok_or_else(|| Error::Custom("Missing __ac_nonce cookie".to_string()))?;
+    let response = client
+        .http
+        .get(LIVE_URL)
+        .headers(client.headers.clone())
+        .header(COOKIE, format!("__ac_nonce={nonce}"))
+        .send()
+        .await?;
+    set_cookie(&response, "ttwid").ok_or_else(|| Error::Custom("Missing ttwid cookie".to_string()))
+}
+
+fn set_cookie(response: &Response, name: &str) -> Option<String> {
+    response
+        .headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .filter_map(|v| cookie::Cookie::parse(v).ok())
+        .find(|c| c.name() == name)
+        .map(|c| c.value().to_string())
+}
+
+/// Picks the pull url from `stream_url`. The quality is matched against the `level`
+/// of the room's qualities, the highest one is used when it isn't available.
+fn select_stream(stream_url: &Value, options: &StreamOptions) -> Option<(String, Extension)> {
+    let hls = matches!(
+        options.protocol,
+        Some(Protocol::HlsTs) | Some(Protocol::HlsFmp4)
+    );
+    let extension = if hls { Extension::Ts } else { Extension::Flv };
+
+    let pull_data = &stream_url["live_core_sdk_data"]["pull_data"];
+    let stream_data: Option<Value> = pull_data["stream_data"]
+        .as_str()
+        .and_then(|s| serde_json::from_str(s).ok());
+    if let Some(stream_data) = stream_data {
+        let available: Vec<&Value> = pull_data["options"]["qualities"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|q| stream_data["data"][q["sdk_key"].as_str().unwrap_or_default()].is_object())
+            .collect();
+        let sdk_key = available
+            .iter()
+            .find(|q| options.quality.is_some_and(|qn| q["level"] == qn))
+            .or_else(|| available.iter().max_by_key(|q| q["level"].as_i64()))
+            .and_then(|q| q["sdk_key"].as_str());
+        let main = match sdk_key {
+            Some(key) => &stream_data["data"][key]["main"],
+            None => &stream_data["data"]["origin"]["main"],
+        };
+        if let Some(url) = main[if hls { "hls" } else { "flv" }]
+            .as_str()
+            .filter(|u| !u.is_empty())
+        {
+            return Some((url.to_string(), extension));
+        }
+    }
+
+    let urls = if hls {
+        &stream_url["hls_pull_url_map"]
+    } else {
+        &stream_url["flv_pull_url"]
+    };
+    PULL_URL_KEYS
+        .iter()
+        .find_map(|key| urls[key].as_str())
+        .map(|url| (url.to_string(), extension))
+}
+
+/// The qualities of `pull_data` with their protocols, or the keys of the pull url maps
+/// when the room has no `stream_data`.
+fn streams(stream_url: &Value) -> Vec<StreamVariant> {
+    let protocols = [("flv", Protocol::HttpFlv), ("hls", Protocol::HlsTs)];
+    let pull_data = &stream_url["live_core_sdk_data"]["pull_data"];
+    let stream_data: Option<Value> = pull_data["stream_data"]
+        .as_str()
+        .and_then(|s| serde_json::from_str(s).ok());
+    if let Some(stream_data) = stream_data {
+        let mut streams = Vec::new();
+        for quality in pull_data["options"]["qualities"]
+            .as_array()
+            .into_iter()
+            .flatten()
+        {
+            let main =
+                &stream_data["data"][quality["sdk_key"].as_str().unwrap_or_default()]["main"];
+            for (key, protocol) in protocols {
+                if main[key].as_str().is_some_and(|url| !url.is_empty()) {
+                    streams.push(StreamVariant {
+                        name: quality["name"].as_str().unwrap_or_default().to_string(),
+                        quality: quality["level"].as_u64().map(|level| level as u32),
+                        codec: None,
+                        protocol: Some(protocol),
+                        cdns: Vec::new(),
+                    });
+                }
+            }
+        }
+        return streams;
+    }
+    [
+        ("flv_pull_url", Protocol::HttpFlv),
+        ("hls_pull_url_map", Protocol::HlsTs),
+    ]
+    .into_iter()
+    .flat_map(|(map, protocol)| {
+        PULL_URL_KEYS
+            .iter()
+            .filter(move |key| stream_url[map][key].is_string())
+            .map(move |key| StreamVariant {
+                name: key.to_string(),
+                protocol: Some(protocol),
+                ..Default::default()
+            })
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn select_stream_quality() {
+        let stream_data = json!({"data": {
+            "origin": {"main": {"flv": "origin.flv", "hls": "origin.m3u8"}},
+            "sd": {"main": {"flv": "sd.flv", "hls": "sd.m3u8"}},
+        }});
+        let stream_url = json!({
+            "flv_pull_url": {"SD1": "sd1.flv", "HD1": "hd1.flv"},
+            "hls_pull_url_map": {"SD1": "sd1.m3u8"},
+            "live_core_sdk_data": {"pull_data": {
+                "stream_data": stream_data.to_string(),
+                "options": {"qualities": [
+                    {"sdk_key": "origin", "level": 5},
+                    {"sdk_key": "sd", "level": 2},
+                    {"sdk_key": "ld", "level": 1},
+                ]},
+            }},
+        });
+        let (url, _) = select_stream(&stream_url, &StreamOptions::default()).unwrap();
+        assert_eq!(url, "origin.flv");
+
+        let options = StreamOptions {
+            quality: Some(2),
+            protocol: Some(Protocol::HlsTs),
+            ..Default::default()
+        };
+        let (url, extension) = select_stream(&stream_url, &options).unwrap();
+        assert_eq!(url, "sd.m3u8");
+        assert!(matches!(extension, Extension::Ts));
+
+        let options = StreamOptions {
+            quality: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            select_stream(&stream_url, &options).unwrap().0,
+            "origin.flv"
+        );
+
+        let fallback = json!({"flv_pull_url": {"SD1": "sd1.flv", "HD1": "hd1.flv"}});
+        assert_eq!(
+            select_stream(&fallback, &StreamOptions::default())
+                .unwrap()
+                .0,
+            "hd1.flv"
+        );
+    }
+
+    #[test]
+    fn list_streams() {
+        let stream_data = json!({"data": {
+            "origin": {"main": {"flv": "origin.flv", "hls": "origin.m3u8"}},
+            "sd": {"main": {"flv": "sd.flv", "hls": ""}},
+        }});
+        let stream_url = json!({
+            "live_core_sdk_data": {"pull_data": {
+                "stream_data": stream_data.to_string(),
+                "options": {"qualities": [
+                    {"sdk_key": "origin", "level": 5, "name": "原画"},
+                    {"sdk_key": "sd", "level": 2, "name": "标清"},
+                    {"sdk_key": "ld", "level": 1, "name": "流畅"},
+                ]},
+            }},
+        });
+        let names: Vec<_> = streams(&stream_url)
+            .into_iter()
+            .map(|s| (s.name, s.quality, s.protocol))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("原画".to_string(), Some(5), Some(Protocol::HttpFlv)),
+                ("原画".to_string(), Some(5), Some(Protocol::HlsTs)),
+                ("标清".to_string(), Some(2), Some(Protocol::HttpFlv)),
+            ]
+        );
+
+        let fallback = json!({
+            "flv_pull_url": {"SD1": "sd1.flv", "HD1": "hd1.flv"},
+            "hls_pull_url_map": {"SD1": "sd1.m3u8"},
+        });
+        let keys: Vec<_> = streams(&fallback)
+            .into_iter()
+            .map(|s| (s.name, s.protocol))
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                ("HD1".to_string(), Some(Protocol::HttpFlv)),
+                ("SD1".to_string(), Some(Protocol::HttpFlv)),
+                ("SD1".to_string(), Some(Protocol::HlsTs)),
+            ]
+        );
+    }
+}
@@ -1,9 +1,14 @@
 use crate::client::StatelessClient;
-use crate::downloader::error::Error;
-use crate::downloader::extractor::{Extension, Site, SiteDefinition};
+use crate::downloader::error::{Error, Result};
+use crate::downloader::extractor::{
+    Extension, LiveStatus, Protocol, Site, SiteDefinition, StreamOptions, StreamVariant, json_text,
+    local_time,
+};
 use async_trait::async_trait;
 use md5::{Digest, Md5};
-use tracing::info;
+use rand::Rng;
+use serde_json::Value;
+use tracing::{info, warn};
 
 use std::any::Any;
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -18,82 +23,379 @@ impl SiteDefinition for DouyuLive {
             .is_match(url)
     }
 
-    async fn get_site(
+    async fn get_site(&self, url: &str, client: StatelessClient) -> Result<Site> {
+        self.get_site_with_options(url, client, &StreamOptions::default())
+            .await
+    }
+
+    async fn get_site_with_options(
         &self,
         url: &str,
         client: StatelessClient,
-    ) -> crate::downloader::error::Result<Site> {
-        let text = client.client.get(url).send().await?.text().await?;
-        let patterns = [
-            r"\$ROOM\.room_id\s*=\s*(\d+)",
-            r"room_id\s*=\s*(\d+)",
-            r#""room_id.?":(\d+)"#,
-            r"data-onlineid=(\d+)",
-        ];
-
-        // Compile each pattern independently.
-        let room_id = patterns
-            .iter()
-            .map(|pat| regex::Regex::new(pat).unwrap())
-            .find_map(|pat| pat.captures(&text))
-            .map(|captures| captures[1].to_string())
-            .ok_or_else(|| Error::Custom(format!("Wrong url: {url}")))?;
-
-        let room_info: serde_json::Value = client
-            .client
-            .get(format!("https://www.douyu.com/betard/{room_id}"))
-            .send()
-            .await?
-            .json()
-            .await?;
-
-        let time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_micros();
-        let mut hasher = Md5::new();
-        hasher.update(format!("{room_id}{time}"));
-        let sign = format!("{:x}", hasher.finalize());
-        let data = [
-            ("did", "10000000000000000000000000001501"),
-            ("rid", &room_id),
-        ];
+        options: &StreamOptions,
+    ) -> Result<Site> {
+        let (room_id, room_info) = room_info(url, &client).await?;
+
+        let did = device_id();
         info!("{room_id}");
-        let result: serde_json::Value = client
-            .client
-            .post(format!(
-                "https://playweb.douyucdn.cn/lapi/live/hlsH5Preview/{room_id}"
-            ))
-            .header("rid", &room_id)
-            .header("time", time.to_string())
-            .header("auth", sign)
-            .form(&data)
-            .send()
-            .await?
-            .json()
-            .await?;
-        if result["error"] == 0
-            && let Some(key) = regex::Regex::new(r"(\d{1,8}[0-9a-zA-Z]+)_?\d{0,4}(/playlist|.m3u8)")
-                .unwrap()
-                .captures(&result["data"]["rtmp_live"].to_string())
-        {
-            return Ok(Site {
-                name: "douyu",
-                title: room_info
-                    .get("room")
-                    .and_then(|room| room.get("room_name"))
-                    .and_then(|name| name.as_str())
-                    .unwrap_or_default()
-                    .to_string(),
-                direct_url: format!("https://hw-tct.douyucdn.cn/live/{}.flv?uuid=", &key[1]),
-                extension: Extension::Flv,
-                client,
-            });
-        }
-        Err(Error::Custom(result.to_string()))
+        // Only the signing falls back to the preview, which ignores the rate and cdn.
+        let signing = encryption(&client, &did)
+            .await
+            .and_then(|encryption| sign(&room_id, &encryption, 0).map(|_| encryption));
+        let (direct_url, streams) = match signing {
+            Ok(encryption) => h5_play(&client, &room_id, &did, &encryption, options).await?,
+            Err(e) => {
+                warn!("Unable to sign getH5Play, falling back to preview: {e}");
+                (h5_preview(&client, &room_id, &did).await?, Vec::new())
+            }
+        };
+
+        let room = &room_info["room"];
+        let text = |key: &str| room[key].as_str().unwrap_or_default().to_string();
+        Ok(Site {
+            name: "douyu",
+            title: text("room_name"),
+            direct_url,
+            fallback_urls: Vec::new(),
+            streams,
+            streamer: text("nickname"),
+            room_id,
+            uid: json_text(&room["up_id"]),
+            area: text("second_lvl_name"),
+            cover: text("room_pic"),
+            live_start_time: room["show_time"].as_i64().and_then(local_time),
+            extension: Extension::Flv,
+            client,
+        })
+    }
+
+    async fn live_status(&self, url: &str, client: StatelessClient) -> Result<LiveStatus> {
+        let (_, room_info) = room_info(url, &client).await?;
+        Ok(status(&room_info["room"]))
     }
 
     fn as_any(&self) -> &dyn Any {
         self
     }
 }
+
+/// The numeric room id, looked up in the page since urls may use a vanity name, and the
+/// `betard` room info.
+async fn room_info(url: &str, client: &StatelessClient) -> Result<(String, Value)> {
+    let text = client.http.get(url).send().await?.text().await?;
+    let patterns = [
+        r"\$ROOM\.room_id\s*=\s*(\d+)",
+        r"room_id\s*=\s*(\d+)",
+        r#""room_id.?":(\d+)"#,
+        r"data-onlineid=(\d+)",
+    ];
+
+    // Compile each pattern independently.
+    let room_id = patterns
+        .iter()
+        .map(|pat| regex::Regex::new(pat).unwrap())
+        .find_map(|pat| pat.captures(&text))
+        .map(|captures| captures[1].to_string())
+        .ok_or_else(|| Error::Custom(format!("Wrong url: {url}")))?;
+
+    let room_info: Value = client
+        .http
+        .get(format!("https://www.douyu.com/betard/{room_id}"))
+        .send()
+        .await?
+        .json()
+        .await?;
+    Ok((room_id, room_info))
+}
+
+/// `show_status` 1 is live, unless it's a replay (`videoLoop`).
+fn status(room: &Value) -> LiveStatus {
+    let text = |key: &str| room[key].as_str().unwrap_or_default().to_string();
+    LiveStatus {
+        live: room["show_status"] == 1 && room["videoLoop"] != 1,
+        title: text("room_name"),
+        area: text("second_lvl_name"),
+        cover: text("room_pic"),
+    }
+}
+
+/// Random 32 digit hex device id, Douyu rate limits requests sharing a `did`.
+fn device_id() -> String {
+    let mut rng = rand::thread_rng();
+    (0..32)
+        .map(|_| format!("{:x}", rng.gen_range(0..16)))
+        .collect()
+}
+
+fn md5_hex(data: &str) -> String {
+    let mut hasher = Md5::new();
+    hasher.update(data);
+    format!("{:x}", hasher.finalize())
+}
+
+/// Parameters signed with the key handed out by `getEncryption`, as done by the web player.
+#[derive(Debug)]
+struct Signature {
+    enc_data: String,
+    tt: u64,
+    auth: String,
+}
+
+fn sign(room_id: &str, encryption: &Value, tt: u64) -> Result<Signature> {
+    let field = |key: &str| {
+        encryption[key]
+            .as_str()
+            .ok_or_else(|| Error::Custom(format!("Missing {key}: {encryption}")))
+    };
+    let key = field("key")?;
+    let mut secret = field("rand_str")?.to_string();
+    for _ in 0..encryption["enc_time"].as_u64().unwrap_or_default() {
+        secret = md5_hex(&format!("{secret}{key}"));
+    }
+    let salt = if encryption["is_special"] == 1 {
+        String::new()
+    } else {
+        format!("{room_id}{tt}")
+    };
+    Ok(Signature {
+        enc_data: field("enc_data")?.to_string(),
+        tt,
+        auth: md5_hex(&format!("{secret}{key}{salt}")),
+    })
+}
+
+/// The keys `getH5Play` requests are signed with, see [`sign`].
+async fn encryption(client: &StatelessClient, did: &str) -> Result<Value> {
+    let mut encryption: Value = client
+        .http
+        .get("https://www.douyu.com/wgapi/livenc/liveweb/websec/getEncryption")
+        .query(&[("did", did)])
+        .send()
+        .await?
+        .json()
+        .await?;
+    if encryption["error"] != 0 {
+        return Err(Error::Custom(encryption.to_string()));
+    }
+    Ok(encryption["data"].take())
+}
+
+/// Selects `rate` and `cdn` with the signed `getH5Play` API. The requested quality is the
+/// `rate`, 0 being the original quality, which is used when the quality isn't offered.
+/// Returns the url along with the rates and cdns offered.
+async fn h5_play(
+    client: &StatelessClient,
+    room_id: &str,
+    did: &str,
+    encryption: &Value,
+    options: &StreamOptions,
+) -> Result<(String, Vec<StreamVariant>)> {
+    let rate = options.quality.unwrap_or_default().to_string();
+    let mut play = get_h5_play(client, room_id, did, encryption, &rate, "").await?;
+    let (rate, cdn) = select_rate_cdn(&play, options);
+    // Douyu's own choice, the one just played, when every cdn is blacklisted.
+    let cdn = match cdn {
+        Some(cdn) => cdn.to_string(),
+        None => {
+            let fallback = play["rtmp_cdn"].as_str().unwrap_or_default().to_string();
+            if play["cdnsWithName"]
+                .as_array()
+                .is_some_and(|cdns| !cdns.is_empty())
+            {
+                warn!("Every cdn of room {room_id} is blacklisted, falling back to {fallback}");
+            }
+            fallback
+        }
+    };
+    if rate != play["rate"].as_i64().unwrap_or_default() || play["rtmp_cdn"] != cdn.as_str() {
+        play = get_h5_play(client, room_id, did, encryption, &rate.to_string(), &cdn).await?;
+    }
+    match (play["rtmp_url"].as_str(), play["rtmp_live"].as_str()) {
+        (Some(rtmp_url), Some(rtmp_live)) => {
+            Ok((format!("{rtmp_url}/{rtmp_live}"), streams(&play)))
+        }
+        _ => Err(Error::Custom(play.to_string())),
+    }
+}
+
+async fn get_h5_play(
+    client: &StatelessClient,
+    room_id: &str,
+    did: &str,
+    encryption: &Value,
+    rate: &str,
+    cdn: &str,
+) -> Result<Value> {
+    let tt = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let signature = sign(room_id, encryption, tt)?;
+    let mut result: Value = client
+        .http
+        .post(format!(
+            "https://www.douyu.com/lapi/live/getH5PlayV1/{room_id}"
+        ))
+        .form(&[
+            ("enc_data", signature.enc_data.as_str()),
+            ("tt", &signature.tt.to_string()),
+            ("did", did),
+            ("auth", &signature.auth),
+            ("cdn", cdn),
+            ("rate", rate),
+            ("hevc", "0"),
+            ("fa", "0"),
+            ("ive", "0"),
+        ])
+        .send()
+        .await?
+        .json()
+        .await?;
+    if result["error"] != 0 {
+        return Err(Error::Custom(result.to_string()));
+    }
+    Ok(result["data"].take())
+}
+
+/// Every `rate` in `multirates`, all served by the cdns in `cdnsWithName`.
+fn streams(play: &Value) -> Vec<StreamVariant> {
+    let cdns: Vec<String> = play["cdnsWithName"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|c| c["cdn"].as_str().map(String::from))
+        .collect();
+    play["multirates"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|r| StreamVariant {
+            name: r["name"].as_str().unwrap_or_default().to_string(),
+            quality: r["rate"].as_u64().map(|rate| rate as u32),
+            codec: None,
+            protocol: Some(Protocol::HttpFlv),
+            cdns: cdns.clone(),
+        })
+        .collect()
+}
+
+/// Returns the `rate` and `cdn` to play, `None` when no cdn is allowed.
+fn select_rate_cdn<'a>(play: &'a Value, options: &StreamOptions) -> (i64, Option<&'a str>) {
+    let rate = play["multirates"]
+        .as_array()
+        .and_then(|rates| {
+            rates
+                .iter()
+                .filter_map(|r| r["rate"].as_i64())
+                .find(|rate| options.quality.is_some_and(|qn| *rate == qn as i64))
+        })
+        .unwrap_or(0);
+    let cdn = play["cdnsWithName"].as_array().and_then(|cdns| {
+        cdns.iter()
+            .filter_map(|c| c["cdn"].as_str())
+            .filter(|cdn| options.cdn_allowed(cdn))
+            // Douyu's choice wins among hosts that are not preferred.
+            .min_by_key(|cdn| (options.cdn_rank(cdn), play["rtmp_cdn"] != *cdn))
+    });
+    (rate, cdn)
+}
+
+async fn h5_preview(client: &StatelessClient, room_id: &str, did: &str) -> Result<String> {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_micros();
+    let sign = md5_hex(&format!("{room_id}{time}"));
+    let data = [("did", did), ("rid", room_id)];
+    let result: Value = client
+        .http
+        .post(format!(
+            "https://playweb.douyucdn.cn/lapi/live/hlsH5Preview/{room_id}"
+        ))
+        .header("rid", room_id)
+        .header("time", time.to_string())
+        .header("auth", sign)
+        .form(&data)
+        .send()
+        .await?
+        .json()
+        .await?;
+    if result["error"] == 0
+        && let Some(key) = regex::Regex::new(r"(\d{1,8}[0-9a-zA-Z]+)_?\d{0,4}(/playlist|.m3u8)")
+            .unwrap()
+            .captures(&result["data"]["rtmp_live"].to_string())
+    {
+        return Ok(format!(
+            "https://hw-tct.douyucdn.cn/live/{}.flv?uuid=",
+            &key[1]
+        ));
+    }
+    Err(Error::Custom(result.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn sign_and_select() -> Result<()> {
+        let encryption = json!({
+            "rand_str": "abc",
+            "enc_time": 2,
+            "key": "k",
+            "is_special": 0,
+            "enc_data": "data",
+        });
+        let signature = sign("9999", &encryption, 1700000000)?;
+        let secret = md5_hex(&format!("{}k", md5_hex("abck")));
+        assert_eq!(signature.auth, md5_hex(&format!("{secret}k99991700000000")));
+        assert_eq!(signature.enc_data, "data");
+        assert_eq!(device_id().len(), 32);
+
+        let play = json!({
+            "rate": 0,
+            "rtmp_cdn": "hw-h5",
+            "multirates": [{"rate": 0}, {"rate": 4}, {"rate": 3}],
+            "cdnsWithName": [{"cdn": "hw-h5"}, {"cdn": "tct-h5"}, {"cdn": "ali-h5"}],
+        });
+        assert_eq!(
+            select_rate_cdn(&play, &StreamOptions::default()),
+            (0, Some("hw-h5"))
+        );
+        let options = StreamOptions {
+            quality: Some(4),
+            cdn_prefer: vec!["ali".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(select_rate_cdn(&play, &options), (4, Some("ali-h5")));
+        let options = StreamOptions {
+            quality: Some(2),
+            cdn_blacklist: vec!["hw".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(select_rate_cdn(&play, &options), (0, Some("tct-h5")));
+        let options = StreamOptions {
+            cdn_blacklist: vec!["h5".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(select_rate_cdn(&play, &options), (0, None));
+        let streams = streams(&play);
+        assert_eq!(streams.len(), 3);
+        assert_eq!(streams[1].quality, Some(4));
+        assert_eq!(streams[1].cdns, ["hw-h5", "tct-h5", "ali-h5"]);
+        Ok(())
+    }
+
+    #[test]
+    fn room_status() {
+        let room =
+            json!({"show_status": 1, "videoLoop": 0, "room_name": "live", "room_pic": "pic"});
+        let live = status(&room);
+        assert!(live.live);
+        assert_eq!(live.title, "live");
+        assert_eq!(live.cover, "pic");
+        assert!(!status(&json!({"show_status": 1, "videoLoop": 1})).live);
+        assert!(!status(&json!({"show_status": 2, "videoLoop": 0})).live);
+    }
+}
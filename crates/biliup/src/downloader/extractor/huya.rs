@@ -1,9 +1,18 @@
 use crate::client::StatelessClient;
-use crate::downloader::error::Result;
-use crate::downloader::extractor::{Extension, Site, SiteDefinition};
+use crate::downloader::error::{Error, Result};
+use crate::downloader::extractor::{
+    Extension, LiveStatus, Protocol, Site, SiteDefinition, StreamOptions, StreamVariant, json_text,
+    local_time,
+};
 use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use md5::{Digest, Md5};
+use rand::Rng;
 use serde_json::Value;
 use std::any::Any;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
 
 pub struct HuyaLive {}
 
@@ -16,58 +25,269 @@ impl SiteDefinition for HuyaLive {
     }
 
     async fn get_site(&self, url: &str, client: StatelessClient) -> Result<Site> {
-        let response = client.client.get(url).send().await?;
-        // println!("{:?}", response);
+        self.get_site_with_options(url, client, &StreamOptions::default())
+            .await
+    }
 
-        let text = response.text().await?;
-        let mut stream: Value = match regex::Regex::new(r"stream: (\{.+)\n.*?\};")
-            .unwrap()
-            .captures(&text)
-        {
-            Some(captures) => serde_json::from_str(&captures[1])?,
-            _ => {
-                return Err(crate::downloader::error::Error::Custom(format!(
-                    "Not online: {text}"
-                )));
-            }
-        };
+    async fn get_site_with_options(
+        &self,
+        url: &str,
+        client: StatelessClient,
+        options: &StreamOptions,
+    ) -> Result<Site> {
+        let mut stream = stream_info(url, &client)
+            .await?
+            .ok_or_else(|| Error::Custom(format!("Not online: {url}")))?;
         let game = stream["data"][0].take();
-        let game_stream_info = game["gameStreamInfoList"]
-            .as_array()
-            .and_then(|game_stream_info_list| game_stream_info_list.first())
-            .ok_or_else(|| {
-                crate::downloader::error::Error::Custom(format!("Not online: {game}"))
-            })?;
-        let mut v_multi_stream_info = stream["vMultiStreamInfo"].take();
-        // vec![1,2].iter().max()
-        // println!("{}", v_multi_stream_info);
-        let _stream_info = v_multi_stream_info
-            .as_array()
-            .and_then(|v| v.iter().max_by_key(|info| info["iBitRate"].as_i64()));
-        // println!("{:?}", stream_info);
-        // let ratio = ;
-        let direct_url = format!(
-            "{}/{}.{}?{}&ratio={}",
-            game_stream_info["sFlvUrl"].as_str().unwrap(),
-            game_stream_info["sStreamName"].as_str().unwrap(),
-            game_stream_info["sFlvUrlSuffix"].as_str().unwrap(),
-            game_stream_info["sFlvAntiCode"].as_str().unwrap(),
-            v_multi_stream_info[0]["iBitRate"].take()
-        );
-        // println!("{}", direct_url);
+        let ratio = select_ratio(&stream["vMultiStreamInfo"], options);
+        let mut urls = stream_urls(&game["gameStreamInfoList"], options, ratio).into_iter();
+        let direct_url = urls
+            .next()
+            .ok_or_else(|| Error::Custom(format!("Not online: {game}")))?;
+
+        let live_info = &game["gameLiveInfo"];
+        let text = |key: &str| live_info[key].as_str().unwrap_or_default().to_string();
         Ok(Site {
             name: "huya",
-            title: game["gameLiveInfo"]["introduction"]
-                .as_str()
-                .unwrap()
-                .to_string(),
+            title: text("introduction"),
             direct_url,
+            fallback_urls: urls.collect(),
+            streams: streams(&stream["vMultiStreamInfo"], &game["gameStreamInfoList"]),
+            streamer: text("nick"),
+            room_id: json_text(&live_info["profileRoom"]),
+            uid: json_text(&live_info["uid"]),
+            area: text("gameFullName"),
+            cover: text("screenshot"),
+            live_start_time: live_info["startTime"].as_i64().and_then(local_time),
             extension: Extension::Flv,
             client,
         })
     }
 
+    async fn live_status(&self, url: &str, client: StatelessClient) -> Result<LiveStatus> {
+        let Some(stream) = stream_info(url, &client).await? else {
+            return Ok(LiveStatus::default());
+        };
+        let game = &stream["data"][0];
+        let live_info = &game["gameLiveInfo"];
+        let text = |key: &str| live_info[key].as_str().unwrap_or_default().to_string();
+        Ok(LiveStatus {
+            live: game["gameStreamInfoList"]
+                .as_array()
+                .is_some_and(|list| !list.is_empty()),
+            title: text("introduction"),
+            area: text("gameFullName"),
+            cover: text("screenshot"),
+        })
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
 }
+
+/// The `stream` object embedded in the room page, which only live rooms have.
+async fn stream_info(url: &str, client: &StatelessClient) -> Result<Option<Value>> {
+    let text = client.http.get(url).send().await?.text().await?;
+    match regex::Regex::new(r"stream: (\{.+)\n.*?\};")
+        .unwrap()
+        .captures(&text)
+    {
+        Some(captures) => Ok(Some(serde_json::from_str(&captures[1])?)),
+        None => Ok(None),
+    }
+}
+
+/// Bitrate of the `vMultiStreamInfo` entry matching the requested quality. 0 is the
+/// source quality, which is also used when the quality isn't offered.
+fn select_ratio(multi_stream_info: &Value, options: &StreamOptions) -> i64 {
+    multi_stream_info
+        .as_array()
+        .and_then(|info| {
+            info.iter()
+                .filter_map(|i| i["iBitRate"].as_i64())
+                .find(|rate| options.quality.is_some_and(|qn| *rate == qn as i64))
+        })
+        .unwrap_or(0)
+}
+
+/// Every bitrate in `vMultiStreamInfo`, all served by the CDNs in `gameStreamInfoList`.
+fn streams(multi_stream_info: &Value, stream_info_list: &Value) -> Vec<StreamVariant> {
+    let cdns: Vec<String> = stream_info_list
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|info| info["sCdnType"].as_str().map(String::from))
+        .collect();
+    multi_stream_info
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|info| StreamVariant {
+            name: info["sDisplayName"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            quality: info["iBitRate"].as_u64().map(|rate| rate as u32),
+            codec: None,
+            protocol: Some(Protocol::HttpFlv),
+            cdns: cdns.clone(),
+        })
+        .collect()
+}
+
+/// Flv urls of every CDN in `gameStreamInfoList`, ordered by `cdn_prefer` and then by
+/// Huya's own priority. Entries without a usable url are skipped.
+fn stream_urls(stream_info_list: &Value, options: &StreamOptions, ratio: i64) -> Vec<String> {
+    let mut stream_infos: Vec<&Value> = stream_info_list
+        .as_array()
+        .map(|list| {
+            list.iter()
+                .filter(|info| options.cdn_allowed(info["sCdnType"].as_str().unwrap_or_default()))
+                .collect()
+        })
+        .unwrap_or_default();
+    stream_infos.sort_by_key(|info| {
+        (
+            options.cdn_rank(info["sCdnType"].as_str().unwrap_or_default()),
+            std::cmp::Reverse(info["iWebPriorityRate"].as_i64()),
+        )
+    });
+    stream_infos
+        .into_iter()
+        .filter_map(|info| match stream_url(info, ratio) {
+            Ok(url) => Some(url),
+            Err(e) => {
+                warn!("Skip CDN {}: {e}", info["sCdnType"]);
+                None
+            }
+        })
+        .collect()
+}
+
+fn stream_url(info: &Value, ratio: i64) -> Result<String> {
+    let field = |key: &str| {
+        info[key]
+            .as_str()
+            .ok_or_else(|| Error::Custom(format!("Missing {key}: {info}")))
+    };
+    let stream_name = field("sStreamName")?;
+    let mut url = format!(
+        "{}/{stream_name}.{}?{}",
+        field("sFlvUrl")?,
+        field("sFlvUrlSuffix")?,
+        anti_code(stream_name, field("sFlvAntiCode")?)?
+    );
+    if ratio > 0 {
+        url.push_str(&format!("&ratio={ratio}"));
+    }
+    Ok(url)
+}
+
+/// Regenerates `wsSecret` and `seqid` for a random uid, the anti code embedded in the
+/// page expires and is then refused with 403.
+fn anti_code(stream_name: &str, anti_code: &str) -> Result<String> {
+    let params: Vec<(String, String)> = url::form_urlencoded::parse(anti_code.as_bytes())
+        .into_owned()
+        .collect();
+    let param = |key: &str| {
+        params
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+            .ok_or_else(|| Error::Custom(format!("Missing {key} in anti code: {anti_code}")))
+    };
+    let ws_time = param("wsTime")?;
+    let ctype = param("ctype")?;
+    let fm = STANDARD
+        .decode(param("fm")?.replace(' ', "+"))
+        .map_err(|e| Error::Custom(format!("Invalid fm: {e}")))?;
+    let fm = String::from_utf8_lossy(&fm);
+    let ws_secret_prefix = fm.split('_').next().unwrap_or_default();
+
+    let platform_id = 100;
+    let mut rng = rand::thread_rng();
+    let uid: u64 = rng.gen_range(12340000..12350000);
+    let convert_uid = ((uid << 8) | (uid >> 24)) & 0xFFFFFFFF;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    let seq_id = uid + now;
+    let ws_secret_hash = md5_hex(&format!("{seq_id}|{ctype}|{platform_id}"));
+    let ws_secret = md5_hex(&format!(
+        "{ws_secret_prefix}_{convert_uid}_{stream_name}_{ws_secret_hash}_{ws_time}"
+    ));
+    let ct = (u64::from_str_radix(ws_time, 16).unwrap_or_default() * 1000) + rng.gen_range(0..1000);
+    let uuid = (ct % 10_000_000_000 * 1000 + rng.gen_range(0..1000)) % 0xFFFFFFFF;
+
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    query
+        .append_pair("wsSecret", &ws_secret)
+        .append_pair("wsTime", ws_time)
+        .append_pair("seqid", &seq_id.to_string())
+        .append_pair("ctype", ctype)
+        .append_pair("ver", "1")
+        .append_pair("fs", param("fs").unwrap_or_default())
+        .append_pair("t", &platform_id.to_string())
+        .append_pair("u", &convert_uid.to_string())
+        .append_pair("uuid", &uuid.to_string())
+        .append_pair("sdk_sid", &now.to_string())
+        .append_pair("codec", "264");
+    Ok(query.finish())
+}
+
+fn md5_hex(data: &str) -> String {
+    let mut hasher = Md5::new();
+    hasher.update(data);
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn rank_cdns() -> Result<()> {
+        let anti_code = "wsSecret=old&wsTime=65a0b0c0&fm=RFdxOEJjSjNoNkRKdDZUWV8kMF8kMV8kMl8kMw%3D%3D&ctype=huya_live&fs=bgct";
+        let list = json!([
+            {"sCdnType": "AL", "iWebPriorityRate": 10, "sStreamName": "s", "sFlvUrl": "http://al.flv.huya.com/src", "sFlvUrlSuffix": "flv", "sFlvAntiCode": anti_code},
+            {"sCdnType": "TX", "iWebPriorityRate": 20, "sStreamName": "s", "sFlvUrl": "http://tx.flv.huya.com/src", "sFlvUrlSuffix": "flv", "sFlvAntiCode": anti_code},
+            {"sCdnType": "HW", "iWebPriorityRate": 30, "sStreamName": "s", "sFlvUrl": "http://hw.flv.huya.com/src", "sFlvUrlSuffix": "flv", "sFlvAntiCode": anti_code},
+        ]);
+        let options = StreamOptions {
+            quality: Some(2000),
+            cdn_prefer: vec!["TX".to_string()],
+            cdn_blacklist: vec!["HW".to_string()],
+            ..Default::default()
+        };
+        let ratio = select_ratio(&json!([{"iBitRate": 0}, {"iBitRate": 2000}]), &options);
+        let urls = stream_urls(&list, &options, ratio);
+        assert_eq!(urls.len(), 2);
+        assert!(urls[0].starts_with("http://tx.flv.huya.com/src/s.flv?wsSecret="));
+        assert!(urls[0].ends_with("&ratio=2000"));
+        assert!(!urls[0].contains("wsSecret=old"));
+        assert!(urls[1].starts_with("http://al.flv.huya.com/src/s.flv?"));
+
+        let urls = stream_urls(&list, &StreamOptions::default(), 0);
+        assert!(urls[0].starts_with("http://hw.flv.huya.com/"));
+        assert!(!urls[0].contains("ratio="));
+
+        // A broken entry doesn't take the other CDNs down with it.
+        let mut broken = list.clone();
+        broken[2]["sFlvAntiCode"] = json!("wsTime=65a0b0c0");
+        let urls = stream_urls(&broken, &StreamOptions::default(), 0);
+        assert_eq!(urls.len(), 2);
+        assert!(urls[0].starts_with("http://tx.flv.huya.com/"));
+
+        let streams = streams(
+            &json!([{"sDisplayName": "蓝光", "iBitRate": 0}, {"sDisplayName": "超清", "iBitRate": 2000}]),
+            &list,
+        );
+        assert_eq!(streams[1].name, "超清");
+        assert_eq!(streams[1].quality, Some(2000));
+        assert_eq!(streams[1].cdns, ["AL", "TX", "HW"]);
+        Ok(())
+    }
+}
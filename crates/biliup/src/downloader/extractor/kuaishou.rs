@@ -0,0 +1,294 @@
+use crate::client::StatelessClient;
+use crate::downloader::error::{Error, Result};
+use crate::downloader::extractor::{
+    BROWSER_UA, Codec, Extension, LiveStatus, Protocol, Site, SiteDefinition, StreamOptions,
+    StreamVariant, json_text, local_time,
+};
+use async_trait::async_trait;
+use reqwest::header::{COOKIE, HeaderValue, REFERER, SET_COOKIE, USER_AGENT};
+use serde_json::Value;
+use std::any::Any;
+
+const LIVE_URL: &str = "https://live.kuaishou.com/";
+
+pub struct KuaishouLive;
+
+#[async_trait]
+impl SiteDefinition for KuaishouLive {
+    fn can_handle_url(&self, url: &str) -> bool {
+        regex::Regex::new(r"(?:https?://)?live\.kuaishou\.com/u/[\w-]+")
+            .unwrap()
+            .is_match(url)
+    }
+
+    async fn get_site(&self, url: &str, client: StatelessClient) -> Result<Site> {
+        self.get_site_with_options(url, client, &StreamOptions::default())
+            .await
+    }
+
+    async fn get_site_with_options(
+        &self,
+        url: &str,
+        mut client: StatelessClient,
+        options: &StreamOptions,
+    ) -> Result<Site> {
+        let (id, mut play) = play(url, &mut client, options).await?;
+        let live_stream = play["liveStream"].take();
+        if play["isLiving"] != true || live_stream["playUrls"].is_null() {
+            return Err(Error::Custom(format!("Not online: {url}")));
+        }
+        let direct_url = select_stream(&live_stream["playUrls"], options)
+            .ok_or_else(|| Error::Custom(format!("No stream url: {}", live_stream["playUrls"])))?;
+
+        let author = &play["author"];
+        Ok(Site {
+            name: "kuaishou",
+            title: live_stream["caption"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            direct_url,
+            fallback_urls: Vec::new(),
+            streams: streams(&live_stream["playUrls"]),
+            streamer: author["name"].as_str().unwrap_or_default().to_string(),
+            room_id: id,
+            uid: json_text(&author["id"]),
+            area: play["gameInfo"]["name"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            cover: live_stream["poster"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            // Milliseconds.
+            live_start_time: live_stream["startTime"]
+                .as_i64()
+                .and_then(|t| local_time(t / 1000)),
+            extension: Extension::Flv,
+            client,
+        })
+    }
+
+    async fn live_status(&self, url: &str, client: StatelessClient) -> Result<LiveStatus> {
+        self.live_status_with_options(url, client, &StreamOptions::default())
+            .await
+    }
+
+    async fn live_status_with_options(
+        &self,
+        url: &str,
+        mut client: StatelessClient,
+        options: &StreamOptions,
+    ) -> Result<LiveStatus> {
+        let (_, play) = play(url, &mut client, options).await?;
+        let live_stream = &play["liveStream"];
+        Ok(LiveStatus {
+            live: play["isLiving"] == true && !live_stream["playUrls"].is_null(),
+            title: live_stream["caption"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            area: play["gameInfo"]["name"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            cover: live_stream["poster"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// The user id in `url` and the `playList` entry of the room page, with the headers and
+/// cookies the page needs set on `client`.
+async fn play(
+    url: &str,
+    client: &mut StatelessClient,
+    options: &StreamOptions,
+) -> Result<(String, Value)> {
+    let id = match regex::Regex::new(r"/u/([\w-]+)").unwrap().captures(url) {
+        Some(captures) => captures[1].to_string(),
+        _ => {
+            return Err(Error::Custom(format!("Wrong url: {url}")));
+        }
+    };
+    client
+        .headers
+        .insert(USER_AGENT, HeaderValue::from_static(BROWSER_UA));
+    client
+        .headers
+        .insert(REFERER, HeaderValue::from_static(LIVE_URL));
+    // Cookieless requests only get a captcha, a `Cookie` header of the stream options or
+    // the credential is passed through, otherwise the front page's cookies are used.
+    if let Some(cookies) = options.cookies_for(LIVE_URL) {
+        client.headers.insert(
+            COOKIE,
+            HeaderValue::from_str(cookies).map_err(|e| Error::Custom(e.to_string()))?,
+        );
+    }
+    if !client.headers.contains_key(COOKIE) {
+        let response = client
+            .http
+            .get(LIVE_URL)
+            .headers(client.headers.clone())
+            .send()
+            .await?;
+        let cookies = response
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .filter_map(|v| cookie::Cookie::parse(v).ok())
+            .map(|c| format!("{}={}", c.name(), c.value()))
+            .collect::<Vec<_>>()
+            .join("; ");
+        if !cookies.is_empty() {
+            client.headers.insert(
+                COOKIE,
+                HeaderValue::from_str(&cookies).map_err(|e| Error::Custom(e.to_string()))?,
+            );
+        }
+    }
+
+    let text = client
+        .http
+        .get(format!("https://live.kuaishou.com/u/{id}"))
+        .headers(client.headers.clone())
+        .send()
+        .await?
+        .text()
+        .await?;
+    let mut state: Value =
+        match regex::Regex::new(r"(?s)window\.__INITIAL_STATE__\s*=\s*(\{.*?\});\s*\(function")
+            .unwrap()
+            .captures(&text)
+        {
+            Some(captures) => serde_json::from_str(&captures[1].replace(":undefined", ":null"))?,
+            _ => {
+                return Err(Error::Custom(format!("No initial state: {url}")));
+            }
+        };
+    let play = state["liveroom"]["playList"][0].take();
+    if !play["errorType"].is_null() {
+        return Err(Error::Custom(format!("{}", play["errorType"])));
+    }
+    Ok((id, play))
+}
+
+/// Returns the FLV url of the representation with the highest bitrate. `playUrls` is
+/// either a list of adaptation sets or, on newer pages, keyed by codec (`h264`, `hevc`).
+fn select_stream(play_urls: &Value, options: &StreamOptions) -> Option<String> {
+    let sets: Vec<&Value> = match play_urls {
+        Value::Array(sets) => sets.iter().collect(),
+        Value::Object(codecs) => codecs
+            .iter()
+            .filter(|(codec, _)| match options.codec {
+                Some(Codec::Avc) => codec.as_str() == "h264",
+                Some(Codec::Hevc) => codec.as_str() == "hevc",
+                None => true,
+            })
+            .map(|(_, set)| set)
+            .collect(),
+        _ => Vec::new(),
+    };
+    sets.iter()
+        .filter_map(|set| set["adaptationSet"]["representation"].as_array())
+        .flatten()
+        .filter(|r| r["url"].as_str().is_some())
+        .max_by_key(|r| r["bitrate"].as_i64())
+        .and_then(|r| r["url"].as_str())
+        .map(str::to_string)
+}
+
+/// Every representation in `playUrls`, the highest bitrate is always recorded.
+fn streams(play_urls: &Value) -> Vec<StreamVariant> {
+    let sets: Vec<(Option<Codec>, &Value)> = match play_urls {
+        Value::Array(sets) => sets.iter().map(|set| (None, set)).collect(),
+        Value::Object(codecs) => codecs
+            .iter()
+            .map(|(codec, set)| {
+                let codec = match codec.as_str() {
+                    "h264" => Some(Codec::Avc),
+                    "hevc" => Some(Codec::Hevc),
+                    _ => None,
+                };
+                (codec, set)
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    sets.into_iter()
+        .flat_map(|(codec, set)| {
+            set["adaptationSet"]["representation"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(move |r| StreamVariant {
+                    name: r["name"].as_str().unwrap_or_default().to_string(),
+                    quality: None,
+                    codec,
+                    protocol: Some(Protocol::HttpFlv),
+                    cdns: Vec::new(),
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn select_highest_bitrate() {
+        let play_urls = json!({
+            "h264": {"adaptationSet": {"representation": [
+                {"url": "h264_2000.flv", "bitrate": 2000},
+                {"url": "h264_8000.flv", "bitrate": 8000},
+            ]}},
+            "hevc": {"adaptationSet": {"representation": [
+                {"url": "hevc_4000.flv", "bitrate": 4000},
+            ]}},
+        });
+        assert_eq!(
+            select_stream(&play_urls, &StreamOptions::default()).as_deref(),
+            Some("h264_8000.flv")
+        );
+        let options = StreamOptions {
+            codec: Some(Codec::Hevc),
+            ..Default::default()
+        };
+        assert_eq!(
+            select_stream(&play_urls, &options).as_deref(),
+            Some("hevc_4000.flv")
+        );
+
+        let legacy = json!([{"adaptationSet": {"representation": [
+            {"url": "1000.flv", "bitrate": 1000},
+            {"url": "3000.flv", "bitrate": 3000},
+        ]}}]);
+        assert_eq!(
+            select_stream(&legacy, &StreamOptions::default()).as_deref(),
+            Some("3000.flv")
+        );
+        let codecs: Vec<_> = streams(&play_urls).iter().map(|s| s.codec).collect();
+        assert_eq!(
+            codecs,
+            [Some(Codec::Avc), Some(Codec::Avc), Some(Codec::Hevc)]
+        );
+    }
+
+    #[test]
+    fn handles_user_ids() {
+        let kuaishou = KuaishouLive;
+        assert!(kuaishou.can_handle_url("https://live.kuaishou.com/u/3xabc"));
+        assert!(kuaishou.can_handle_url("https://live.kuaishou.com/u/-abc_1"));
+        assert!(!kuaishou.can_handle_url("https://live.kuaishou.com/u/"));
+    }
+}
@@ -0,0 +1,309 @@
+use crate::client::StatelessClient;
+use crate::downloader::error::{Error, Result};
+use crate::downloader::extractor::{
+    Codec, Extension, LiveStatus, Protocol, Site, SiteDefinition, StreamOptions, StreamVariant,
+    json_text,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Local};
+use m3u8_rs::{MasterPlaylist, Playlist};
+use rand::Rng;
+use reqwest::header::{AUTHORIZATION, HeaderMap, HeaderValue};
+use serde_json::{Value, json};
+use std::any::Any;
+use url::Url;
+
+const CLIENT_ID: &str = "kimne78kx3ncx6brgo4mv6wki5h1ko";
+const PLAYBACK_ACCESS_TOKEN_HASH: &str =
+    "0828119ded1c13477966434e15800ff57ddacf13ba1911c129dc2200705b0712";
+
+/// Twitch live streams. [`StreamOptions::twitch_oauth_token`], the `auth-token` cookie
+/// of a logged in browser, is needed for subscriber-only streams and to skip ads for
+/// subscribers.
+#[derive(Default)]
+pub struct TwitchLive;
+
+#[async_trait]
+impl SiteDefinition for TwitchLive {
+    fn can_handle_url(&self, url: &str) -> bool {
+        regex::Regex::new(r"(?:https?://)?(?:(?:www|m)\.)?twitch\.tv/\w+")
+            .unwrap()
+            .is_match(url)
+    }
+
+    async fn get_site(&self, url: &str, client: StatelessClient) -> Result<Site> {
+        self.get_site_with_options(url, client, &StreamOptions::default())
+            .await
+    }
+
+    async fn get_site_with_options(
+        &self,
+        url: &str,
+        client: StatelessClient,
+        options: &StreamOptions,
+    ) -> Result<Site> {
+        let login = login(url)?;
+        let query = user_query(&login);
+        let mut response: Value = client
+            .http
+            .post("https://gql.twitch.tv/gql")
+            .headers(gql_headers(options.twitch_oauth_token.as_deref())?)
+            .json(&json!([
+                {
+                    "operationName": "PlaybackAccessToken",
+                    "extensions": {
+                        "persistedQuery": {
+                            "version": 1,
+                            "sha256Hash": PLAYBACK_ACCESS_TOKEN_HASH
+                        }
+                    },
+                    "variables": {
+                        "isLive": true,
+                        "login": login,
+                        "isVod": false,
+                        "vodID": "",
+                        "playerType": "site"
+                    }
+                },
+                { "query": query }
+            ]))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let user = response[1]["data"]["user"].take();
+        if user.is_null() {
+            return Err(Error::Custom(format!("{}", response[1])));
+        }
+        let stream = &user["stream"];
+        if stream.is_null() {
+            return Err(Error::Custom(format!("Not online: {url}")));
+        }
+        let token = &response[0]["data"]["streamPlaybackAccessToken"];
+        let (Some(value), Some(signature)) = (token["value"].as_str(), token["signature"].as_str())
+        else {
+            return Err(Error::Custom(format!("{}", response[0])));
+        };
+
+        let mut usher = Url::parse(&format!(
+            "https://usher.ttvnw.net/api/channel/hls/{login}.m3u8"
+        ))?;
+        usher
+            .query_pairs_mut()
+            .append_pair("player", "twitchweb")
+            .append_pair("p", &rand::thread_rng().gen_range(0..1_000_000).to_string())
+            .append_pair("type", "any")
+            .append_pair("allow_source", "true")
+            .append_pair("allow_audio_only", "true")
+            .append_pair("allow_spectre", "false")
+            .append_pair("fast_bread", "true")
+            .append_pair("sig", signature)
+            .append_pair("token", value);
+        let bytes = client.retryable(usher.as_str()).await?.bytes().await?;
+        let (direct_url, streams) = match m3u8_rs::parse_playlist(&bytes) {
+            Ok((_, Playlist::MasterPlaylist(pl))) => (
+                select_variant(&pl, options)
+                    .map(|uri| usher.join(uri))
+                    .transpose()?
+                    .ok_or_else(|| Error::Custom(format!("No variant: {pl:?}")))?
+                    .to_string(),
+                streams(&pl),
+            ),
+            _ => (usher.to_string(), Vec::new()),
+        };
+
+        Ok(Site {
+            name: "twitch",
+            title: stream["title"].as_str().unwrap_or_default().to_string(),
+            direct_url,
+            fallback_urls: Vec::new(),
+            streams,
+            streamer: user["displayName"].as_str().unwrap_or_default().to_string(),
+            room_id: login,
+            uid: json_text(&user["id"]),
+            area: stream["game"]["displayName"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            cover: stream["previewImageURL"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            live_start_time: stream["createdAt"]
+                .as_str()
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .map(|t| t.with_timezone(&Local)),
+            extension: Extension::Ts,
+            client,
+        })
+    }
+
+    async fn live_status(&self, url: &str, client: StatelessClient) -> Result<LiveStatus> {
+        self.live_status_with_options(url, client, &StreamOptions::default())
+            .await
+    }
+
+    async fn live_status_with_options(
+        &self,
+        url: &str,
+        client: StatelessClient,
+        options: &StreamOptions,
+    ) -> Result<LiveStatus> {
+        let mut response: Value = client
+            .http
+            .post("https://gql.twitch.tv/gql")
+            .headers(gql_headers(options.twitch_oauth_token.as_deref())?)
+            .json(&json!({ "query": user_query(&login(url)?) }))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let user = response["data"]["user"].take();
+        if user.is_null() {
+            return Err(Error::Custom(format!("{response}")));
+        }
+        let stream = &user["stream"];
+        Ok(LiveStatus {
+            live: !stream.is_null(),
+            title: stream["title"].as_str().unwrap_or_default().to_string(),
+            area: stream["game"]["displayName"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            cover: stream["previewImageURL"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+fn login(url: &str) -> Result<String> {
+    match regex::Regex::new(r"twitch\.tv/(\w+)")
+        .unwrap()
+        .captures(url)
+    {
+        Some(captures) => Ok(captures[1].to_lowercase()),
+        _ => Err(Error::Custom(format!("Wrong url: {url}"))),
+    }
+}
+
+/// Headers of GQL requests, made as the account of `oauth_token` if there is one.
+fn gql_headers(oauth_token: Option<&str>) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    headers.insert("Client-ID", HeaderValue::from_static(CLIENT_ID));
+    if let Some(token) = oauth_token {
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("OAuth {token}"))
+                .map_err(|e| Error::Custom(e.to_string()))?,
+        );
+    }
+    Ok(headers)
+}
+
+/// The user and, while live, their stream.
+fn user_query(login: &str) -> String {
+    format!(
+        r#"query {{ user(login: "{login}") {{ id displayName stream {{ id title createdAt previewImageURL(width: 1280, height: 720) game {{ displayName }} }} }} }}"#
+    )
+}
+
+/// The quality is the video height, e.g. 1080. The first variant, the source, is used
+/// when it isn't available.
+fn select_variant<'a>(pl: &'a MasterPlaylist, options: &StreamOptions) -> Option<&'a str> {
+    let video = pl
+        .variants
+        .iter()
+        .filter(|v| v.resolution.is_some())
+        .collect::<Vec<_>>();
+    options
+        .quality
+        .and_then(|height| {
+            video
+                .iter()
+                .find(|v| v.resolution.is_some_and(|r| r.height == height as u64))
+                .copied()
+        })
+        .or_else(|| video.first().copied())
+        .or_else(|| pl.variants.first())
+        .map(|v| v.uri.as_str())
+}
+
+/// The variants of the master playlist, named by their `VIDEO` attribute.
+fn streams(pl: &MasterPlaylist) -> Vec<StreamVariant> {
+    pl.variants
+        .iter()
+        .map(|v| StreamVariant {
+            name: v
+                .video
+                .clone()
+                .unwrap_or_else(|| v.uri.trim_end_matches(".m3u8").to_string()),
+            quality: v.resolution.map(|r| r.height as u32),
+            codec: v.codecs.as_deref().and_then(|codecs| {
+                codecs.split(',').find_map(|c| match c.get(..4) {
+                    Some("avc1") => Some(Codec::Avc),
+                    Some("hvc1" | "hev1") => Some(Codec::Hevc),
+                    _ => None,
+                })
+            }),
+            protocol: Some(Protocol::HlsTs),
+            cdns: Vec::new(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_quality() {
+        let playlist = b"#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=6000000,RESOLUTION=1920x1080,VIDEO=\"chunked\"
+chunked.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=3000000,RESOLUTION=1280x720,VIDEO=\"720p60\"
+720p60.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=160000,CODECS=\"mp4a.40.2\",VIDEO=\"audio_only\"
+audio_only.m3u8
+";
+        let (_, pl) = m3u8_rs::parse_master_playlist(playlist).unwrap();
+        assert_eq!(
+            select_variant(&pl, &StreamOptions::default()),
+            Some("chunked.m3u8")
+        );
+        let options = StreamOptions {
+            quality: Some(720),
+            ..Default::default()
+        };
+        assert_eq!(select_variant(&pl, &options), Some("720p60.m3u8"));
+        let options = StreamOptions {
+            quality: Some(480),
+            ..Default::default()
+        };
+        assert_eq!(select_variant(&pl, &options), Some("chunked.m3u8"));
+    }
+
+    #[test]
+    fn list_variants() {
+        let playlist = b"#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=6000000,RESOLUTION=1920x1080,VIDEO=\"chunked\"
+chunked.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=3000000,RESOLUTION=1280x720,CODECS=\"avc1.4D401F,mp4a.40.2\"
+720p60.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=160000,CODECS=\"mp4a.40.2\",VIDEO=\"audio_only\"
+audio_only.m3u8
+";
+        let (_, pl) = m3u8_rs::parse_master_playlist(playlist).unwrap();
+        let streams = streams(&pl);
+        let names: Vec<_> = streams.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["chunked", "720p60", "audio_only"]);
+        assert_eq!(streams[1].quality, Some(720));
+        assert_eq!(streams[1].codec, Some(Codec::Avc));
+        assert_eq!(streams[2].quality, None);
+    }
+}
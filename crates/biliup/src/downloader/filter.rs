@@ -0,0 +1,245 @@
+//! Rules for files too small to be worth keeping, such as the fragments a flapping stream
+//! leaves behind. A [`SegmentFilter`] checks every finished file before it is reported,
+//! on the thread of a [`PostProcessor`](crate::downloader::postprocess::PostProcessor)
+//! when it merges files.
+
+use crate::downloader::event::{RecordingEvent, SegmentInfo};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{error, info};
+
+/// What becomes of a file below the minimum size or duration.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Undersized {
+    /// Keeps the file without reporting it, so it isn't uploaded.
+    #[default]
+    Skip,
+    Delete,
+    /// Holds the file back and prepends it to the next one with ffmpeg.
+    Merge,
+}
+
+impl Undersized {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Undersized::Skip => "skip",
+            Undersized::Delete => "delete",
+            Undersized::Merge => "merge",
+        }
+    }
+}
+
+impl std::str::FromStr for Undersized {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Undersized::Skip),
+            "delete" => Ok(Undersized::Delete),
+            "merge" => Ok(Undersized::Merge),
+            _ => Err(format!("unknown action for undersized segments: {s}")),
+        }
+    }
+}
+
+/// Checks finished files against a minimum size and duration. Clones share the file held
+/// back for [`Undersized::Merge`], which is reported as it is by [`SegmentFilter::flush`]
+/// once the recording ended without a next file.
+#[derive(Debug, Clone, Default)]
+pub struct SegmentFilter {
+    /// Bytes a file needs at least.
+    pub min_size: Option<u64>,
+    /// Stream time a file needs at least.
+    pub min_duration: Option<Duration>,
+    pub undersized: Undersized,
+    pending: Arc<Mutex<Option<SegmentInfo>>>,
+}
+
+impl SegmentFilter {
+    pub fn new(
+        min_size: Option<u64>,
+        min_duration: Option<Duration>,
+        undersized: Undersized,
+    ) -> Self {
+        Self {
+            min_size,
+            min_duration,
+            undersized,
+            pending: Default::default(),
+        }
+    }
+
+    /// Whether undersized files are merged, which runs ffmpeg.
+    pub fn merges(&self) -> bool {
+        self.undersized == Undersized::Merge
+    }
+
+    pub fn is_undersized(&self, segment: &SegmentInfo) -> bool {
+        self.min_size.is_some_and(|min| segment.size < min)
+            || self.min_duration.is_some_and(|min| segment.duration < min)
+    }
+
+    /// Applies the rules to a file that was just closed, returning the files to report in
+    /// order. A file held back earlier is merged into it first, or reported on its own
+    /// when that fails.
+    pub fn apply(
+        &self,
+        mut segment: SegmentInfo,
+        emit: impl Fn(RecordingEvent),
+    ) -> Vec<SegmentInfo> {
+        let mut report = Vec::new();
+        let pending = self.pending.lock().unwrap().take();
+        if let Some(pending) = pending {
+            match merge(&pending.path, &segment.path) {
+                Ok(()) => {
+                    info!(
+                        "Merged {} into {}",
+                        pending.path.display(),
+                        segment.path.display()
+                    );
+                    segment.started_at = pending.started_at;
+                    segment.duration += pending.duration;
+                    segment.size = fs::metadata(&segment.path).map_or(0, |m| m.len());
+                }
+                Err(e) => {
+                    error!("Unable to merge {}: {e}", pending.path.display());
+                    emit(RecordingEvent::Error {
+                        message: format!("Unable to merge {}: {e}", pending.path.display()),
+                    });
+                    report.push(pending);
+                }
+            }
+        }
+        if !self.is_undersized(&segment) {
+            report.push(segment);
+            return report;
+        }
+        info!(
+            "{} is undersized ({} bytes, {:.1?}), {}",
+            segment.path.display(),
+            segment.size,
+            segment.duration,
+            self.undersized.as_str()
+        );
+        match self.undersized {
+            Undersized::Skip => {}
+            Undersized::Delete => {
+                if let Err(e) = fs::remove_file(&segment.path) {
+                    error!("Unable to delete {}: {e}", segment.path.display());
+                }
+            }
+            Undersized::Merge => *self.pending.lock().unwrap() = Some(segment.clone()),
+        }
+        emit(RecordingEvent::SegmentUndersized {
+            segment,
+            action: self.undersized,
+        });
+        report
+    }
+
+    /// Takes the file held back for merging, to be reported once no file follows.
+    pub fn flush(&self) -> Option<SegmentInfo> {
+        let pending = self.pending.lock().unwrap().take()?;
+        info!("Nothing to merge {} into", pending.path.display());
+        Some(pending)
+    }
+}
+
+/// Replaces `second` with the concatenation of `first` and `second`, deleting `first`.
+fn merge(first: &Path, second: &Path) -> io::Result<()> {
+    let entry = |path: &Path| -> io::Result<String> {
+        let path = path
+            .canonicalize()?
+            .to_string_lossy()
+            .replace('\'', r"'\''");
+        Ok(format!("file '{path}'\n"))
+    };
+    let list = second.with_extension("concat.txt");
+    fs::write(&list, entry(first)? + &entry(second)?)?;
+    let extension = second.extension().unwrap_or_default().to_string_lossy();
+    let output = second.with_extension(format!("merge.{extension}"));
+    let status = Command::new("ffmpeg")
+        .args(["-hide_banner", "-loglevel", "error", "-y"])
+        .args(["-f", "concat", "-safe", "0", "-i"])
+        .arg(&list)
+        .args(["-map", "0", "-c", "copy"])
+        .arg(&output)
+        .status();
+    let _ = fs::remove_file(&list);
+    let status = status?;
+    if !status.success() {
+        let _ = fs::remove_file(&output);
+        return Err(io::Error::other(format!("ffmpeg exited with {status}")));
+    }
+    fs::rename(&output, second)?;
+    fs::remove_file(first)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader::testing::{TempDir, collect_events, segment};
+
+    #[test]
+    fn drop_undersized_segments() -> anyhow::Result<()> {
+        let dir = TempDir::new("filter")?;
+        let segment = |name: &str, size: u64, secs: u64| -> io::Result<SegmentInfo> {
+            let path = dir.join(name);
+            fs::write(&path, vec![0; size as usize])?;
+            Ok(segment(path, size, Duration::from_secs(secs)))
+        };
+        let (handler, events) = collect_events();
+        let emit = |event| handler(&event);
+
+        let filter = SegmentFilter::new(Some(10), Some(Duration::from_secs(5)), Undersized::Delete);
+        let kept = filter.apply(segment("big.flv", 10, 5)?, emit);
+        let short = filter.apply(segment("short.flv", 10, 1)?, emit);
+        let small = segment("small.flv", 1, 5)?;
+        let small_path = small.path.clone();
+        let small = filter.apply(small, emit);
+
+        assert!(matches!(&kept[..], [segment] if segment.size == 10));
+        assert!(short.is_empty() && small.is_empty() && !small_path.exists());
+        assert!(matches!(
+            &events.lock().unwrap()[..],
+            [
+                RecordingEvent::SegmentUndersized {
+                    action: Undersized::Delete,
+                    ..
+                },
+                RecordingEvent::SegmentUndersized { .. }
+            ]
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn report_held_back_segments() -> anyhow::Result<()> {
+        let dir = TempDir::new("filter-merge")?;
+        let segment = |name: &str, size: u64| -> io::Result<SegmentInfo> {
+            let path = dir.join(name);
+            fs::write(&path, vec![0; size as usize])?;
+            Ok(segment(path, size, Duration::from_secs(1)))
+        };
+        let filter = SegmentFilter::new(Some(10), None, Undersized::Merge);
+        let held = filter.apply(segment("a.flv", 1)?, |_| {});
+        // Files of zeros can't be merged, both are reported.
+        let unmerged = filter.apply(segment("b.flv", 10)?, |_| {});
+        let last = filter.apply(segment("c.flv", 1)?, |_| {});
+        let flushed = filter.flush();
+
+        assert!(filter.merges() && held.is_empty() && last.is_empty());
+        let names: Vec<_> = unmerged.iter().map(|s| s.path.file_name()).collect();
+        assert_eq!(names, [Some("a.flv".as_ref()), Some("b.flv".as_ref())]);
+        assert!(flushed.is_some_and(|segment| segment.path == dir.join("c.flv")));
+        assert!(filter.flush().is_none());
+        Ok(())
+    }
+}
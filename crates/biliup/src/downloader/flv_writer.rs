@@ -9,7 +9,7 @@ use serde::Serialize;
 use std::fs::File;
 use std::io::{BufWriter, Write};
 
-use tracing::info;
+use tracing::{error, info};
 
 const FLV_HEADER: [u8; 9] = [
     0x46, // 'F'
@@ -36,6 +36,7 @@ impl FlvFile {
     }
 
     pub fn create_new(&mut self) -> std::io::Result<()> {
+        self.buf_writer.flush()?;
         self.file.rename();
         let path = self.file.create()?;
         self.buf_writer = Self::create(path)?;
@@ -92,7 +93,10 @@ impl FlvFile {
 
 impl Drop for FlvFile {
     fn drop(&mut self) {
-        self.file.rename()
+        if let Err(e) = self.buf_writer.flush() {
+            error!("flush {} {e}", self.file.path.display())
+        }
+        self.file.close()
     }
 }
 
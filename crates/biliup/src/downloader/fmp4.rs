@@ -0,0 +1,466 @@
+//! Muxes the separate video and audio fragmented MP4 files of a DASH stream into a
+//! single fragmented MP4, by rewriting boxes instead of remuxing samples.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const VIDEO_TRACK_ID: u32 = 1;
+const AUDIO_TRACK_ID: u32 = 2;
+
+/// A `moof` with the `mdat` boxes following it.
+struct Fragment {
+    moof: Vec<u8>,
+    offset: u64,
+    mdats: Vec<(u64, u64)>,
+    /// Decode time of the fragment in seconds, used to interleave the tracks.
+    time: f64,
+}
+
+struct Track {
+    file: File,
+    ftyp: Vec<u8>,
+    moov: Vec<u8>,
+    fragments: Vec<Fragment>,
+}
+
+impl Track {
+    fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut ftyp = Vec::new();
+        let mut moov = Vec::new();
+        let mut fragments: Vec<Fragment> = Vec::new();
+        let mut pos = 0;
+        while pos < len {
+            file.seek(SeekFrom::Start(pos))?;
+            let (kind, size) = read_box_header(&mut file, len - pos)?;
+            match &kind {
+                b"ftyp" => ftyp = read_box(&mut file, pos, size)?,
+                b"moov" => moov = read_box(&mut file, pos, size)?,
+                b"moof" => fragments.push(Fragment {
+                    moof: read_box(&mut file, pos, size)?,
+                    offset: pos,
+                    mdats: Vec::new(),
+                    time: 0.,
+                }),
+                b"mdat" => match fragments.last_mut() {
+                    Some(fragment) => fragment.mdats.push((pos, size)),
+                    None => return Err(invalid("mdat before moof, not a fragmented mp4")),
+                },
+                // sidx, styp, free and mfra no longer match the muxed file.
+                _ => {}
+            }
+            pos += size;
+        }
+        if moov.is_empty() {
+            return Err(invalid("missing moov"));
+        }
+        let moov = moov[8..].to_vec();
+        let timescale = child(&moov, &[b"trak", b"mdia", b"mdhd"])
+            .map(|mdhd| {
+                let at = if mdhd[0] == 1 { 20 } else { 12 };
+                read_u32(mdhd, at)
+            })
+            .filter(|t| *t > 0)
+            .ok_or_else(|| invalid("missing mdhd"))?;
+        for fragment in &mut fragments {
+            let decode_time = child(&fragment.moof[8..], &[b"traf", b"tfdt"])
+                .map(|tfdt| {
+                    if tfdt[0] == 1 {
+                        read_u64(tfdt, 4)
+                    } else {
+                        read_u32(tfdt, 4) as u64
+                    }
+                })
+                .unwrap_or_default();
+            fragment.time = decode_time as f64 / timescale as f64;
+        }
+        Ok(Self {
+            file,
+            ftyp,
+            moov,
+            fragments,
+        })
+    }
+}
+
+/// Muxes the fragmented MP4 files `video` and `audio`, each holding a single track,
+/// into `out`.
+pub fn mux(video: &Path, audio: &Path, out: &Path) -> io::Result<()> {
+    let video = Track::open(video)?;
+    let audio = Track::open(audio)?;
+    let moov = merge_moov(&video.moov, &audio.moov)?;
+    let mut writer = BufWriter::new(File::create(out)?);
+    writer.write_all(&video.ftyp)?;
+    writer.write_all(&moov)?;
+
+    let mut position = (video.ftyp.len() + moov.len()) as u64;
+    let (mut v, mut a) = (0, 0);
+    let mut sequence = 1;
+    loop {
+        let take_video = match (video.fragments.get(v), audio.fragments.get(a)) {
+            (Some(vf), Some(af)) => vf.time <= af.time,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => break,
+        };
+        let (track, fragment, track_id) = if take_video {
+            v += 1;
+            (&video, &video.fragments[v - 1], VIDEO_TRACK_ID)
+        } else {
+            a += 1;
+            (&audio, &audio.fragments[a - 1], AUDIO_TRACK_ID)
+        };
+        let moof = rewrite_moof(
+            &fragment.moof,
+            track_id,
+            sequence,
+            position,
+            fragment.offset,
+        )?;
+        writer.write_all(&moof)?;
+        position += moof.len() as u64;
+        for &(offset, size) in &fragment.mdats {
+            let mut file = &track.file;
+            file.seek(SeekFrom::Start(offset))?;
+            let copied = io::copy(&mut file.take(size), &mut writer)?;
+            if copied != size {
+                return Err(invalid("truncated mdat"));
+            }
+            position += size;
+        }
+        sequence += 1;
+    }
+    writer.flush()
+}
+
+/// Builds a moov with the video and audio tracks from the payloads of both moov boxes.
+fn merge_moov(video: &[u8], audio: &[u8]) -> io::Result<Vec<u8>> {
+    let (video_timescale, video_duration) =
+        movie_duration(video).ok_or_else(|| invalid("missing mvhd"))?;
+    let (audio_timescale, audio_duration) =
+        movie_duration(audio).ok_or_else(|| invalid("missing mvhd"))?;
+    let audio_duration = audio_duration * video_timescale as u64 / audio_timescale.max(1) as u64;
+    let mut audio_trak = child_box(audio, b"trak")
+        .ok_or_else(|| invalid("missing audio trak"))?
+        .to_vec();
+    set_track_id(
+        &mut audio_trak,
+        AUDIO_TRACK_ID,
+        Some((video_timescale, audio_timescale)),
+    );
+    let mut audio_trex = child(audio, &[b"mvex"])
+        .and_then(|mvex| child_box(mvex, b"trex"))
+        .ok_or_else(|| invalid("missing audio trex"))?
+        .to_vec();
+    write_u32(&mut audio_trex, 12, AUDIO_TRACK_ID);
+
+    let mut payload = Vec::new();
+    let mut has_trak = false;
+    for (kind, b) in boxes(video) {
+        let mut b = b.to_vec();
+        match &kind {
+            b"mvhd" => {
+                set_movie_duration(&mut b, video_duration.max(audio_duration));
+                let next_track_id = b.len() - 4;
+                write_u32(&mut b, next_track_id, AUDIO_TRACK_ID + 1);
+            }
+            b"trak" if has_trak => continue,
+            b"trak" => {
+                has_trak = true;
+                set_track_id(&mut b, VIDEO_TRACK_ID, None);
+                payload.extend_from_slice(&b);
+                b = audio_trak.clone();
+            }
+            b"mvex" => {
+                let mut mvex = Vec::new();
+                for (kind, child) in boxes(&b[8..]) {
+                    if &kind == b"trex" {
+                        let mut trex = child.to_vec();
+                        write_u32(&mut trex, 12, VIDEO_TRACK_ID);
+                        mvex.extend_from_slice(&trex);
+                        mvex.extend_from_slice(&audio_trex);
+                    } else if &kind != b"mehd" {
+                        mvex.extend_from_slice(child);
+                    }
+                }
+                b = make_box(b"mvex", &mvex);
+            }
+            _ => {}
+        }
+        payload.extend_from_slice(&b);
+    }
+    if !has_trak {
+        return Err(invalid("missing video trak"));
+    }
+    Ok(make_box(b"moov", &payload))
+}
+
+/// Sets the track id in `tkhd`, converting its duration to the movie timescale of the
+/// muxed file when `timescales` (new, old) is given.
+fn set_track_id(trak: &mut [u8], track_id: u32, timescales: Option<(u32, u32)>) {
+    let Some(offset) = child_offset(&trak[8..], &[b"tkhd"]).map(|o| o + 8) else {
+        return;
+    };
+    let tkhd = &mut trak[offset..];
+    let (id_at, duration_at) = if tkhd[8] == 1 { (28, 36) } else { (20, 28) };
+    write_u32(tkhd, id_at, track_id);
+    if let Some((new, old)) = timescales {
+        if tkhd[8] == 1 {
+            let duration = read_u64(tkhd, duration_at);
+            write_u64(tkhd, duration_at, duration * new as u64 / old.max(1) as u64);
+        } else {
+            let duration = read_u32(tkhd, duration_at) as u64;
+            write_u32(
+                tkhd,
+                duration_at,
+                (duration * new as u64 / old.max(1) as u64) as u32,
+            );
+        }
+    }
+}
+
+fn movie_duration(moov: &[u8]) -> Option<(u32, u64)> {
+    let mvhd = child(moov, &[b"mvhd"])?;
+    Some(if mvhd[0] == 1 {
+        (read_u32(mvhd, 20), read_u64(mvhd, 24))
+    } else {
+        (read_u32(mvhd, 12), read_u32(mvhd, 16) as u64)
+    })
+}
+
+fn set_movie_duration(mvhd: &mut [u8], duration: u64) {
+    if mvhd[8] == 1 {
+        write_u64(mvhd, 32, duration);
+    } else {
+        write_u32(mvhd, 24, duration.min(u32::MAX as u64) as u32);
+    }
+}
+
+/// Renumbers the fragment and its track, and moves explicit base data offsets along with it.
+fn rewrite_moof(
+    moof: &[u8],
+    track_id: u32,
+    sequence: u32,
+    position: u64,
+    offset: u64,
+) -> io::Result<Vec<u8>> {
+    let mut payload = Vec::new();
+    for (kind, b) in boxes(&moof[8..]) {
+        let mut b = b.to_vec();
+        match &kind {
+            b"mfhd" => write_u32(&mut b, 12, sequence),
+            b"traf" => {
+                let offset_in_traf = child_offset(&b[8..], &[b"tfhd"])
+                    .map(|o| o + 8)
+                    .ok_or_else(|| invalid("missing tfhd"))?;
+                let tfhd = &mut b[offset_in_traf..];
+                write_u32(tfhd, 12, track_id);
+                // base-data-offset-present
+                if read_u32(tfhd, 8) & 0x1 != 0 {
+                    let base = read_u64(tfhd, 16);
+                    write_u64(tfhd, 16, base + position - offset);
+                }
+            }
+            _ => {}
+        }
+        payload.extend_from_slice(&b);
+    }
+    Ok(make_box(b"moof", &payload))
+}
+
+fn read_box_header(file: &mut File, remaining: u64) -> io::Result<([u8; 4], u64)> {
+    let mut header = [0; 8];
+    file.read_exact(&mut header)?;
+    let kind = [header[4], header[5], header[6], header[7]];
+    let size = match read_u32(&header, 0) {
+        0 => remaining,
+        1 => {
+            let mut large = [0; 8];
+            file.read_exact(&mut large)?;
+            u64::from_be_bytes(large)
+        }
+        size => size as u64,
+    };
+    if size < 8 || size > remaining {
+        return Err(invalid("invalid box size"));
+    }
+    Ok((kind, size))
+}
+
+fn read_box(file: &mut File, pos: u64, size: u64) -> io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(pos))?;
+    let mut buf = vec![0; size as usize];
+    file.read_exact(&mut buf)?;
+    if read_u32(&buf, 0) == 1 {
+        // Nested boxes are parsed with 32-bit sizes, the only ones large enough are mdat.
+        return Err(invalid("64-bit box size"));
+    }
+    Ok(buf)
+}
+
+/// Iterates the boxes in `data`, yielding their type and bytes including the header.
+pub(crate) fn boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        if pos + 8 > data.len() {
+            return None;
+        }
+        let size = (read_u32(data, pos) as usize).clamp(8, data.len() - pos);
+        let kind = [data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]];
+        let b = &data[pos..pos + size];
+        pos += size;
+        Some((kind, b))
+    })
+}
+
+fn child_box<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes(data).find(|(k, _)| k == kind).map(|(_, b)| b)
+}
+
+/// Payload of the box at `path` below `data`.
+pub(crate) fn child<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    let offset = child_offset(data, path)?;
+    let size = read_u32(data, offset) as usize;
+    data.get(offset + 8..offset + size)
+}
+
+/// Offset of the box at `path` below `data`.
+fn child_offset(data: &[u8], path: &[&[u8; 4]]) -> Option<usize> {
+    let (first, rest) = path.split_first()?;
+    let mut pos = 0;
+    for (kind, b) in boxes(data) {
+        if &kind == *first {
+            if rest.is_empty() {
+                return Some(pos);
+            }
+            return child_offset(&b[8..], rest).map(|o| pos + 8 + o);
+        }
+        pos += b.len();
+    }
+    None
+}
+
+fn make_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut b = Vec::with_capacity(payload.len() + 8);
+    b.extend_from_slice(&(payload.len() as u32 + 8).to_be_bytes());
+    b.extend_from_slice(kind);
+    b.extend_from_slice(payload);
+    b
+}
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], at: usize) -> u64 {
+    u64::from_be_bytes(data[at..at + 8].try_into().unwrap())
+}
+
+fn write_u32(data: &mut [u8], at: usize, value: u32) {
+    data[at..at + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+fn write_u64(data: &mut [u8], at: usize, value: u64) {
+    data[at..at + 8].copy_from_slice(&value.to_be_bytes());
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader::testing::TempDir;
+
+    fn full_box(kind: &[u8; 4], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+        let mut b = ((version as u32) << 24 | flags).to_be_bytes().to_vec();
+        b.extend_from_slice(payload);
+        make_box(kind, &b)
+    }
+
+    fn track(timescale: u32, fragments: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut mvhd = vec![0; 96];
+        write_u32(&mut mvhd, 8, 1000);
+        write_u32(&mut mvhd, 92, 2);
+        let mut tkhd = vec![0; 80];
+        write_u32(&mut tkhd, 8, 1);
+        let mut mdhd = vec![0; 20];
+        write_u32(&mut mdhd, 8, timescale);
+        let trak = make_box(
+            b"trak",
+            &[
+                full_box(b"tkhd", 0, 3, &tkhd),
+                make_box(b"mdia", &full_box(b"mdhd", 0, 0, &mdhd)),
+            ]
+            .concat(),
+        );
+        let mvex = make_box(b"mvex", &full_box(b"trex", 0, 0, &[0, 0, 0, 1, 0, 0, 0, 1]));
+        let mut file = make_box(b"ftyp", b"iso5");
+        file.extend(make_box(
+            b"moov",
+            &[full_box(b"mvhd", 0, 0, &mvhd), trak, mvex].concat(),
+        ));
+        file.extend(make_box(b"sidx", &[0; 4]));
+        for (i, (decode_time, data)) in fragments.iter().enumerate() {
+            let traf = make_box(
+                b"traf",
+                &[
+                    full_box(b"tfhd", 0, 0x020000, &1u32.to_be_bytes()),
+                    full_box(b"tfdt", 0, 0, &decode_time.to_be_bytes()),
+                ]
+                .concat(),
+            );
+            let mfhd = full_box(b"mfhd", 0, 0, &(i as u32 + 1).to_be_bytes());
+            file.extend(make_box(b"moof", &[mfhd, traf].concat()));
+            file.extend(make_box(b"mdat", data));
+        }
+        file
+    }
+
+    #[test]
+    fn mux_interleaves_fragments() -> io::Result<()> {
+        let dir = TempDir::new("fmp4")?;
+        let (video, audio, out) = (dir.join("v.m4s"), dir.join("a.m4s"), dir.join("out.mp4"));
+        std::fs::write(&video, track(1000, &[(0, b"v0"), (2000, b"v1")]))?;
+        std::fs::write(
+            &audio,
+            track(48000, &[(0, b"a0"), (48000, b"a1"), (96000, b"a2")]),
+        )?;
+        mux(&video, &audio, &out)?;
+        let muxed = std::fs::read(&out)?;
+
+        let moov = child(&muxed, &[b"moov"]).unwrap();
+        assert_eq!(boxes(moov).filter(|(k, _)| k == b"trak").count(), 2);
+        let mvex = child(moov, &[b"mvex"]).unwrap();
+        let trex: Vec<_> = boxes(mvex).map(|(_, b)| read_u32(b, 12)).collect();
+        assert_eq!(trex, [VIDEO_TRACK_ID, AUDIO_TRACK_ID]);
+
+        let mut fragments = Vec::new();
+        let mut moof = None;
+        for (kind, b) in boxes(&muxed) {
+            match &kind {
+                b"moof" => moof = Some(b),
+                b"mdat" => {
+                    let moof = &moof.unwrap()[8..];
+                    let sequence = read_u32(child(moof, &[b"mfhd"]).unwrap(), 4);
+                    let track_id = read_u32(child(moof, &[b"traf", b"tfhd"]).unwrap(), 4);
+                    fragments.push((sequence, track_id, b[8..].to_vec()));
+                }
+                b"sidx" => panic!("sidx is not dropped"),
+                _ => {}
+            }
+        }
+        let expected: Vec<(u32, u32, Vec<u8>)> = vec![
+            (1, 1, b"v0".to_vec()),
+            (2, 2, b"a0".to_vec()),
+            (3, 2, b"a1".to_vec()),
+            (4, 1, b"v1".to_vec()),
+            (5, 2, b"a2".to_vec()),
+        ];
+        assert_eq!(fragments, expected);
+        Ok(())
+    }
+}
@@ -1,11 +1,15 @@
 use crate::downloader::error::Result;
+use crate::downloader::event::{Resolution, SplitReason};
+use crate::downloader::probe;
 use crate::downloader::util::{LifecycleFile, Segmentable};
-use m3u8_rs::Playlist;
+use bytes::Bytes;
+use chrono::{DateTime, FixedOffset};
+use m3u8_rs::{DateRange, MediaPlaylist, MediaSegment, Playlist};
 
 use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::time::Duration;
-use tracing::{debug, info, warn};
+use tracing::{debug, error, info, warn};
 use url::Url;
 
 use crate::client::StatelessClient;
@@ -16,18 +20,43 @@ pub async fn download(
     file: LifecycleFile,
     mut splitting: Segmentable,
 ) -> Result<()> {
-    info!("Downloading {}...", url);
-    let resp = client.retryable(url).await?;
-    info!("{}", resp.status());
-    // let mut resp = resp.bytes_stream();
-    let bytes = resp.bytes().await?;
     let mut ts_file = TsFile::new(file)?;
+    download_to(url, None, client, &mut ts_file, &mut splitting).await
+}
+
+/// Appends the segments of the playlist at `url` to `ts_file` until the stream ends,
+/// starting with `playlist` if it was fetched already. Segments up to
+/// [`TsFile::last_sequence`], written by an earlier connection to the same stream, are
+/// skipped.
+pub(crate) async fn download_to(
+    url: &str,
+    playlist: Option<Bytes>,
+    client: &StatelessClient,
+    ts_file: &mut TsFile,
+    splitting: &mut Segmentable,
+) -> Result<()> {
+    info!("Downloading {}...", url);
+    let bytes = match playlist {
+        Some(playlist) => playlist,
+        None => {
+            let resp = client.retryable(url).await?;
+            info!("{}", resp.status());
+            resp.bytes().await?
+        }
+    };
 
     let mut media_url = Url::parse(url)?;
     let mut pl = match m3u8_rs::parse_playlist(&bytes) {
         Ok((_i, Playlist::MasterPlaylist(pl))) => {
             info!("Master playlist:\n{:#?}", pl);
             media_url = media_url.join(&pl.variants[0].uri)?;
+            ts_file.file.resolution = pl.variants[0].resolution.map(|r| Resolution {
+                width: r.width as u32,
+                height: r.height as u32,
+            });
+            if let Some(codecs) = &pl.variants[0].codecs {
+                (ts_file.file.video_codec, ts_file.file.audio_codec) = probe::hls_codecs(codecs);
+            }
             info!("media url: {media_url}");
             let resp = client.retryable(media_url.as_str()).await?;
             let bs = resp.bytes().await?;
@@ -48,40 +77,70 @@ pub async fn download(
         }
         Err(e) => panic!("Parsing error: \n{e}"),
     };
-    let mut previous_last_segment = 0;
+    let mut previous_last_segment = resume_from(ts_file.last_sequence, &pl);
+    let mut init_uri = None;
+    let mut ads = AdFilter::default();
     loop {
         if pl.segments.is_empty() {
             info!("Segments array is empty - stream finished");
             break;
         }
-        let mut seq = pl.media_sequence;
-        for segment in &pl.segments {
+        for (seq, segment) in (pl.media_sequence..).zip(pl.segments.iter()) {
             if seq > previous_last_segment {
                 if (previous_last_segment > 0) && (seq > (previous_last_segment + 1)) {
                     warn!("SEGMENT INFO SKIPPED");
                 }
+                if ads.is_ad(segment) {
+                    debug!("Skip stitched ad segment {}", segment.uri);
+                    previous_last_segment = seq;
+                    ts_file.last_sequence = seq;
+                    continue;
+                }
                 debug!("Yield segment");
-                if segment.discontinuity {
+                if ads.splits(segment) {
                     warn!("#EXT-X-DISCONTINUITY");
+                    ts_file.file.split_reason = SplitReason::StreamChanged;
                     ts_file.create_new()?;
                     // splitting = Segment::from_seg(splitting);
                     splitting.reset();
                 }
-                let length = download_to_file(
-                    media_url.join(&segment.uri)?,
-                    client,
-                    &mut ts_file.buf_writer,
-                )
-                .await?;
+                // fMP4 streams carry an initialization section that every file has to start with.
+                if let Some(map) = &segment.map
+                    && init_uri.as_ref() != Some(&map.uri)
+                {
+                    let init = client
+                        .retryable(media_url.join(&map.uri)?.as_str())
+                        .await?
+                        .bytes()
+                        .await?;
+                    ts_file.set_init(init)?;
+                    init_uri = Some(map.uri.clone());
+                }
+                let segment_url = media_url.join(&segment.uri)?;
+                let length = if ts_file.probed {
+                    download_to_file(segment_url, client, &mut ts_file.buf_writer).await?
+                } else {
+                    let mut data = Vec::new();
+                    let length = download_to_file(segment_url, client, &mut data).await?;
+                    ts_file.buf_writer.write_all(&data)?;
+                    ts_file.probe(&data);
+                    length
+                };
+                ts_file.written = true;
+                ts_file.segments += 1;
                 splitting.increase_size(length);
                 splitting.increase_time(Duration::from_secs(segment.duration as u64));
+                ts_file.file.duration = splitting.duration();
+                ts_file.file.check_space()?;
                 if splitting.needed() {
+                    ts_file.file.split_reason = SplitReason::Policy;
+                    splitting.rename_next(&mut ts_file.file);
                     ts_file.create_new()?;
                     splitting.reset();
                 }
                 previous_last_segment = seq;
+                ts_file.last_sequence = seq;
             }
-            seq += 1;
         }
         let resp = client.retryable(media_url.as_str()).await?;
         let bs = resp.bytes().await?;
@@ -93,7 +152,24 @@ pub async fn download(
     Ok(())
 }
 
-async fn download_to_file(url: Url, client: &StatelessClient, out: &mut impl Write) -> Result<u64> {
+/// The sequence number of the last segment already written before `pl`, `previous`
+/// unless the playlist ends before it, as with a stream that started over.
+fn resume_from(previous: u64, pl: &MediaPlaylist) -> u64 {
+    if pl.media_sequence + pl.segments.len() as u64 <= previous {
+        warn!(
+            "media sequence went back from {previous} to {}",
+            pl.media_sequence
+        );
+        return 0;
+    }
+    previous
+}
+
+pub(crate) async fn download_to_file(
+    url: Url,
+    client: &StatelessClient,
+    out: &mut impl Write,
+) -> Result<u64> {
     debug!("url: {url}");
     let mut response = client.retryable(url.as_str()).await?;
     let mut length: u64 = 0;
@@ -108,9 +184,67 @@ async fn download_to_file(url: Url, client: &StatelessClient, out: &mut impl Wri
     Ok(length)
 }
 
+/// Skips the ads Twitch stitches into live playlists, announced by `#EXT-X-DATERANGE`
+/// tags with the `twitch-stitched-ad` class and spanning the ad segments.
+#[derive(Default)]
+struct AdFilter {
+    ranges: Vec<(DateTime<FixedOffset>, DateTime<FixedOffset>)>,
+    /// Whether the segment before was a skipped ad.
+    after_ad: bool,
+}
+
+impl AdFilter {
+    fn is_ad(&mut self, segment: &MediaSegment) -> bool {
+        let ad = self.in_range(segment);
+        self.after_ad |= ad;
+        ad
+    }
+
+    /// Whether `segment`, which isn't an ad, starts a new file. The discontinuity ending
+    /// an ad break only separates the skipped ads from the stream, which carries on.
+    fn splits(&mut self, segment: &MediaSegment) -> bool {
+        let after_ad = std::mem::take(&mut self.after_ad);
+        segment.discontinuity && !after_ad
+    }
+
+    fn in_range(&mut self, segment: &MediaSegment) -> bool {
+        if let Some(range) = &segment.daterange
+            && is_stitched_ad(range)
+        {
+            let end = range.end_date.or_else(|| {
+                range
+                    .duration
+                    .map(|d| range.start_date + chrono::Duration::milliseconds((d * 1000.) as i64))
+            });
+            match end {
+                Some(end) => self.ranges.push((range.start_date, end)),
+                // Without an end the ad lasts as long as the segment announcing it.
+                None => return true,
+            }
+        }
+        let Some(time) = segment.program_date_time else {
+            return false;
+        };
+        self.ranges.retain(|(_, end)| time < *end);
+        self.ranges.iter().any(|(start, _)| *start <= time)
+    }
+}
+
+fn is_stitched_ad(range: &DateRange) -> bool {
+    range.class.as_deref() == Some("twitch-stitched-ad") || range.id.starts_with("stitched-ad-")
+}
+
 pub struct TsFile {
     pub buf_writer: BufWriter<File>,
     pub file: LifecycleFile,
+    init: Option<Bytes>,
+    written: bool,
+    /// Number of media segments written over all files.
+    pub(crate) segments: u64,
+    /// Media sequence number of the last segment handled, kept across reconnects.
+    pub(crate) last_sequence: u64,
+    /// Whether the codecs of the current file were read off its media already.
+    probed: bool,
 }
 
 impl TsFile {
@@ -119,16 +253,62 @@ impl TsFile {
         Ok(Self {
             buf_writer: Self::create(path)?,
             file,
+            init: None,
+            written: false,
+            segments: 0,
+            last_sequence: 0,
+            probed: false,
         })
     }
 
     pub fn create_new(&mut self) -> std::io::Result<()> {
+        self.buf_writer.flush()?;
         self.file.rename();
         let path = self.file.create()?;
         self.buf_writer = Self::create(path)?;
+        self.written = false;
+        self.probed = false;
+        if let Some(init) = &self.init {
+            self.buf_writer.write_all(init)?;
+        }
         Ok(())
     }
 
+    /// Sets the fMP4 initialization section, starting a new file if media was
+    /// already written with a different one.
+    pub fn set_init(&mut self, init: Bytes) -> std::io::Result<()> {
+        if self.init.as_ref() == Some(&init) {
+            return Ok(());
+        }
+        self.init = Some(init);
+        self.probed = false;
+        if self.written {
+            self.file.split_reason = SplitReason::StreamChanged;
+            return self.create_new();
+        }
+        // Nothing but an outdated initialization section can be in the file yet.
+        let path = if self.file.extension == "mp4" {
+            self.file.path.clone()
+        } else {
+            // fMP4 media, which came from a playlist that looked like MPEG-TS.
+            self.file.reopen_as("mp4")?.to_path_buf()
+        };
+        self.buf_writer = Self::create(path)?;
+        self.buf_writer
+            .write_all(self.init.as_deref().unwrap_or_default())
+    }
+
+    /// Takes over the codecs and picture size found in the initialization section, or
+    /// else in `segment`, keeping the ones the playlist announced otherwise.
+    fn probe(&mut self, segment: &[u8]) {
+        let media = probe::media_segment(self.init.as_deref().unwrap_or(segment));
+        let file = &mut self.file;
+        file.video_codec = media.video_codec.or(file.video_codec.take());
+        file.audio_codec = media.audio_codec.or(file.audio_codec.take());
+        file.resolution = media.resolution.or(file.resolution);
+        self.probed = true;
+    }
+
     fn create<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<BufWriter<File>> {
         let path = path.as_ref();
         let out = match File::create(path) {
@@ -147,7 +327,10 @@ impl TsFile {
 
 impl Drop for TsFile {
     fn drop(&mut self) {
-        self.file.rename()
+        if let Err(e) = self.buf_writer.flush() {
+            error!("flush {} {e}", self.file.path.display())
+        }
+        self.file.close()
     }
 }
 
@@ -166,6 +349,72 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn skip_stitched_ads() -> Result<()> {
+        let playlist = br#"#EXTM3U
+#EXT-X-VERSION:3
+#EXT-X-TARGETDURATION:2
+#EXT-X-MEDIA-SEQUENCE:100
+#EXT-X-PROGRAM-DATE-TIME:2024-01-01T00:00:00.000Z
+#EXTINF:2.000,live
+live-100.ts
+#EXT-X-DATERANGE:ID="stitched-ad-1",CLASS="twitch-stitched-ad",START-DATE="2024-01-01T00:00:02.000Z",DURATION=4.000
+#EXT-X-DISCONTINUITY
+#EXT-X-PROGRAM-DATE-TIME:2024-01-01T00:00:02.000Z
+#EXTINF:2.000,Amazon
+ad-101.ts
+#EXT-X-PROGRAM-DATE-TIME:2024-01-01T00:00:04.000Z
+#EXTINF:2.000,Amazon
+ad-102.ts
+#EXT-X-DISCONTINUITY
+#EXT-X-PROGRAM-DATE-TIME:2024-01-01T00:00:06.000Z
+#EXTINF:2.000,live
+live-103.ts
+#EXT-X-DISCONTINUITY
+#EXT-X-PROGRAM-DATE-TIME:2024-01-01T00:00:08.000Z
+#EXTINF:2.000,live
+live-104.ts
+"#;
+        let (_, pl) =
+            m3u8_rs::parse_media_playlist(playlist).map_err(|e| anyhow::anyhow!("{e}"))?;
+        let mut ads = super::AdFilter::default();
+        let mut kept = Vec::new();
+        for segment in &pl.segments {
+            if !ads.is_ad(segment) {
+                kept.push((segment.uri.as_str(), ads.splits(segment)));
+            }
+        }
+        // Only the discontinuity that isn't around the ads starts a new file.
+        assert_eq!(
+            kept,
+            [
+                ("live-100.ts", false),
+                ("live-103.ts", false),
+                ("live-104.ts", true)
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resume_after_reconnect() -> Result<()> {
+        let playlist = |sequence: u64| {
+            let text = format!(
+                "#EXTM3U\n#EXT-X-TARGETDURATION:2\n#EXT-X-MEDIA-SEQUENCE:{sequence}\n\
+                 #EXTINF:2.0,\na.ts\n#EXTINF:2.0,\nb.ts\n#EXTINF:2.0,\nc.ts\n"
+            );
+            m3u8_rs::parse_media_playlist(text.as_bytes())
+                .map(|(_, pl)| pl)
+                .map_err(|e| anyhow::anyhow!("{e}"))
+        };
+        // Still in the live window, the segments after 11 are new.
+        assert_eq!(super::resume_from(11, &playlist(10)?), 11);
+        assert_eq!(super::resume_from(0, &playlist(10)?), 0);
+        // The stream started over.
+        assert_eq!(super::resume_from(500, &playlist(0)?), 0);
+        Ok(())
+    }
+
     #[test]
     fn it_works() -> Result<()> {
         // download(
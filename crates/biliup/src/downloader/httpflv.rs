@@ -1,8 +1,11 @@
+use crate::downloader::event::SplitReason;
 use crate::downloader::flv_parser::{
-    AACPacketType, AVCPacketType, CodecId, FrameType, SoundFormat, TagData, TagHeader,
+    AACPacketType, AVCPacketType, CodecId, FrameType, SoundFormat, TagData, TagHeader, TagType,
     aac_audio_packet_header, avc_video_packet_header, script_data, tag_data, tag_header,
 };
 use crate::downloader::flv_writer::{FlvFile, FlvTag, TagDataHeader};
+use crate::downloader::probe;
+use crate::downloader::rtmp;
 use crate::downloader::util::{LifecycleFile, Segmentable};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use nom::{Err, IResult};
@@ -27,33 +30,102 @@ pub async fn download(connection: Connection, file: LifecycleFile, segment: Segm
 pub(crate) async fn parse_flv(
     mut connection: Connection,
     file: LifecycleFile,
-    mut segment: Segmentable,
+    segment: Segmentable,
 ) -> crate::downloader::error::Result<()> {
-    let mut flv_tags_cache: Vec<(TagHeader, Bytes, Bytes)> = Vec::new();
-
     let _previous_tag_size = connection.read_frame(4).await?;
+    FlvRecorder::new(file, segment)?.record(connection).await
+}
+
+/// Writes the tags of one or more connections to the same [`FlvFile`], splitting it at
+/// keyframes when `segment` says so.
+pub(crate) struct FlvRecorder {
+    out: FlvFile,
+    segment: Segmentable,
+    flv_tags_cache: Vec<(TagHeader, Bytes, Bytes)>,
+    on_meta_data: Option<(TagHeader, Bytes, Bytes)>,
+    aac_sequence_header: Option<(TagHeader, Bytes, Bytes)>,
+    h264_sequence_header: Option<(TagHeader, Bytes, Bytes)>,
+    prev_timestamp: u32,
+    create_new: bool,
+    /// Added to the timestamps of the current connection so that a reconnected stream
+    /// continues where the previous one stopped.
+    timestamp_offset: i64,
+    /// Timestamp of the last tag received, after applying `timestamp_offset`.
+    last_timestamp: Option<u32>,
+    /// Number of tags received over all connections.
+    pub(crate) tags: u64,
+}
+
+impl FlvRecorder {
+    pub(crate) fn new(
+        file: LifecycleFile,
+        mut segment: Segmentable,
+    ) -> crate::downloader::error::Result<Self> {
+        let out = FlvFile::new(file)?;
+        segment.set_size_position(9 + 4);
+        Ok(Self {
+            out,
+            segment,
+            flv_tags_cache: Vec::new(),
+            on_meta_data: None,
+            aac_sequence_header: None,
+            h264_sequence_header: None,
+            prev_timestamp: 0,
+            create_new: false,
+            timestamp_offset: 0,
+            last_timestamp: None,
+            tags: 0,
+        })
+    }
 
-    let mut out = FlvFile::new(file)?;
-    segment.set_size_position(9 + 4);
-    // let mut downloaded_size = 9 + 4;
-    let mut on_meta_data = None;
-    let mut aac_sequence_header = None;
-    let mut h264_sequence_header: Option<(TagHeader, Bytes, Bytes)> = None;
-    let mut prev_timestamp = 0;
-    let mut create_new = false;
-    loop {
-        let tag_header_bytes = connection.read_frame(11).await?;
-        if tag_header_bytes.is_empty() {
-            // let mut rdr = Cursor::new(tag_header_bytes);
-            // println!("{}", rdr.read_u32::<BigEndian>().unwrap());
-            break;
+    /// Reads tags from `connection`, positioned at the first tag header, until the
+    /// stream ends.
+    pub(crate) async fn record(
+        &mut self,
+        mut connection: Connection,
+    ) -> crate::downloader::error::Result<()> {
+        let mut rebase = self.last_timestamp.is_some();
+        loop {
+            let tag_header_bytes = connection.read_frame(11).await?;
+            if tag_header_bytes.is_empty() {
+                break;
+            }
+
+            let (_, mut tag_header) = map_parse_err(tag_header(&tag_header_bytes), "tag header")?;
+            let bytes = connection.read_frame(tag_header.data_size as usize).await?;
+            let previous_tag_size = connection.read_frame(4).await?;
+            self.continue_timestamps(&mut tag_header, std::mem::take(&mut rebase));
+            self.tags += 1;
+            self.write(tag_header, bytes, previous_tag_size)?;
         }
+        Ok(())
+    }
 
-        let (_, tag_header) = map_parse_err(tag_header(&tag_header_bytes), "tag header")?;
-        // write_tag_header(&mut out, &tag_header)?;
+    /// Moves the timestamp of `tag_header` by the offset of the current connection.
+    /// `rebase` is set for the first tag of a new connection, which starts over at its
+    /// own timestamps and continues right after the last tag received.
+    fn continue_timestamps(&mut self, tag_header: &mut TagHeader, rebase: bool) {
+        if let Some(last_timestamp) = self.last_timestamp
+            && rebase
+        {
+            self.timestamp_offset = last_timestamp as i64 + 1 - tag_header.timestamp as i64;
+            info!(
+                "{} continues at {}ms",
+                self.out.file.file_name,
+                last_timestamp + 1
+            );
+        }
+        tag_header.timestamp =
+            (tag_header.timestamp as i64 + self.timestamp_offset).clamp(0, u32::MAX as i64) as u32;
+        self.last_timestamp = Some(tag_header.timestamp);
+    }
 
-        let bytes = connection.read_frame(tag_header.data_size as usize).await?;
-        let previous_tag_size = connection.read_frame(4).await?;
+    fn write(
+        &mut self,
+        tag_header: TagHeader,
+        bytes: Bytes,
+        previous_tag_size: Bytes,
+    ) -> crate::downloader::error::Result<()> {
         // out.write(&bytes)?;
         let (i, flv_tag_data) = map_parse_err(
             tag_data(tag_header.tag_type, tag_header.data_size as usize)(&bytes),
@@ -65,12 +137,12 @@ pub(crate) async fn parse_flv(
                     let (_, packet_header) = aac_audio_packet_header(audio_data.sound_data)
                         .expect("Error in parsing aac audio packet header.");
                     if packet_header.packet_type == AACPacketType::SequenceHeader {
-                        if aac_sequence_header.is_some() {
+                        if self.aac_sequence_header.is_some() {
                             warn!("Unexpected aac sequence header tag. {tag_header:?}");
                             // panic!("Unexpected aac_sequence_header tag.");
-                            // create_new = true;
+                            // self.create_new = true;
                         }
-                        aac_sequence_header =
+                        self.aac_sequence_header =
                             Some((tag_header, bytes.clone(), previous_tag_size.clone()))
                     }
                     Some(packet_header.packet_type)
@@ -94,14 +166,14 @@ pub(crate) async fn parse_flv(
                     let (_, avc_video_header) = avc_video_packet_header(video_data.video_data)
                         .expect("Error in parsing avc video packet header.");
                     if avc_video_header.packet_type == AVCPacketType::SequenceHeader {
-                        if let Some((_, binary_data, _)) = &h264_sequence_header {
+                        if let Some((_, binary_data, _)) = &self.h264_sequence_header {
                             warn!("Unexpected h264 sequence header tag. {tag_header:?}");
                             if bytes != binary_data {
-                                create_new = true;
+                                self.create_new = true;
                                 warn!("Different h264 sequence header tag. {tag_header:?}");
                             }
                         }
-                        h264_sequence_header =
+                        self.h264_sequence_header =
                             Some((tag_header, bytes.clone(), previous_tag_size.clone()))
                     }
                     (
@@ -124,10 +196,10 @@ pub(crate) async fn parse_flv(
             }
             TagData::Script => {
                 let (_, tag_data) = script_data(i).expect("Error in parsing script tag.");
-                if on_meta_data.is_some() {
+                if self.on_meta_data.is_some() {
                     warn!("Unexpected script tag. {tag_header:?}");
                 }
-                on_meta_data = Some((tag_header, bytes.clone(), previous_tag_size.clone()));
+                self.on_meta_data = Some((tag_header, bytes.clone(), previous_tag_size.clone()));
 
                 FlvTag {
                     header: tag_header,
@@ -145,67 +217,101 @@ pub(crate) async fn parse_flv(
                 ..
             } => {
                 let timestamp = flv_tag.header.timestamp as u64;
-                if prev_timestamp == 0 && timestamp != 0 {
-                    segment.set_start_time(Duration::from_millis(timestamp));
+                if self.prev_timestamp == 0 && timestamp != 0 {
+                    self.segment
+                        .set_start_time(Duration::from_millis(timestamp));
                 }
-                segment.set_time_position(Duration::from_millis(timestamp));
-                for (tag_header, flv_tag_data, previous_tag_size_bytes) in &flv_tags_cache {
-                    if tag_header.timestamp < prev_timestamp {
+                self.segment
+                    .set_time_position(Duration::from_millis(timestamp));
+                for (tag_header, flv_tag_data, previous_tag_size_bytes) in &self.flv_tags_cache {
+                    if tag_header.timestamp < self.prev_timestamp {
                         warn!(
-                            "Non-monotonous DTS in output stream; previous: {prev_timestamp}, current: {};",
-                            tag_header.timestamp
+                            "Non-monotonous DTS in output stream; previous: {}, current: {};",
+                            self.prev_timestamp, tag_header.timestamp
                         );
                     }
-                    out.write_tag(tag_header, flv_tag_data, previous_tag_size_bytes)?;
-                    segment.increase_size((11 + tag_header.data_size + 4) as u64);
+                    self.out
+                        .write_tag(tag_header, flv_tag_data, previous_tag_size_bytes)?;
+                    match tag_header.tag_type {
+                        TagType::Video => {
+                            if let Some(codec) = probe::video_codec(flv_tag_data) {
+                                self.out.file.video_codec = Some(codec);
+                            }
+                            if let Some(resolution) = probe::video_resolution(flv_tag_data) {
+                                self.out.file.resolution = Some(resolution);
+                            }
+                        }
+                        TagType::Audio => {
+                            if let Some((codec, ..)) = probe::audio_codec(flv_tag_data) {
+                                self.out.file.audio_codec = Some(codec);
+                            }
+                        }
+                        TagType::Script => {}
+                    }
+                    self.segment
+                        .increase_size((11 + tag_header.data_size + 4) as u64);
                     // downloaded_size += (11 + tag_header.data_size + 4) as u64;
-                    prev_timestamp = tag_header.timestamp
+                    self.prev_timestamp = tag_header.timestamp
                     // println!("{downloaded_size}");
                 }
-                flv_tags_cache.clear();
+                self.flv_tags_cache.clear();
+                self.out.file.duration = self.segment.duration();
+                self.out.file.check_space()?;
 
-                if segment.needed() || create_new {
-                    segment.set_start_time(Duration::from_millis(timestamp));
-                    segment.set_size_position(9 + 4);
+                if self.segment.needed() || self.create_new {
+                    self.segment
+                        .set_start_time(Duration::from_millis(timestamp));
+                    self.segment.set_size_position(9 + 4);
 
-                    let (meta_header, meta_bytes, previous_meta_tag_size) =
-                        on_meta_data.as_ref().expect("on_meta_data does not exist");
+                    let (meta_header, meta_bytes, previous_meta_tag_size) = self
+                        .on_meta_data
+                        .as_ref()
+                        .expect("on_meta_data does not exist");
                     // onMetaData
-                    flv_tags_cache.push((
+                    self.flv_tags_cache.push((
                         *meta_header,
                         meta_bytes.clone(),
                         previous_meta_tag_size.clone(),
                     ));
                     // AACSequenceHeader
-                    let aac_sequence_header = aac_sequence_header
+                    let aac_sequence_header = self
+                        .aac_sequence_header
                         .as_ref()
                         .expect("aac_sequence_header does not exist");
-                    flv_tags_cache.push((
+                    self.flv_tags_cache.push((
                         aac_sequence_header.0,
                         aac_sequence_header.1.clone(),
                         aac_sequence_header.2.clone(),
                     ));
-                    if !create_new {
+                    if !self.create_new {
                         // H264SequenceHeader
-                        flv_tags_cache.push(
-                            h264_sequence_header
+                        self.flv_tags_cache.push(
+                            self.h264_sequence_header
                                 .as_ref()
                                 .expect("h264_sequence_header does not exist")
                                 .clone(),
                         );
                     }
-                    info!("{} splitting.{segment:?}", out.file.file_name);
-                    out.create_new()?;
-                    create_new = false;
+                    info!("{} splitting.{:?}", self.out.file.file_name, self.segment);
+                    self.out.file.split_reason = if self.create_new {
+                        SplitReason::StreamChanged
+                    } else {
+                        SplitReason::Policy
+                    };
+                    self.segment.rename_next(&mut self.out.file);
+                    self.out.create_new()?;
+                    self.create_new = false;
                 }
-                flv_tags_cache.push((tag_header, bytes.clone(), previous_tag_size.clone()));
+                self.flv_tags_cache
+                    .push((tag_header, bytes.clone(), previous_tag_size.clone()));
             }
             _ => {
-                flv_tags_cache.push((tag_header, bytes.clone(), previous_tag_size.clone()));
+                self.flv_tags_cache
+                    .push((tag_header, bytes.clone(), previous_tag_size.clone()));
             }
         }
+        Ok(())
     }
-    Ok(())
 }
 
 pub fn map_parse_err<'a, T>(
@@ -228,18 +334,49 @@ pub fn map_parse_err<'a, T>(
 }
 
 pub struct Connection {
-    resp: Response,
+    body: Body,
     buffer: BytesMut,
 }
 
+/// Where the FLV stream of a [`Connection`] comes from.
+enum Body {
+    Http(Response),
+    Rtmp(Box<rtmp::Session>),
+}
+
 impl Connection {
     pub fn new(resp: Response) -> Connection {
+        Self::with_body(Body::Http(resp))
+    }
+
+    pub(crate) fn rtmp(session: rtmp::Session) -> Connection {
+        Self::with_body(Body::Rtmp(Box::new(session)))
+    }
+
+    fn with_body(body: Body) -> Connection {
         Connection {
-            resp,
+            body,
             buffer: BytesMut::with_capacity(8 * 1024),
         }
     }
 
+    /// Reads the whole response, such as a playlist.
+    pub(crate) async fn bytes(self) -> crate::downloader::error::Result<Bytes> {
+        match self.body {
+            Body::Http(resp) => Ok(resp.bytes().await?),
+            Body::Rtmp(_) => Err(crate::downloader::error::Error::Custom(
+                "RTMP streams have no end to read to".to_string(),
+            )),
+        }
+    }
+
+    async fn chunk(&mut self) -> crate::downloader::error::Result<Option<Bytes>> {
+        match &mut self.body {
+            Body::Http(resp) => Ok(resp.chunk().await?),
+            Body::Rtmp(session) => session.chunk().await,
+        }
+    }
+
     pub async fn read_frame(
         &mut self,
         chunk_size: usize,
@@ -254,7 +391,7 @@ impl Connection {
             // BytesMut::with_capacity(0).deref_mut()
             // tokio::fs::File::open("").read()
             // self.resp.chunk()
-            match timeout(Duration::from_secs(30), self.resp.chunk()).await? {
+            match timeout(Duration::from_secs(30), self.chunk()).await? {
                 Ok(Some(chunk)) => {
                     // let n = chunk.len();
                     // println!("Chunk: {:?}", chunk);
@@ -281,8 +418,13 @@ impl Connection {
 
 #[cfg(test)]
 mod tests {
+    use super::FlvRecorder;
+    use crate::downloader::event::{RecordingEvent, SplitReason};
+    use crate::downloader::flv_parser::{TagHeader, TagType};
+    use crate::downloader::testing::{TempDir, collect_events};
+    use crate::downloader::util::{LifecycleFile, Segmentable};
     use anyhow::Result;
-    use bytes::{Buf, BufMut, BytesMut};
+    use bytes::{Buf, BufMut, Bytes, BytesMut};
 
     #[test]
     fn byte_it_works() -> Result<()> {
@@ -316,6 +458,46 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn continue_timestamps_after_reconnect() -> Result<()> {
+        let dir = TempDir::new("httpflv")?;
+        let (handler, events) = collect_events();
+        let file = LifecycleFile::new(&dir.join("out").to_string_lossy(), "flv", Some(handler));
+        let mut recorder = FlvRecorder::new(file, Segmentable::default())?;
+        // AVC keyframe NALU.
+        let body = Bytes::from_static(&[0x17, 0x01, 0, 0, 0, 0xAA]);
+        for (rebase, timestamp) in [(false, 0), (false, 40), (false, 80), (true, 0), (true, 40)] {
+            let mut tag_header = TagHeader {
+                tag_type: TagType::Video,
+                data_size: body.len() as u32,
+                timestamp,
+                stream_id: 0,
+            };
+            recorder.continue_timestamps(&mut tag_header, rebase && timestamp == 0);
+            recorder.write(tag_header, body.clone(), Bytes::from_static(&[0, 0, 0, 17]))?;
+        }
+        let path = recorder.out.file.file_name.clone();
+        drop(recorder);
+        let flv = std::fs::read(&path)?;
+
+        let events = events.lock().unwrap();
+        assert!(matches!(events[0], RecordingEvent::FileOpened { .. }));
+        let RecordingEvent::SegmentClosed(segment) = &events[1] else {
+            panic!("{events:?}");
+        };
+        assert_eq!(segment.path.to_string_lossy(), path);
+        assert_eq!(segment.size, flv.len() as u64);
+        assert_eq!(segment.reason, SplitReason::End);
+
+        // Tags wait in the cache for the next keyframe.
+        let timestamps: Vec<_> = flv[13..]
+            .chunks(11 + 6 + 4)
+            .map(|tag| u32::from_be_bytes([0, tag[4], tag[5], tag[6]]))
+            .collect();
+        assert_eq!(timestamps, [0, 40, 80, 81]);
+        Ok(())
+    }
+
     #[test]
     fn it_works() -> Result<()> {
         // download(
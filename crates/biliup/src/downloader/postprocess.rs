@@ -0,0 +1,413 @@
+//! Steps run on every finished file, such as remuxing or moving it elsewhere. A
+//! [`PostProcessor`] runs them in order on a thread of its own, so the recording goes on
+//! meanwhile, and reports [`RecordingEvent::SegmentClosed`] with the final path once done.
+//! The [`SegmentFilter`] of the recording runs there too, before the steps.
+
+use crate::downloader::event::{EventHandler, RecordingEvent, SegmentInfo};
+use crate::downloader::filter::SegmentFilter;
+use crate::downloader::sidecar::Sidecar;
+use crate::downloader::template;
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use tracing::{error, info};
+
+/// A post-processing step, written as `remux:<format>`, `move:<dir>`, `exec:<command>`,
+/// `sha256` or `delete-after-upload`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Step {
+    /// Copies the streams into a file of the given format with ffmpeg, replacing the
+    /// original.
+    Remux(String),
+    /// Moves the file into the directory.
+    Move(PathBuf),
+    /// Runs a command. `{path}`, `{dir}`, `{name}` and `{stem}` in its arguments are
+    /// replaced with those of the file.
+    Exec(String),
+    /// Writes the SHA-256 of the file to `<file>.sha256`, as `sha256sum` does.
+    Sha256,
+    /// Deletes the file once it was uploaded. Left to the uploader, the chain skips it.
+    DeleteAfterUpload,
+}
+
+impl FromStr for Step {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg.trim()).filter(|arg| !arg.is_empty())),
+            None => (s, None),
+        };
+        match (name.trim(), arg) {
+            ("remux", Some(format)) if is_format(format) => Ok(Step::Remux(format.to_string())),
+            ("move", Some(dir)) => Ok(Step::Move(PathBuf::from(dir))),
+            ("exec", Some(command)) => Ok(Step::Exec(command.to_string())),
+            ("sha256", None) => Ok(Step::Sha256),
+            ("delete-after-upload", None) => Ok(Step::DeleteAfterUpload),
+            _ => Err(format!(
+                "invalid post-processing step `{s}`, expected remux:<format>, move:<dir>, \
+                 exec:<command>, sha256 or delete-after-upload"
+            )),
+        }
+    }
+}
+
+/// Whether `format` is a file extension of letters and digits only, such as `mp4`.
+fn is_format(format: &str) -> bool {
+    !format.is_empty() && format.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+impl TryFrom<String> for Step {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Step::Remux(format) => write!(f, "remux:{format}"),
+            Step::Move(dir) => write!(f, "move:{}", dir.display()),
+            Step::Exec(command) => write!(f, "exec:{command}"),
+            Step::Sha256 => write!(f, "sha256"),
+            Step::DeleteAfterUpload => write!(f, "delete-after-upload"),
+        }
+    }
+}
+
+impl From<Step> for String {
+    fn from(step: Step) -> Self {
+        step.to_string()
+    }
+}
+
+impl Step {
+    /// Whether the step runs commands or writes outside of the recording directory, which
+    /// steps taken from untrusted input must not do.
+    pub fn touches_host(&self) -> bool {
+        matches!(self, Step::Move(_) | Step::Exec(_))
+    }
+
+    /// Runs the step on the file at `path`, returning where the file is afterwards.
+    fn run(&self, path: &Path) -> io::Result<PathBuf> {
+        match self {
+            Step::Remux(format) => {
+                if !is_format(format) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid remux format `{format}`"),
+                    ));
+                }
+                let output = path.with_extension(format);
+                if output == path {
+                    return Ok(output);
+                }
+                let status = Command::new("ffmpeg")
+                    .args(["-hide_banner", "-loglevel", "error", "-y", "-i"])
+                    .arg(path)
+                    .args(["-map", "0", "-c", "copy"])
+                    .arg(&output)
+                    .status()?;
+                if !status.success() {
+                    let _ = fs::remove_file(&output);
+                    return Err(io::Error::other(format!("ffmpeg exited with {status}")));
+                }
+                fs::remove_file(path)?;
+                Ok(output)
+            }
+            Step::Move(dir) => {
+                fs::create_dir_all(dir)?;
+                // Files of the same name in `dir` are kept.
+                let stem = dir.join(path.file_stem().unwrap_or_default());
+                let target = PathBuf::from(template::unique_file_name(
+                    &stem.to_string_lossy(),
+                    &path.extension().unwrap_or_default().to_string_lossy(),
+                ));
+                // Renaming fails across file systems.
+                if fs::rename(path, &target).is_err() {
+                    fs::copy(path, &target)?;
+                    fs::remove_file(path)?;
+                }
+                Ok(target)
+            }
+            Step::Exec(command) => {
+                let words = shell_words::split(command)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                let value = |name: &str| {
+                    let value = match name {
+                        "path" => path.as_os_str(),
+                        "dir" => path.parent().unwrap_or(Path::new("")).as_os_str(),
+                        "name" => path.file_name()?,
+                        "stem" => path.file_stem()?,
+                        _ => return None,
+                    };
+                    Some(value.to_string_lossy().into_owned())
+                };
+                let mut words = words.iter().map(|word| template::render(word, value));
+                let program = words
+                    .next()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty command"))?;
+                let status = Command::new(&program).args(words).status()?;
+                if !status.success() {
+                    return Err(io::Error::other(format!("{program} exited with {status}")));
+                }
+                Ok(path.to_path_buf())
+            }
+            Step::Sha256 => {
+                let mut hasher = Sha256::new();
+                io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+                let hash: String = hasher
+                    .finalize()
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect();
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                let mut checksum = path.as_os_str().to_owned();
+                checksum.push(".sha256");
+                fs::write(checksum, format!("{hash}  {name}\n"))?;
+                Ok(path.to_path_buf())
+            }
+            Step::DeleteAfterUpload => Ok(path.to_path_buf()),
+        }
+    }
+}
+
+struct Job {
+    /// `None` reports the file `filter` held back, once the recording ended.
+    segment: Option<SegmentInfo>,
+    filter: Option<SegmentFilter>,
+    events: Option<EventHandler>,
+    sidecar: Option<Sidecar>,
+}
+
+impl Job {
+    fn run(self, steps: &[Step]) {
+        let emit = |event: RecordingEvent| {
+            if let Some(events) = &self.events {
+                events(&event)
+            }
+        };
+        let segments = match (&self.filter, self.segment) {
+            (Some(filter), Some(segment)) => filter.apply(segment, emit),
+            (Some(filter), None) => filter.flush().into_iter().collect(),
+            (None, segment) => segment.into_iter().collect(),
+        };
+        for segment in segments {
+            process(steps, segment, self.events.as_ref(), self.sidecar.as_ref());
+        }
+    }
+}
+
+/// Runs a chain of [`Step`]s on finished files, one file after the other.
+#[derive(Clone)]
+pub struct PostProcessor {
+    steps: Arc<[Step]>,
+    sender: Sender<Job>,
+    worker: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl PostProcessor {
+    pub fn new(steps: Vec<Step>) -> Self {
+        let steps: Arc<[Step]> = steps.into();
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let worker = {
+            let steps = steps.clone();
+            std::thread::spawn(move || {
+                for job in receiver {
+                    // A step that panics loses its file, but not the files after it.
+                    if panic::catch_unwind(AssertUnwindSafe(|| job.run(&steps))).is_err() {
+                        error!("post-processing panicked, the file was skipped");
+                    }
+                }
+            })
+        };
+        Self {
+            steps,
+            sender,
+            worker: Arc::new(Mutex::new(Some(worker))),
+        }
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    /// Whether uploaded files are to be deleted.
+    pub fn deletes_after_upload(&self) -> bool {
+        self.steps.contains(&Step::DeleteAfterUpload)
+    }
+
+    /// Queues a finished file to be checked by `filter`. `events` gets the
+    /// [`RecordingEvent::SegmentClosed`] of every file kept once all steps ran, right
+    /// after `sidecar` was written next to it.
+    pub fn process(
+        &self,
+        segment: SegmentInfo,
+        filter: Option<SegmentFilter>,
+        events: Option<EventHandler>,
+        sidecar: Option<Sidecar>,
+    ) {
+        self.send(Job {
+            segment: Some(segment),
+            filter,
+            events,
+            sidecar,
+        });
+    }
+
+    /// Queues the file `filter` held back for merging, after the recording ended.
+    pub fn flush(
+        &self,
+        filter: SegmentFilter,
+        events: Option<EventHandler>,
+        sidecar: Option<Sidecar>,
+    ) {
+        self.send(Job {
+            segment: None,
+            filter: Some(filter),
+            events,
+            sidecar,
+        });
+    }
+
+    fn send(&self, job: Job) {
+        if self.sender.send(job).is_err() {
+            error!("post-processing stopped");
+        }
+    }
+
+    /// Waits for the queued files to be processed, after the recordings sharing this
+    /// processor ended.
+    pub fn finish(self) {
+        let worker = self.worker.lock().unwrap().take();
+        drop(self);
+        if let Some(worker) = worker
+            && worker.join().is_err()
+        {
+            error!("post-processing panicked");
+        }
+    }
+}
+
+fn process(
+    steps: &[Step],
+    mut segment: SegmentInfo,
+    events: Option<&EventHandler>,
+    sidecar: Option<&Sidecar>,
+) {
+    let emit = |event: RecordingEvent| {
+        if let Some(events) = events {
+            events(&event)
+        }
+    };
+    for step in steps {
+        match step.run(&segment.path) {
+            Ok(path) => segment.path = path,
+            Err(e) => {
+                error!("{step} {}: {e}", segment.path.display());
+                emit(RecordingEvent::Error {
+                    message: format!("{step} {}: {e}", segment.path.display()),
+                });
+                break;
+            }
+        }
+    }
+    info!("Processed {}", segment.path.display());
+    segment.size = fs::metadata(&segment.path).map_or(segment.size, |m| m.len());
+    if let Some(sidecar) = sidecar {
+        sidecar.write(&segment, emit);
+    }
+    emit(RecordingEvent::SegmentClosed(segment));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader::testing::{TempDir, collect_events, segment};
+    use std::time::Duration;
+
+    #[test]
+    fn run_steps_in_order() -> anyhow::Result<()> {
+        let dir = TempDir::new("postprocess")?;
+        let move_to = format!("move:{}", dir.join("done").display());
+        let steps: Vec<Step> = [move_to.as_str(), "sha256", "delete-after-upload"]
+            .into_iter()
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map_err(anyhow::Error::msg)?;
+        assert_eq!(steps[0].to_string(), move_to);
+        assert!("remux".parse::<Step>().is_err());
+
+        let path = dir.join("live.flv");
+        fs::write(&path, b"flv")?;
+        let processor = PostProcessor::new(steps);
+        assert!(processor.deletes_after_upload());
+
+        let (events, closed) = collect_events();
+        processor.process(
+            segment(path.clone(), 0, Duration::ZERO),
+            None,
+            Some(events),
+            None,
+        );
+        processor.finish();
+
+        let moved = dir.join("done/live.flv");
+        let checksum = fs::read_to_string(dir.join("done/live.flv.sha256"));
+        assert!(!path.exists());
+        assert_eq!(
+            checksum?,
+            "2c676e792a4a7c41b97ab5ec4c55c2964db292e77ade70a2dc3fd7c58c3ebb61  live.flv\n"
+        );
+        let closed = closed.lock().unwrap();
+        assert!(matches!(
+            &closed[..],
+            [RecordingEvent::SegmentClosed(SegmentInfo { path, size: 3, .. })] if *path == moved
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn move_keeps_existing_files() -> anyhow::Result<()> {
+        let dir = TempDir::new("postprocess-move")?;
+        fs::create_dir_all(dir.join("done"))?;
+        fs::write(dir.join("done/live.flv"), b"old")?;
+        fs::write(dir.join("live.flv"), b"new")?;
+        let step = Step::Move(dir.join("done"));
+        assert!(step.touches_host());
+        assert!(!Step::Sha256.touches_host());
+        let moved = step.run(&dir.join("live.flv"));
+        let old = fs::read(dir.join("done/live.flv"));
+        let new = fs::read(dir.join("done/live-1.flv"));
+        assert_eq!(moved?, dir.join("done/live-1.flv"));
+        assert_eq!(old?, b"old");
+        assert_eq!(new?, b"new");
+        Ok(())
+    }
+
+    #[test]
+    fn remux_formats() -> anyhow::Result<()> {
+        assert_eq!("remux:mp4".parse(), Ok(Step::Remux("mp4".to_string())));
+        assert!("remux:../mp4".parse::<Step>().is_err());
+        assert!("remux:a\\b".parse::<Step>().is_err());
+
+        let dir = TempDir::new("postprocess-remux")?;
+        let path = dir.join("live.flv");
+        fs::write(&path, b"flv")?;
+        let remux = Step::Remux("x/y".to_string()).run(&path);
+        assert_eq!(remux.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert!(path.exists());
+        Ok(())
+    }
+}
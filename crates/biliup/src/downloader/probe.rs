@@ -0,0 +1,828 @@
+//! Reads the start of a live stream to report its container and codec parameters.
+
+use crate::downloader::dash::{ContentType, Manifest};
+use crate::downloader::error::{Error, Result};
+use crate::downloader::event::Resolution;
+use crate::downloader::flv_parser::{ScriptDataValue, TagType, header, script_data, tag_header};
+use crate::downloader::fmp4;
+use crate::downloader::httpflv::Connection;
+use m3u8_rs::Playlist;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use url::Url;
+
+/// Tags read at most while looking for the codec parameters.
+const MAX_TAGS: usize = 100;
+
+const AAC_SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct StreamProbe {
+    /// `flv`, `hls` or `dash`.
+    pub container: String,
+    /// RFC 6381 codec string, e.g. `avc1.64002a`.
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    /// Header fields, `onMetaData` entries or playlist attributes.
+    pub details: BTreeMap<String, String>,
+}
+
+impl Display for StreamProbe {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Container: {}", self.container)?;
+        if let Some(codec) = &self.video_codec {
+            writeln!(f, "Video: {codec}")?;
+        }
+        if let Some(codec) = &self.audio_codec {
+            writeln!(f, "Audio: {codec}")?;
+        }
+        for (key, value) in &self.details {
+            writeln!(f, "  {key}: {value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads tags until the video and audio sequence headers announced by the FLV header
+/// were seen.
+pub(crate) async fn flv(mut connection: Connection) -> Result<StreamProbe> {
+    let bytes = connection.read_frame(9 + 4).await?;
+    let (_, flv_header) =
+        header(&bytes).map_err(|_| Error::Custom("Not an FLV stream".to_string()))?;
+    let mut probe = StreamProbe {
+        container: "flv".to_string(),
+        ..Default::default()
+    };
+    probe
+        .details
+        .insert("version".to_string(), flv_header.version.to_string());
+
+    for _ in 0..MAX_TAGS {
+        if (probe.video_codec.is_some() || !flv_header.video)
+            && (probe.audio_codec.is_some() || !flv_header.audio)
+        {
+            break;
+        }
+        let bytes = connection.read_frame(11).await?;
+        if bytes.len() < 11 {
+            break;
+        }
+        let (_, tag) =
+            tag_header(&bytes).map_err(|_| Error::Custom(format!("Invalid tag: {bytes:?}")))?;
+        let size = tag.data_size as usize;
+        let data = connection.read_frame(size + 4).await?;
+        let Some(data) = data.get(..size) else {
+            break;
+        };
+        match tag.tag_type {
+            TagType::Script => metadata(data, &mut probe.details),
+            TagType::Video if probe.video_codec.is_none() => probe.video_codec = video_codec(data),
+            TagType::Audio if probe.audio_codec.is_none() => {
+                if let Some((codec, sample_rate, channels)) = audio_codec(data) {
+                    probe.audio_codec = Some(codec);
+                    probe
+                        .details
+                        .insert("audio_sample_rate".to_string(), sample_rate.to_string());
+                    probe
+                        .details
+                        .insert("audio_channels".to_string(), channels.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(probe)
+}
+
+/// Describes a master playlist by its variants and a media playlist by its attributes.
+pub(crate) fn hls(playlist: &[u8]) -> Result<StreamProbe> {
+    let mut probe = StreamProbe {
+        container: "hls".to_string(),
+        ..Default::default()
+    };
+    match m3u8_rs::parse_playlist_res(playlist) {
+        Ok(Playlist::MasterPlaylist(pl)) => {
+            for (i, variant) in pl.variants.iter().enumerate() {
+                let resolution = variant
+                    .resolution
+                    .map(|r| format!(" {}x{}", r.width, r.height))
+                    .unwrap_or_default();
+                probe.details.insert(
+                    format!("variant_{i}"),
+                    format!(
+                        "{}{resolution} {}bps {}",
+                        variant.uri,
+                        variant.bandwidth,
+                        variant.codecs.as_deref().unwrap_or_default()
+                    ),
+                );
+            }
+            if let Some(codecs) = pl.variants.first().and_then(|v| v.codecs.as_deref()) {
+                (probe.video_codec, probe.audio_codec) = hls_codecs(codecs);
+            }
+        }
+        Ok(Playlist::MediaPlaylist(pl)) => {
+            let mut insert =
+                |key: &str, value: String| probe.details.insert(key.to_string(), value);
+            if let Some(version) = pl.version {
+                insert("version", version.to_string());
+            }
+            insert("target_duration", pl.target_duration.to_string());
+            insert("media_sequence", pl.media_sequence.to_string());
+            insert("segments", pl.segments.len().to_string());
+            if let Some(map) = pl.segments.iter().find_map(|s| s.map.as_ref()) {
+                insert("init", map.uri.clone());
+            }
+            if let Some(segment) = pl.segments.first() {
+                insert("first_segment", segment.uri.clone());
+            }
+        }
+        Err(_) => return Err(Error::Custom("Not an HLS playlist".to_string())),
+    }
+    Ok(probe)
+}
+
+/// Video and audio codec listed in the `CODECS` attribute of a variant.
+pub(crate) fn hls_codecs(codecs: &str) -> (Option<String>, Option<String>) {
+    let (mut video, mut audio) = (None, None);
+    for codec in codecs.split(',').map(str::trim) {
+        let slot = if codec.starts_with("mp4a") {
+            &mut audio
+        } else {
+            &mut video
+        };
+        slot.get_or_insert_with(|| codec.to_string());
+    }
+    (video, audio)
+}
+
+/// Describes a DASH manifest by its representations, the codecs are the ones recorded.
+pub(crate) fn dash(manifest: &[u8], url: &Url) -> Result<StreamProbe> {
+    let text = std::str::from_utf8(manifest)
+        .map_err(|_| Error::Custom("Not a DASH manifest".to_string()))?;
+    let manifest = Manifest::parse(text, url)?;
+    let mut probe = StreamProbe {
+        container: "dash".to_string(),
+        ..Default::default()
+    };
+    let kind = if manifest.dynamic {
+        "dynamic"
+    } else {
+        "static"
+    };
+    probe.details.insert("type".to_string(), kind.to_string());
+    for (i, representation) in manifest.representations.iter().enumerate() {
+        let resolution = representation
+            .resolution
+            .map(|r| format!(" {}x{}", r.width, r.height))
+            .unwrap_or_default();
+        probe.details.insert(
+            format!("representation_{i}"),
+            format!(
+                "{}{resolution} {}bps {}",
+                representation.id,
+                representation.bandwidth,
+                representation.codecs.as_deref().unwrap_or_default()
+            ),
+        );
+    }
+    for representation in manifest.select() {
+        match representation.content_type {
+            ContentType::Audio => probe.audio_codec = representation.codecs.clone(),
+            _ => probe.video_codec = representation.codecs.clone(),
+        }
+    }
+    Ok(probe)
+}
+
+/// Scalar entries of `onMetaData`, such as `width`, `height` and `framerate`.
+fn metadata(data: &[u8], details: &mut BTreeMap<String, String>) {
+    let Ok((_, script)) = script_data(data) else {
+        return;
+    };
+    let (ScriptDataValue::ECMAArray(objects) | ScriptDataValue::Object(objects)) = script.arguments
+    else {
+        return;
+    };
+    for object in objects {
+        let value = match object.data {
+            ScriptDataValue::Number(n) => n.to_string(),
+            ScriptDataValue::Boolean(b) => b.to_string(),
+            ScriptDataValue::String(s) | ScriptDataValue::LongString(s) => s.to_string(),
+            _ => continue,
+        };
+        details.insert(object.name.to_string(), value);
+    }
+}
+
+/// Codec string from the sequence header of a video tag, `None` for other packets.
+pub(crate) fn video_codec(data: &[u8]) -> Option<String> {
+    let (fourcc, config) = if data.first()? & 0x80 != 0 {
+        // Enhanced RTMP: a FourCC follows the packet type, 0 being the sequence start.
+        if data[0] & 0x0f != 0 {
+            return None;
+        }
+        (std::str::from_utf8(data.get(1..5)?).ok()?, data.get(5..)?)
+    } else {
+        let fourcc = match data[0] & 0x0f {
+            7 => "avc1",
+            12 => "hvc1",
+            codec_id => return Some(format!("codec id {codec_id}")),
+        };
+        if *data.get(1)? != 0 {
+            return None;
+        }
+        (fourcc, data.get(5..)?)
+    };
+    config_codec(fourcc, config)
+}
+
+/// Codec string from the decoder configuration record of `fourcc`.
+fn config_codec(fourcc: &str, config: &[u8]) -> Option<String> {
+    Some(match fourcc {
+        // AVCDecoderConfigurationRecord: profile, compatibility and level.
+        "avc1" | "avc3" => format!(
+            "{fourcc}.{:02x}{:02x}{:02x}",
+            config.get(1)?,
+            config.get(2)?,
+            config.get(3)?
+        ),
+        // HEVCDecoderConfigurationRecord: general_profile_idc and general_level_idc.
+        "hvc1" | "hev1" => format!("{fourcc}.{}.L{}", config.get(1)? & 0x1f, config.get(12)?),
+        fourcc => fourcc.to_string(),
+    })
+}
+
+/// Picture size from the SPS in the sequence header of an AVC video tag, `None` for
+/// other packets and codecs.
+pub(crate) fn video_resolution(data: &[u8]) -> Option<Resolution> {
+    let flags = *data.first()?;
+    let avc_sequence_start = if flags & 0x80 != 0 {
+        flags & 0x0f == 0 && data.get(1..5)? == b"avc1"
+    } else {
+        flags & 0x0f == 7 && *data.get(1)? == 0
+    };
+    if !avc_sequence_start {
+        return None;
+    }
+    // AVCDecoderConfigurationRecord: 5 bytes, the number of SPS, then each SPS after its
+    // length.
+    let config = data.get(5..)?;
+    let length = usize::from(u16::from_be_bytes([*config.get(6)?, *config.get(7)?]));
+    sps_resolution(config.get(8..8 + length)?)
+}
+
+/// Reads the picture size off an H.264 sequence parameter set, starting with its NAL
+/// header.
+fn sps_resolution(nal: &[u8]) -> Option<Resolution> {
+    let mut sps = Bits::new(nal.get(1..)?);
+    let profile_idc = sps.bits(8)?;
+    sps.bits(16)?; // constraint flags and level_idc
+    sps.ue()?; // seq_parameter_set_id
+    let mut chroma_format_idc = 1;
+    let mut separate_colour_plane = false;
+    if matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format_idc = sps.ue()?;
+        if chroma_format_idc == 3 {
+            separate_colour_plane = sps.bits(1)? == 1;
+        }
+        sps.ue()?; // bit_depth_luma_minus8
+        sps.ue()?; // bit_depth_chroma_minus8
+        sps.bits(1)?; // qpprime_y_zero_transform_bypass_flag
+        if sps.bits(1)? == 1 {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..lists {
+                if sps.bits(1)? == 1 {
+                    sps.skip_scaling_list(if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+    sps.ue()?; // log2_max_frame_num_minus4
+    match sps.ue()? {
+        0 => {
+            sps.ue()?; // log2_max_pic_order_cnt_lsb_minus4
+        }
+        1 => {
+            sps.bits(1)?; // delta_pic_order_always_zero_flag
+            sps.se()?; // offset_for_non_ref_pic
+            sps.se()?; // offset_for_top_to_bottom_field
+            for _ in 0..sps.ue()? {
+                sps.se()?; // offset_for_ref_frame
+            }
+        }
+        _ => {}
+    }
+    sps.ue()?; // max_num_ref_frames
+    sps.bits(1)?; // gaps_in_frame_num_value_allowed_flag
+    let width_in_mbs = sps.ue()? + 1;
+    let height_in_map_units = sps.ue()? + 1;
+    let frame_mbs_only = sps.bits(1)?;
+    if frame_mbs_only == 0 {
+        sps.bits(1)?; // mb_adaptive_frame_field_flag
+    }
+    sps.bits(1)?; // direct_8x8_inference_flag
+    let (mut crop_x, mut crop_y) = (0, 0);
+    if sps.bits(1)? == 1 {
+        let (left, right, top, bottom) = (sps.ue()?, sps.ue()?, sps.ue()?, sps.ue()?);
+        // Crop units depend on the chroma subsampling.
+        let (unit_x, unit_y) = match (chroma_format_idc, separate_colour_plane) {
+            (0, _) | (3, true) => (1, 1),
+            (1, _) => (2, 2),
+            (2, _) => (2, 1),
+            _ => (1, 1),
+        };
+        crop_x = left.checked_add(right)?.checked_mul(unit_x)?;
+        crop_y = top
+            .checked_add(bottom)?
+            .checked_mul(unit_y * (2 - frame_mbs_only))?;
+    }
+    Some(Resolution {
+        width: width_in_mbs.checked_mul(16)?.checked_sub(crop_x)?,
+        height: height_in_map_units
+            .checked_mul((2 - frame_mbs_only) * 16)?
+            .checked_sub(crop_y)?,
+    })
+}
+
+/// Bit reader over an RBSP, skipping the emulation prevention bytes.
+struct Bits {
+    data: Vec<u8>,
+    position: usize,
+}
+
+impl Bits {
+    fn new(nal: &[u8]) -> Self {
+        let mut data = Vec::with_capacity(nal.len());
+        for &byte in nal {
+            if byte == 3 && data.ends_with(&[0, 0]) {
+                continue;
+            }
+            data.push(byte);
+        }
+        Self { data, position: 0 }
+    }
+
+    fn bits(&mut self, n: u32) -> Option<u32> {
+        let mut value = 0;
+        for _ in 0..n {
+            let byte = self.data.get(self.position / 8)?;
+            value = (value << 1) | u32::from((byte >> (7 - self.position % 8)) & 1);
+            self.position += 1;
+        }
+        Some(value)
+    }
+
+    /// Unsigned Exp-Golomb code.
+    fn ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.bits(1)? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        Some((1 << zeros) - 1 + self.bits(zeros)?)
+    }
+
+    /// Signed Exp-Golomb code.
+    fn se(&mut self) -> Option<i32> {
+        let value = self.ue()?;
+        let magnitude = value.div_ceil(2) as i32;
+        Some(if value % 2 == 1 {
+            magnitude
+        } else {
+            -magnitude
+        })
+    }
+
+    fn skip_scaling_list(&mut self, size: usize) -> Option<()> {
+        let (mut last, mut next) = (8, 8);
+        for _ in 0..size {
+            if next != 0 {
+                next = (last + self.se()? + 256) % 256;
+            }
+            if next != 0 {
+                last = next;
+            }
+        }
+        Some(())
+    }
+}
+
+/// Codec string, sample rate and channels of an audio tag. AAC is only described by
+/// its sequence header.
+pub(crate) fn audio_codec(data: &[u8]) -> Option<(String, u32, u8)> {
+    let flags = *data.first()?;
+    let channels = (flags & 1) + 1;
+    let sample_rate = [5512, 11025, 22050, 44100][usize::from((flags >> 2) & 3)];
+    match flags >> 4 {
+        10 => {
+            if *data.get(1)? != 0 {
+                return None;
+            }
+            // AudioSpecificConfig: 5 bits object type, 4 bits frequency index, 4 bits channels.
+            let config = data.get(2..4)?;
+            let object_type = config[0] >> 3;
+            let index = ((config[0] & 7) << 1) | (config[1] >> 7);
+            Some((
+                format!("mp4a.40.{object_type}"),
+                *AAC_SAMPLE_RATES.get(usize::from(index))?,
+                (config[1] >> 3) & 0x0f,
+            ))
+        }
+        2 => Some(("mp3".to_string(), sample_rate, channels)),
+        format => Some((format!("sound format {format}"), sample_rate, channels)),
+    }
+}
+
+/// Codecs and picture size of a recorded HLS segment.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct MediaInfo {
+    pub(crate) video_codec: Option<String>,
+    pub(crate) audio_codec: Option<String>,
+    pub(crate) resolution: Option<Resolution>,
+}
+
+/// Reads the sample descriptions of an fMP4 initialization section, or the parameter
+/// sets and ADTS headers of MPEG-TS.
+pub(crate) fn media_segment(data: &[u8]) -> MediaInfo {
+    if data
+        .get(4..8)
+        .is_some_and(|kind| kind == b"ftyp" || kind == b"moov")
+    {
+        fmp4_init(data)
+    } else {
+        mpeg_ts(data)
+    }
+}
+
+fn fmp4_init(init: &[u8]) -> MediaInfo {
+    let mut info = MediaInfo::default();
+    let Some(moov) = fmp4::child(init, &[b"moov"]) else {
+        return info;
+    };
+    for (_, trak) in fmp4::boxes(moov).filter(|(kind, _)| kind == b"trak") {
+        // The version, flags and entry count of `stsd` come before the sample entries.
+        let Some((format, entry)) = fmp4::child(&trak[8..], &[b"mdia", b"minf", b"stbl", b"stsd"])
+            .and_then(|stsd| fmp4::boxes(stsd.get(8..)?).next())
+        else {
+            continue;
+        };
+        let child = |offset: usize, kind: &[u8; 4]| {
+            fmp4::boxes(entry.get(offset..)?)
+                .find(|(k, _)| k == kind)
+                .and_then(|(_, b)| b.get(8..))
+        };
+        let fourcc = String::from_utf8_lossy(&format);
+        match &format {
+            // VisualSampleEntry: width and height at 32, boxes after 86 bytes.
+            b"avc1" | b"avc3" | b"hvc1" | b"hev1" => {
+                let kind = if format.starts_with(b"avc") {
+                    b"avcC"
+                } else {
+                    b"hvcC"
+                };
+                info.video_codec = child(86, kind).and_then(|config| config_codec(&fourcc, config));
+                info.resolution = entry.get(32..36).map(|size| Resolution {
+                    width: u16::from_be_bytes([size[0], size[1]]).into(),
+                    height: u16::from_be_bytes([size[2], size[3]]).into(),
+                });
+            }
+            // AudioSampleEntry: boxes after 36 bytes.
+            b"mp4a" => info.audio_codec = child(36, b"esds").and_then(esds_codec),
+            _ => {}
+        }
+    }
+    info
+}
+
+/// Codec string from the descriptors of an `esds` box, which nest the
+/// DecoderConfigDescriptor and its AudioSpecificConfig in an ES_Descriptor.
+fn esds_codec(esds: &[u8]) -> Option<String> {
+    let mut data = esds.get(4..)?;
+    let mut object_type = None;
+    loop {
+        let tag = *data.first()?;
+        // The size is coded in up to 4 bytes of 7 bits, which isn't needed to go deeper.
+        let mut at = 1;
+        while data.get(at)? & 0x80 != 0 {
+            at += 1;
+        }
+        let body = data.get(at + 1..)?;
+        match tag {
+            3 => {
+                let flags = *body.get(2)?;
+                let mut skip = 3;
+                if flags & 0x80 != 0 {
+                    skip += 2;
+                }
+                if flags & 0x40 != 0 {
+                    skip += 1 + usize::from(*body.get(skip)?);
+                }
+                if flags & 0x20 != 0 {
+                    skip += 2;
+                }
+                data = body.get(skip..)?;
+            }
+            4 => {
+                object_type = Some(*body.first()?);
+                data = body.get(13..)?;
+            }
+            5 => return Some(format!("mp4a.{:02x}.{}", object_type?, body.first()? >> 3)),
+            _ => return None,
+        }
+    }
+}
+
+/// Looks up the elementary streams in the PMT and reads the first parameter sets and
+/// ADTS header of their payloads.
+fn mpeg_ts(data: &[u8]) -> MediaInfo {
+    let mut pmt_pid = None;
+    let mut streams: Vec<(u16, u8)> = Vec::new();
+    let mut payloads: BTreeMap<u16, Vec<u8>> = BTreeMap::new();
+    for packet in data
+        .chunks_exact(188)
+        .take_while(|packet| packet[0] == 0x47)
+    {
+        let pid = u16::from_be_bytes([packet[1] & 0x1f, packet[2]]);
+        let unit_start = packet[1] & 0x40 != 0;
+        let payload = match (packet[3] >> 4) & 3 {
+            1 => &packet[4..],
+            3 => match packet.get(5 + usize::from(packet[4])..) {
+                Some(payload) => payload,
+                None => continue,
+            },
+            _ => continue,
+        };
+        if pid == 0 && unit_start {
+            // PAT: program number and PMT pid of the first program.
+            pmt_pid = psi_section(payload)
+                .and_then(|section| section.get(8..12))
+                .map(|program| u16::from_be_bytes([program[2] & 0x1f, program[3]]));
+        } else if Some(pid) == pmt_pid && unit_start && streams.is_empty() {
+            streams = psi_section(payload)
+                .and_then(pmt_streams)
+                .unwrap_or_default();
+        } else if streams.iter().any(|(stream, _)| *stream == pid) {
+            payloads.entry(pid).or_default().extend_from_slice(payload);
+        }
+    }
+
+    let mut info = MediaInfo::default();
+    for (pid, stream_type) in streams {
+        let Some(payload) = payloads.get(&pid) else {
+            continue;
+        };
+        match stream_type {
+            // H.264
+            0x1b => {
+                if let Some(sps) = nal_units(payload).find(|nal| nal[0] & 0x1f == 7) {
+                    // The SPS starts with the profile, compatibility and level as well.
+                    info.video_codec = config_codec("avc1", sps);
+                    info.resolution = sps_resolution(sps);
+                }
+            }
+            // H.265
+            0x24 => {
+                if let Some(sps) = nal_units(payload).find(|nal| (nal[0] >> 1) & 0x3f == 33) {
+                    // The profile_tier_level of the SPS sits 2 bytes further in than in the
+                    // decoder configuration record.
+                    let rbsp = Bits::new(sps).data;
+                    info.video_codec = rbsp.get(2..).and_then(|ptl| config_codec("hvc1", ptl));
+                }
+            }
+            // ADTS: profile in the 2 bits after the sync word and protection flag.
+            0x0f => {
+                info.audio_codec = payload
+                    .windows(3)
+                    .find(|adts| adts[0] == 0xff && adts[1] & 0xf6 == 0xf0)
+                    .map(|adts| format!("mp4a.40.{}", (adts[2] >> 6) + 1));
+            }
+            0x03 | 0x04 => info.audio_codec = Some("mp3".to_string()),
+            _ => {}
+        }
+    }
+    info
+}
+
+/// The section a PSI payload starts with, after its pointer field.
+fn psi_section(payload: &[u8]) -> Option<&[u8]> {
+    let section = payload.get(1 + usize::from(*payload.first()?)..)?;
+    let length = usize::from(u16::from_be_bytes([
+        *section.get(1)? & 0x0f,
+        *section.get(2)?,
+    ]));
+    section.get(..3 + length)
+}
+
+/// Pid and stream type of the elementary streams a PMT lists.
+fn pmt_streams(section: &[u8]) -> Option<Vec<(u16, u8)>> {
+    let info_length = usize::from(u16::from_be_bytes([
+        *section.get(10)? & 0x0f,
+        *section.get(11)?,
+    ]));
+    // The CRC ends the section.
+    let mut entries = section.get(12 + info_length..section.len().checked_sub(4)?)?;
+    let mut streams = Vec::new();
+    while let [
+        stream_type,
+        pid_high,
+        pid_low,
+        length_high,
+        length_low,
+        rest @ ..,
+    ] = entries
+    {
+        streams.push((
+            u16::from_be_bytes([pid_high & 0x1f, *pid_low]),
+            *stream_type,
+        ));
+        let length = usize::from(u16::from_be_bytes([length_high & 0x0f, *length_low]));
+        entries = rest.get(length..)?;
+    }
+    Some(streams)
+}
+
+/// NAL units of an Annex B byte stream, such as the payload of video PES packets.
+fn nal_units(stream: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let starts: Vec<usize> = stream
+        .windows(3)
+        .enumerate()
+        .filter(|(_, code)| code == &[0, 0, 1])
+        .map(|(i, _)| i + 3)
+        .collect();
+    (0..starts.len())
+        .map(move |i| &stream[starts[i]..starts.get(i + 1).map_or(stream.len(), |next| next - 3)])
+        .filter(|nal| !nal.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sps_picture_size() {
+        let tag = |sps: &[u8]| {
+            let mut tag = vec![0x17, 0, 0, 0, 0, 1, sps[1], sps[2], sps[3], 0xff, 0xe1, 0];
+            tag.push(sps.len() as u8);
+            tag.extend(sps);
+            tag
+        };
+        // Baseline, 80x45 macroblocks.
+        let baseline = [0x67, 0x42, 0xc0, 0x1f, 0xda, 0x01, 0x40, 0x16, 0xe4];
+        // High, 120x68 macroblocks cropped by 8 lines at the bottom.
+        let high = [
+            0x67, 0x64, 0x00, 0x28, 0xac, 0xb2, 0x80, 0xf0, 0x04, 0x4f, 0xca, 0x80,
+        ];
+        let resolution = |sps| video_resolution(&tag(sps)).map(|r| (r.width, r.height));
+        assert_eq!(resolution(&baseline), Some((1280, 720)));
+        assert_eq!(resolution(&high), Some((1920, 1080)));
+        assert_eq!(video_resolution(&[0x27, 1, 0, 0, 0]), None);
+        // Baseline, 2^31 + 1 macroblocks wide.
+        let overflowing = [
+            0x67, 0x42, 0xc0, 0x1f, 0xf8, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x0f, 0x40,
+        ];
+        assert_eq!(resolution(&overflowing), None);
+    }
+
+    #[test]
+    fn codec_parameters() {
+        // AVC sequence header, High profile level 4.2.
+        let avc = [0x17, 0, 0, 0, 0, 1, 0x64, 0x00, 0x2a, 0xff];
+        assert_eq!(video_codec(&avc).as_deref(), Some("avc1.64002a"));
+        assert_eq!(video_codec(&[0x27, 1, 0, 0, 0]), None);
+        let mut hevc = vec![0x1c, 0, 0, 0, 0, 1, 0x01];
+        hevc.extend([0; 10]);
+        hevc.push(150);
+        assert_eq!(video_codec(&hevc).as_deref(), Some("hvc1.1.L150"));
+
+        // AAC LC, 48 kHz, stereo.
+        assert_eq!(
+            audio_codec(&[0xaf, 0, 0x11, 0x90]),
+            Some(("mp4a.40.2".to_string(), 48000, 2))
+        );
+        assert_eq!(audio_codec(&[0xaf, 1, 0x21]), None);
+
+        let probe = hls(b"#EXTM3U
+#EXT-X-VERSION:7
+#EXT-X-TARGETDURATION:2
+#EXT-X-MEDIA-SEQUENCE:100
+#EXT-X-MAP:URI=\"h1.m4s\"
+#EXTINF:1.000,
+100.m4s
+")
+        .unwrap();
+        assert_eq!(probe.details["init"], "h1.m4s");
+        assert_eq!(probe.details["segments"], "1");
+
+        let probe = hls(b"#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=6000000,RESOLUTION=1920x1080,CODECS=\"avc1.64002A,mp4a.40.2\"
+chunked.m3u8
+")
+        .unwrap();
+        assert_eq!(probe.video_codec.as_deref(), Some("avc1.64002A"));
+        assert_eq!(probe.audio_codec.as_deref(), Some("mp4a.40.2"));
+    }
+
+    #[test]
+    fn segment_codecs() {
+        // Baseline, 80x45 macroblocks, as in `sps_picture_size`.
+        let sps = [0x67, 0x42, 0xc0, 0x1f, 0xda, 0x01, 0x40, 0x16, 0xe4];
+        let packet = |pid: u16, payload: &[u8]| {
+            // The adaptation field stuffs the packet up to 188 bytes.
+            let mut packet = vec![0x47, 0x40 | (pid >> 8) as u8, pid as u8, 0x30];
+            packet.push((183 - payload.len()) as u8);
+            if payload.len() < 183 {
+                packet.push(0);
+            }
+            packet.resize(188 - payload.len(), 0xff);
+            packet.extend(payload);
+            packet
+        };
+        let pes = |stream_id: u8, es: &[u8]| {
+            [
+                &[0, 0, 1, stream_id, 0, 0, 0x80, 0x80, 5, 0x21, 0, 1, 0, 1],
+                es,
+            ]
+            .concat()
+        };
+        let ts = [
+            packet(
+                0,
+                &[0, 0, 0xb0, 13, 0, 1, 0xc1, 0, 0, 0, 1, 0xe1, 0, 0, 0, 0, 0],
+            ),
+            packet(
+                0x100,
+                &[
+                    0, 2, 0xb0, 23, 0, 1, 0xc1, 0, 0, 0xe1, 1, 0xf0, 0, 0x1b, 0xe1, 1, 0xf0, 0,
+                    0x0f, 0xe1, 2, 0xf0, 0, 0, 0, 0, 0,
+                ],
+            ),
+            packet(
+                0x101,
+                &pes(
+                    0xe0,
+                    &[&[0, 0, 0, 1], &sps[..], &[0, 0, 0, 1, 0x65]].concat(),
+                ),
+            ),
+            packet(0x102, &pes(0xc0, &[0xff, 0xf1, 0x50, 0x80, 0x10])),
+        ]
+        .concat();
+        assert_eq!(
+            media_segment(&ts),
+            MediaInfo {
+                video_codec: Some("avc1.42c01f".to_string()),
+                audio_codec: Some("mp4a.40.2".to_string()),
+                resolution: Some(Resolution {
+                    width: 1280,
+                    height: 720
+                }),
+            }
+        );
+
+        let make_box = |kind: &[u8; 4], payload: &[u8]| {
+            [&(payload.len() as u32 + 8).to_be_bytes()[..], kind, payload].concat()
+        };
+        let trak = |entry: Vec<u8>| {
+            let stsd = make_box(b"stsd", &[&[0, 0, 0, 0, 0, 0, 0, 1], &entry[..]].concat());
+            let stbl = make_box(b"stbl", &stsd);
+            make_box(b"trak", &make_box(b"mdia", &make_box(b"minf", &stbl)))
+        };
+        let mut visual = vec![0; 78];
+        visual[24..28].copy_from_slice(&[0x07, 0x80, 0x04, 0x38]);
+        visual.extend(make_box(b"avcC", &[1, 0x64, 0, 0x2a, 0xff]));
+        let mut audio = vec![0; 28];
+        audio.extend(make_box(
+            b"esds",
+            &[
+                0, 0, 0, 0, 3, 25, 0, 1, 0, 4, 17, 0x40, 0x15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 5,
+                2, 0x11, 0x90,
+            ],
+        ));
+        let moov = [
+            trak(make_box(b"avc1", &visual)),
+            trak(make_box(b"mp4a", &audio)),
+        ]
+        .concat();
+        let init = [make_box(b"ftyp", b"iso5"), make_box(b"moov", &moov)].concat();
+        assert_eq!(
+            media_segment(&init),
+            MediaInfo {
+                video_codec: Some("avc1.64002a".to_string()),
+                audio_codec: Some("mp4a.40.2".to_string()),
+                resolution: Some(Resolution {
+                    width: 1920,
+                    height: 1080
+                }),
+            }
+        );
+        assert_eq!(media_segment(b"not media"), MediaInfo::default());
+    }
+}
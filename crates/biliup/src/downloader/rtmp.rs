@@ -0,0 +1,814 @@
+//! A minimal RTMP client that plays a stream and hands it out as FLV, so that `rtmp://`
+//! and `rtmps://` urls are recorded like HTTP-FLV streams.
+
+use crate::downloader::error::{Error, Result};
+use crate::downloader::flv_parser::{ScriptDataValue, script_data_value};
+use bytes::{BufMut, Bytes, BytesMut};
+use rand::Rng;
+use std::collections::{HashMap, VecDeque};
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tracing::{debug, info};
+use url::{Host, Position, Url};
+
+const HANDSHAKE_SIZE: usize = 1536;
+/// Size of the chunks sent, the server announces the size of its own.
+const CHUNK_SIZE: usize = 4096;
+/// Bytes the server may send before waiting for an acknowledgement.
+const WINDOW_SIZE: u32 = 2_500_000;
+/// Time the connection, the handshake and the `play` command may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+const SET_CHUNK_SIZE: u8 = 1;
+const ABORT: u8 = 2;
+const ACKNOWLEDGEMENT: u8 = 3;
+const USER_CONTROL: u8 = 4;
+const WINDOW_ACK_SIZE: u8 = 5;
+const SET_PEER_BANDWIDTH: u8 = 6;
+const AUDIO: u8 = 8;
+const VIDEO: u8 = 9;
+const DATA: u8 = 18;
+const COMMAND: u8 = 20;
+const AGGREGATE: u8 = 22;
+
+/// Chunk stream of the protocol control messages.
+const CONTROL_CHUNK_STREAM: u8 = 2;
+const COMMAND_CHUNK_STREAM: u8 = 3;
+const STREAM_CHUNK_STREAM: u8 = 8;
+
+trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+/// A stream being played.
+pub struct Session {
+    io: BufReader<Box<dyn Io>>,
+    /// Size of the chunks received.
+    chunk_size: usize,
+    chunk_streams: HashMap<u32, ChunkStream>,
+    /// Bytes received before the server expects an acknowledgement.
+    window: u32,
+    received: u64,
+    acknowledged: u64,
+    /// FLV data to hand out before reading on, starting with the FLV header.
+    pending: VecDeque<Bytes>,
+}
+
+/// Whether `url` is played over RTMP rather than requested over HTTP.
+pub fn is_rtmp(url: &str) -> bool {
+    let scheme = url.split_once("://").map_or("", |(scheme, _)| scheme);
+    scheme.eq_ignore_ascii_case("rtmp") || scheme.eq_ignore_ascii_case("rtmps")
+}
+
+/// Connects to `url`, `rtmp://host[:port]/app[/instance]/stream[?query]`, and starts
+/// playing its stream. The FLV header is the first chunk read from the session.
+pub async fn connect(url: &str) -> Result<Session> {
+    let url = Url::parse(url)?;
+    tokio::time::timeout(CONNECT_TIMEOUT, async {
+        let io = open(&url).await?;
+        Session::start(io, &url).await
+    })
+    .await?
+}
+
+async fn open(url: &Url) -> Result<Box<dyn Io>> {
+    let host = match url.host() {
+        Some(Host::Ipv6(ip)) => ip.to_string(),
+        Some(host) => host.to_string(),
+        None => return Err(Error::Custom(format!("No host in {url}"))),
+    };
+    let tls = url.scheme() == "rtmps";
+    let port = url.port().unwrap_or(if tls { 443 } else { 1935 });
+    let tcp = TcpStream::connect((host.as_str(), port)).await?;
+    tcp.set_nodelay(true)?;
+    if !tls {
+        return Ok(Box::new(tcp));
+    }
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let name = ServerName::try_from(host.clone())
+        .map_err(|e| Error::Custom(format!("Invalid host {host}: {e}")))?;
+    let tls = TlsConnector::from(Arc::new(config))
+        .connect(name, tcp)
+        .await?;
+    Ok(Box::new(tls))
+}
+
+/// The application and the stream name in the path of `url`, everything up to the
+/// last segment is the application.
+fn split_path(url: &Url) -> Option<(String, String)> {
+    let (app, stream) = url.path().trim_matches('/').rsplit_once('/')?;
+    if app.is_empty() || stream.is_empty() {
+        return None;
+    }
+    let stream = match url.query() {
+        Some(query) => format!("{stream}?{query}"),
+        None => stream.to_string(),
+    };
+    Some((app.to_string(), stream))
+}
+
+impl Session {
+    async fn start(io: Box<dyn Io>, url: &Url) -> Result<Session> {
+        let (app, stream) =
+            split_path(url).ok_or_else(|| Error::Custom(format!("No stream name in {url}")))?;
+        let tc_url = format!("{}/{app}", &url[..Position::BeforePath]);
+        let mut session = Session {
+            io: BufReader::new(io),
+            chunk_size: 128,
+            chunk_streams: HashMap::new(),
+            window: u32::MAX,
+            received: 0,
+            acknowledged: 0,
+            pending: VecDeque::from([flv_header()]),
+        };
+        session.handshake().await?;
+        session.play(&tc_url, &app, &stream).await?;
+        Ok(session)
+    }
+
+    /// The next piece of the FLV stream, `None` once the stream ended.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>> {
+        if let Some(bytes) = self.pending.pop_front() {
+            return Ok(Some(bytes));
+        }
+        loop {
+            let Some(message) = self.message().await? else {
+                return Ok(None);
+            };
+            if message.type_id == COMMAND {
+                let Some(status) = Status::parse(&message.payload) else {
+                    continue;
+                };
+                if status.ends_play() {
+                    info!("RTMP stream ended: {status}");
+                    return Ok(None);
+                }
+                debug!("RTMP status: {status}");
+            } else if let Some(tag) = flv_tag(&message) {
+                return Ok(Some(tag));
+            }
+        }
+    }
+
+    /// Simple handshake, the random bytes of the server are echoed.
+    async fn handshake(&mut self) -> Result<()> {
+        let mut c0c1 = vec![0u8; 1 + HANDSHAKE_SIZE];
+        c0c1[0] = 3;
+        rand::thread_rng().fill(&mut c0c1[9..]);
+        self.io.write_all(&c0c1).await?;
+        self.io.flush().await?;
+        let mut s0s1 = vec![0u8; 1 + HANDSHAKE_SIZE];
+        self.io.read_exact(&mut s0s1).await?;
+        if s0s1[0] != 3 {
+            return Err(Error::Custom(format!(
+                "Unsupported RTMP version {}",
+                s0s1[0]
+            )));
+        }
+        self.io.write_all(&s0s1[1..]).await?;
+        self.io.flush().await?;
+        let mut s2 = vec![0u8; HANDSHAKE_SIZE];
+        self.io.read_exact(&mut s2).await?;
+        Ok(())
+    }
+
+    /// Connects to `app`, creates a stream and plays `stream` on it, waiting until the
+    /// server started playing.
+    async fn play(&mut self, tc_url: &str, app: &str, stream: &str) -> Result<()> {
+        self.send(
+            CONTROL_CHUNK_STREAM,
+            SET_CHUNK_SIZE,
+            0,
+            &(CHUNK_SIZE as u32).to_be_bytes(),
+        )
+        .await?;
+        self.command(
+            0,
+            &[
+                Amf::String("connect"),
+                Amf::Number(1.),
+                Amf::Object(vec![
+                    ("app", Amf::String(app)),
+                    ("flashVer", Amf::String("LNX 9,0,124,2")),
+                    ("tcUrl", Amf::String(tc_url)),
+                    ("fpad", Amf::Boolean(false)),
+                    ("capabilities", Amf::Number(15.)),
+                    ("audioCodecs", Amf::Number(4071.)),
+                    ("videoCodecs", Amf::Number(252.)),
+                    ("videoFunction", Amf::Number(1.)),
+                ]),
+            ],
+        )
+        .await?;
+        self.result(1.).await?;
+        self.command(
+            0,
+            &[Amf::String("createStream"), Amf::Number(2.), Amf::Null],
+        )
+        .await?;
+        let stream_id = self
+            .result(2.)
+            .await?
+            .ok_or_else(|| Error::Custom("createStream returned no stream id".to_string()))?
+            as u32;
+
+        // Set buffer length of 3s.
+        let mut event = BytesMut::with_capacity(10);
+        event.put_u16(3);
+        event.put_u32(stream_id);
+        event.put_u32(3000);
+        self.send(CONTROL_CHUNK_STREAM, USER_CONTROL, 0, &event)
+            .await?;
+        self.command(
+            stream_id,
+            &[
+                Amf::String("play"),
+                Amf::Number(0.),
+                Amf::Null,
+                Amf::String(stream),
+                Amf::Number(-2.),
+            ],
+        )
+        .await?;
+        loop {
+            let message = self.message().await?.ok_or_else(|| {
+                Error::Custom(format!("RTMP connection closed before playing {stream}"))
+            })?;
+            if message.type_id == COMMAND {
+                let Some(status) = Status::parse(&message.payload) else {
+                    continue;
+                };
+                if status.code == "NetStream.Play.Start" {
+                    return Ok(());
+                }
+                if status.ends_play() {
+                    return Err(Error::Custom(format!("Unable to play {stream}: {status}")));
+                }
+                debug!("RTMP status: {status}");
+            } else if let Some(tag) = flv_tag(&message) {
+                // Some servers start sending without a status.
+                self.pending.push_back(tag);
+                return Ok(());
+            }
+        }
+    }
+
+    /// Waits for the answer to the command `transaction`, returns the number following
+    /// its command object, the stream id of `createStream`.
+    async fn result(&mut self, transaction: f64) -> Result<Option<f64>> {
+        loop {
+            let message = self
+                .message()
+                .await?
+                .ok_or_else(|| Error::Custom("RTMP connection closed".to_string()))?;
+            if message.type_id != COMMAND {
+                continue;
+            }
+            let values = values(&message.payload);
+            let (Some(ScriptDataValue::String(name)), Some(ScriptDataValue::Number(id))) =
+                (values.first(), values.get(1))
+            else {
+                continue;
+            };
+            if *id != transaction {
+                continue;
+            }
+            match *name {
+                "_result" => {
+                    return Ok(match values.get(3) {
+                        Some(ScriptDataValue::Number(n)) => Some(*n),
+                        _ => None,
+                    });
+                }
+                "_error" => {
+                    let description = values
+                        .iter()
+                        .find_map(|value| string_property(value, "description"))
+                        .unwrap_or_default();
+                    return Err(Error::Custom(format!("RTMP command failed: {description}")));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Reads chunks until a message other than a protocol control message is complete,
+    /// `None` once the connection closed.
+    async fn message(&mut self) -> Result<Option<Message>> {
+        loop {
+            let first = match self.io.read_u8().await {
+                Ok(first) => first,
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            let mut read = 1;
+            let id = match first & 0x3f {
+                0 => {
+                    read += 1;
+                    64 + self.io.read_u8().await? as u32
+                }
+                1 => {
+                    read += 2;
+                    64 + self.io.read_u16_le().await? as u32
+                }
+                id => id as u32,
+            };
+            let fmt = first >> 6;
+            let header_size = [11, 7, 3, 0][fmt as usize];
+            let mut header = [0u8; 11];
+            self.io.read_exact(&mut header[..header_size]).await?;
+            read += header_size;
+
+            let stream = self.chunk_streams.entry(id).or_default();
+            if fmt < 3 {
+                // A new message, anything left of the previous one was abandoned.
+                stream.payload.clear();
+                stream.extended = u24(&header) == 0xFF_FFFF;
+            }
+            if fmt < 2 {
+                stream.length = u24(&header[3..]) as usize;
+                stream.type_id = header[6];
+            }
+            let timestamp = if stream.extended {
+                read += 4;
+                self.io.read_u32().await?
+            } else {
+                u24(&header)
+            };
+            match fmt {
+                0 => {
+                    stream.timestamp = timestamp;
+                    stream.delta = 0;
+                }
+                1 | 2 => {
+                    stream.delta = timestamp;
+                    stream.timestamp = stream.timestamp.wrapping_add(timestamp);
+                }
+                _ if stream.payload.is_empty() => {
+                    stream.timestamp = stream.timestamp.wrapping_add(stream.delta);
+                }
+                _ => {}
+            }
+
+            let start = stream.payload.len();
+            let size = (stream.length - start).min(self.chunk_size);
+            stream.payload.resize(start + size, 0);
+            self.io.read_exact(&mut stream.payload[start..]).await?;
+            read += size;
+            let message = (stream.payload.len() == stream.length).then(|| Message {
+                type_id: stream.type_id,
+                timestamp: stream.timestamp,
+                payload: stream.payload.split().freeze(),
+            });
+
+            self.received += read as u64;
+            if self.received - self.acknowledged >= self.window as u64 {
+                self.acknowledged = self.received;
+                let sequence = self.received as u32;
+                self.send(
+                    CONTROL_CHUNK_STREAM,
+                    ACKNOWLEDGEMENT,
+                    0,
+                    &sequence.to_be_bytes(),
+                )
+                .await?;
+            }
+            let Some(message) = message else {
+                continue;
+            };
+            match message.type_id {
+                SET_CHUNK_SIZE => {
+                    if let Some(size) = be_u32(&message.payload) {
+                        self.chunk_size = (size & 0x7fff_ffff).max(1) as usize;
+                    }
+                }
+                ABORT => {
+                    if let Some(id) = be_u32(&message.payload) {
+                        self.chunk_streams.remove(&id);
+                    }
+                }
+                WINDOW_ACK_SIZE => {
+                    if let Some(window) = be_u32(&message.payload) {
+                        self.window = window.max(1);
+                    }
+                }
+                SET_PEER_BANDWIDTH => {
+                    self.send(
+                        CONTROL_CHUNK_STREAM,
+                        WINDOW_ACK_SIZE,
+                        0,
+                        &WINDOW_SIZE.to_be_bytes(),
+                    )
+                    .await?
+                }
+                USER_CONTROL => {
+                    // Answers ping requests with the same timestamp.
+                    if message.payload.get(..2) == Some(&[0, 6]) {
+                        let mut pong = message.payload.to_vec();
+                        pong[1] = 7;
+                        self.send(CONTROL_CHUNK_STREAM, USER_CONTROL, 0, &pong)
+                            .await?;
+                    }
+                }
+                ACKNOWLEDGEMENT => {}
+                _ => return Ok(Some(message)),
+            }
+        }
+    }
+
+    async fn command(&mut self, stream_id: u32, values: &[Amf<'_>]) -> Result<()> {
+        let mut payload = BytesMut::new();
+        for value in values {
+            value.write(&mut payload);
+        }
+        let chunk_stream = if stream_id == 0 {
+            COMMAND_CHUNK_STREAM
+        } else {
+            STREAM_CHUNK_STREAM
+        };
+        self.send(chunk_stream, COMMAND, stream_id, &payload).await
+    }
+
+    /// Sends a message in chunks of [`CHUNK_SIZE`], its timestamp is always 0.
+    async fn send(
+        &mut self,
+        chunk_stream: u8,
+        type_id: u8,
+        stream_id: u32,
+        payload: &[u8],
+    ) -> Result<()> {
+        let mut buf = BytesMut::with_capacity(12 + payload.len() + payload.len() / CHUNK_SIZE);
+        buf.put_u8(chunk_stream);
+        buf.put_uint(0, 3);
+        buf.put_uint(payload.len() as u64, 3);
+        buf.put_u8(type_id);
+        buf.put_u32_le(stream_id);
+        for (i, chunk) in payload.chunks(CHUNK_SIZE).enumerate() {
+            if i > 0 {
+                buf.put_u8(0xc0 | chunk_stream);
+            }
+            buf.put_slice(chunk);
+        }
+        self.io.write_all(&buf).await?;
+        self.io.flush().await?;
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct ChunkStream {
+    timestamp: u32,
+    delta: u32,
+    length: usize,
+    type_id: u8,
+    /// Whether the timestamps are sent as extended timestamps.
+    extended: bool,
+    payload: BytesMut,
+}
+
+struct Message {
+    type_id: u8,
+    timestamp: u32,
+    payload: Bytes,
+}
+
+/// The `onStatus` information of the stream.
+struct Status {
+    level: String,
+    code: String,
+    description: String,
+}
+
+impl Status {
+    fn parse(payload: &[u8]) -> Option<Status> {
+        let values = values(payload);
+        if values.first() != Some(&ScriptDataValue::String("onStatus")) {
+            return None;
+        }
+        let field = |name| {
+            values
+                .iter()
+                .find_map(|value| string_property(value, name))
+                .unwrap_or_default()
+                .to_string()
+        };
+        Some(Status {
+            level: field("level"),
+            code: field("code"),
+            description: field("description"),
+        })
+    }
+
+    fn ends_play(&self) -> bool {
+        self.level == "error"
+            || matches!(
+                self.code.as_str(),
+                "NetStream.Play.Stop"
+                    | "NetStream.Play.UnpublishNotify"
+                    | "NetStream.Play.StreamNotFound"
+                    | "NetStream.Play.Failed"
+            )
+    }
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.code, self.description)
+    }
+}
+
+/// AMF0 values sent with commands.
+enum Amf<'a> {
+    Number(f64),
+    Boolean(bool),
+    String(&'a str),
+    Object(Vec<(&'a str, Amf<'a>)>),
+    Null,
+}
+
+impl Amf<'_> {
+    fn write(&self, buf: &mut BytesMut) {
+        match self {
+            Amf::Number(n) => {
+                buf.put_u8(0);
+                buf.put_f64(*n);
+            }
+            Amf::Boolean(b) => {
+                buf.put_u8(1);
+                buf.put_u8(*b as u8);
+            }
+            Amf::String(s) if s.len() > u16::MAX as usize => {
+                buf.put_u8(12);
+                buf.put_u32(s.len() as u32);
+                buf.put_slice(s.as_bytes());
+            }
+            Amf::String(s) => {
+                buf.put_u8(2);
+                put_string(buf, s);
+            }
+            Amf::Object(properties) => {
+                buf.put_u8(3);
+                for (name, value) in properties {
+                    put_string(buf, name);
+                    value.write(buf);
+                }
+                buf.put_slice(&[0, 0, 9]);
+            }
+            Amf::Null => buf.put_u8(5),
+        }
+    }
+}
+
+fn put_string(buf: &mut BytesMut, s: &str) {
+    buf.put_u16(s.len() as u16);
+    buf.put_slice(s.as_bytes());
+}
+
+/// The AMF0 values of a command or data message.
+fn values(payload: &[u8]) -> Vec<ScriptDataValue<'_>> {
+    let mut values = Vec::new();
+    let mut input = payload;
+    while let Ok((rest, value)) = script_data_value(input) {
+        values.push(value);
+        input = rest;
+    }
+    values
+}
+
+fn string_property<'a>(value: &'a ScriptDataValue, name: &str) -> Option<&'a str> {
+    let (ScriptDataValue::Object(properties) | ScriptDataValue::ECMAArray(properties)) = value
+    else {
+        return None;
+    };
+    properties
+        .iter()
+        .find(|property| property.name == name)
+        .and_then(|property| match property.data {
+            ScriptDataValue::String(s) | ScriptDataValue::LongString(s) => Some(s),
+            _ => None,
+        })
+}
+
+/// FLV header announcing audio and video, followed by the first previous tag size.
+fn flv_header() -> Bytes {
+    Bytes::from_static(b"FLV\x01\x05\x00\x00\x00\x09\x00\x00\x00\x00")
+}
+
+/// The FLV tags of an audio, video, `onMetaData` or aggregate message.
+fn flv_tag(message: &Message) -> Option<Bytes> {
+    match message.type_id {
+        AUDIO | VIDEO if !message.payload.is_empty() => {
+            Some(tag(message.type_id, message.timestamp, &message.payload))
+        }
+        DATA => {
+            let mut data = &message.payload[..];
+            // Published streams keep the `@setDataFrame` of the publisher.
+            if let Ok((rest, ScriptDataValue::String("@setDataFrame"))) = script_data_value(data) {
+                data = rest;
+            }
+            match script_data_value(data) {
+                Ok((_, ScriptDataValue::String("onMetaData"))) => {
+                    Some(tag(DATA, message.timestamp, data))
+                }
+                _ => None,
+            }
+        }
+        AGGREGATE => aggregate(message),
+        _ => None,
+    }
+}
+
+/// The tags of an aggregate message, moved to the timestamp of the message.
+fn aggregate(message: &Message) -> Option<Bytes> {
+    let mut tags = BytesMut::with_capacity(message.payload.len());
+    let mut data = &message.payload[..];
+    let mut first = None;
+    while data.len() >= 11 {
+        let size = u24(&data[1..]) as usize;
+        let timestamp = u24(&data[4..]) | (data[7] as u32) << 24;
+        let body = data.get(11..11 + size)?;
+        let base = *first.get_or_insert(timestamp);
+        let timestamp = message.timestamp.wrapping_add(timestamp.wrapping_sub(base));
+        tags.put(tag(data[0], timestamp, body));
+        data = data.get(11 + size + 4..).unwrap_or_default();
+    }
+    (!tags.is_empty()).then(|| tags.freeze())
+}
+
+fn tag(type_id: u8, timestamp: u32, data: &[u8]) -> Bytes {
+    let mut tag = BytesMut::with_capacity(11 + data.len() + 4);
+    tag.put_u8(type_id);
+    tag.put_uint(data.len() as u64, 3);
+    tag.put_uint((timestamp & 0xff_ffff) as u64, 3);
+    tag.put_u8((timestamp >> 24) as u8);
+    tag.put_uint(0, 3);
+    tag.put_slice(data);
+    tag.put_u32(11 + data.len() as u32);
+    tag.freeze()
+}
+
+fn u24(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]])
+}
+
+fn be_u32(bytes: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(..4)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    /// A message sent in chunks of `chunk_size`, the first with a type 0 header.
+    fn chunks(id: u8, type_id: u8, timestamp: u32, payload: &[u8], chunk_size: usize) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        buf.put_u8(id);
+        buf.put_uint(timestamp as u64, 3);
+        buf.put_uint(payload.len() as u64, 3);
+        buf.put_u8(type_id);
+        buf.put_u32_le(1);
+        for (i, chunk) in payload.chunks(chunk_size).enumerate() {
+            if i > 0 {
+                buf.put_u8(0xc0 | id);
+            }
+            buf.put_slice(chunk);
+        }
+        buf.to_vec()
+    }
+
+    fn amf(values: &[Amf]) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        for value in values {
+            value.write(&mut buf);
+        }
+        buf.to_vec()
+    }
+
+    fn on_status(code: &str) -> Vec<u8> {
+        amf(&[
+            Amf::String("onStatus"),
+            Amf::Number(0.),
+            Amf::Null,
+            Amf::Object(vec![
+                ("level", Amf::String("status")),
+                ("code", Amf::String(code)),
+                ("description", Amf::String("")),
+            ]),
+        ])
+    }
+
+    #[test]
+    fn split_url() {
+        let url = Url::parse("rtmp://example.com/live/room?token=1").unwrap();
+        assert_eq!(
+            split_path(&url),
+            Some(("live".to_string(), "room?token=1".to_string()))
+        );
+        let url = Url::parse("rtmp://example.com:1936/app/instance/room").unwrap();
+        assert_eq!(
+            split_path(&url),
+            Some(("app/instance".to_string(), "room".to_string()))
+        );
+        assert_eq!(
+            split_path(&Url::parse("rtmp://example.com/live").unwrap()),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn play_as_flv() -> Result<()> {
+        let (client, mut server) = tokio::io::duplex(1 << 20);
+        let server = tokio::spawn(async move {
+            let mut c0c1 = vec![0u8; 1 + HANDSHAKE_SIZE];
+            server.read_exact(&mut c0c1).await?;
+            let mut s0s1s2 = vec![3u8];
+            s0s1s2.extend_from_slice(&[0; 2 * HANDSHAKE_SIZE]);
+            server.write_all(&s0s1s2).await?;
+            let mut c2 = vec![0u8; HANDSHAKE_SIZE];
+            server.read_exact(&mut c2).await?;
+
+            let mut out = Vec::new();
+            out.extend(chunks(2, WINDOW_ACK_SIZE, 0, &64u32.to_be_bytes(), 128));
+            out.extend(chunks(2, SET_CHUNK_SIZE, 0, &8u32.to_be_bytes(), 128));
+            let connected = amf(&[Amf::String("_result"), Amf::Number(1.), Amf::Null]);
+            out.extend(chunks(3, COMMAND, 0, &connected, 8));
+            let created = amf(&[
+                Amf::String("_result"),
+                Amf::Number(2.),
+                Amf::Null,
+                Amf::Number(1.),
+            ]);
+            out.extend(chunks(3, COMMAND, 0, &created, 8));
+            out.extend(chunks(5, COMMAND, 0, &on_status("NetStream.Play.Start"), 8));
+            let metadata = [
+                amf(&[Amf::String("@setDataFrame"), Amf::String("onMetaData")]),
+                amf(&[Amf::Object(vec![("width", Amf::Number(1280.))])]),
+            ]
+            .concat();
+            out.extend(chunks(5, DATA, 0, &metadata, 8));
+            out.extend(chunks(
+                6,
+                VIDEO,
+                1000,
+                &[0x17, 1, 0, 0, 0, 0xaa, 0xbb, 0xcc, 0xdd],
+                8,
+            ));
+            // Type 1 header with a delta of 40ms, continued by a type 3 chunk.
+            out.extend([0x46, 0, 0, 40, 0, 0, 10, AUDIO]);
+            out.extend([0xaf, 1, 1, 2, 3, 4, 5, 6]);
+            out.extend([0xc6, 7, 8]);
+            out.extend(chunks(5, COMMAND, 0, &on_status("NetStream.Play.Stop"), 8));
+            server.write_all(&out).await?;
+            // The acknowledgements and anything else the client sends.
+            let mut sent = Vec::new();
+            server.read_to_end(&mut sent).await?;
+            Ok::<_, std::io::Error>(sent)
+        });
+
+        let url = Url::parse("rtmp://localhost/live/room")?;
+        let mut session = Session::start(Box::new(client), &url).await?;
+        let mut flv = Vec::new();
+        while let Some(chunk) = session.chunk().await? {
+            flv.extend_from_slice(&chunk);
+        }
+        drop(session);
+        let sent = server.await??;
+        // connect, createStream and play were sent, and the 64 byte window acknowledged.
+        assert!(sent.windows(7).any(|w| w == b"connect"));
+        assert!(sent.windows(4).any(|w| w == b"play"));
+        assert!(
+            sent.windows(8)
+                .any(|w| w == [2, 0, 0, 0, 0, 0, 4, ACKNOWLEDGEMENT])
+        );
+
+        assert_eq!(&flv[..13], &flv_header()[..]);
+        let mut tags = Vec::new();
+        let mut data = &flv[13..];
+        while !data.is_empty() {
+            let size = u24(&data[1..]) as usize;
+            let timestamp = u24(&data[4..]);
+            tags.push((data[0], timestamp, data[11..11 + size].to_vec()));
+            assert_eq!(be_u32(&data[11 + size..]), Some(11 + size as u32));
+            data = &data[11 + size + 4..];
+        }
+        assert_eq!(tags.len(), 3);
+        assert_eq!(tags[0].0, DATA);
+        assert!(tags[0].2.starts_with(&amf(&[Amf::String("onMetaData")])));
+        assert_eq!((tags[1].0, tags[1].1), (VIDEO, 1000));
+        assert_eq!(tags[1].2, [0x17, 1, 0, 0, 0, 0xaa, 0xbb, 0xcc, 0xdd]);
+        assert_eq!((tags[2].0, tags[2].1), (AUDIO, 1040));
+        assert_eq!(tags[2].2, [0xaf, 1, 1, 2, 3, 4, 5, 6, 7, 8]);
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+//! `<file>.json` next to every finished file, describing what it contains, so that tools
+//! can rebuild upload metadata without the server database.
+
+use crate::downloader::event::{RecordingEvent, SegmentInfo};
+use crate::downloader::extractor::Site;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use tracing::error;
+
+/// What the files of a recording have in common. Clones share it, so that title changes
+/// and reconnects noticed while recording show up in the files written afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct Sidecar(Arc<Mutex<Recording>>);
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct Recording {
+    platform: String,
+    /// Url of the live room, if known.
+    room_url: Option<String>,
+    room_id: String,
+    streamer: String,
+    uid: String,
+    area: String,
+    /// Stream url the extractor resolved last.
+    stream_url: String,
+    titles: Vec<TitleChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TitleChange {
+    #[serde(serialize_with = "super::event::rfc3339")]
+    pub at: DateTime<Local>,
+    pub title: String,
+}
+
+#[derive(Serialize)]
+struct SidecarFile<'a> {
+    #[serde(flatten)]
+    recording: Recording,
+    #[serde(flatten)]
+    segment: &'a SegmentInfo,
+}
+
+impl Sidecar {
+    pub fn new(site: &Site, room_url: Option<&str>) -> Self {
+        let sidecar = Self(Arc::new(Mutex::new(Recording {
+            platform: site.name.to_string(),
+            room_url: room_url.map(String::from),
+            ..Default::default()
+        })));
+        sidecar.update(site);
+        sidecar
+    }
+
+    /// Sidecar of a stream recorded without an extractor, such as through
+    /// [`download`](crate::downloader::download).
+    pub fn direct(stream_url: &str) -> Self {
+        Self(Arc::new(Mutex::new(Recording {
+            platform: "direct".to_string(),
+            stream_url: stream_url.to_string(),
+            ..Default::default()
+        })))
+    }
+
+    /// Takes over the metadata of the room and the stream being recorded from it.
+    pub fn update(&self, site: &Site) {
+        {
+            let mut recording = self.0.lock().unwrap();
+            recording.room_id.clone_from(&site.room_id);
+            recording.streamer.clone_from(&site.streamer);
+            recording.uid.clone_from(&site.uid);
+            recording.stream_url.clone_from(&site.direct_url);
+        }
+        self.room_changed(site);
+    }
+
+    /// Takes over the title and area of the room, leaving the stream alone.
+    pub fn room_changed(&self, site: &Site) {
+        let mut recording = self.0.lock().unwrap();
+        recording.area.clone_from(&site.area);
+        if recording.titles.last().map(|t| &t.title) != Some(&site.title) {
+            recording.titles.push(TitleChange {
+                at: Local::now(),
+                title: site.title.clone(),
+            });
+        }
+    }
+
+    /// Writes the sidecar of `segment`, with the titles the room had while it was
+    /// recorded.
+    pub fn write(&self, segment: &SegmentInfo, emit: impl Fn(RecordingEvent)) {
+        let mut recording = self.0.lock().unwrap().clone();
+        let titles = std::mem::take(&mut recording.titles);
+        recording.titles = titles
+            .iter()
+            .enumerate()
+            .filter(|(i, change)| {
+                change.at <= segment.ended_at
+                    && titles
+                        .get(i + 1)
+                        .is_none_or(|next| next.at > segment.started_at)
+            })
+            .map(|(_, change)| change.clone())
+            .collect();
+        let mut path = segment.path.clone().into_os_string();
+        path.push(".json");
+        let path = std::path::PathBuf::from(path);
+        let written = serde_json::to_vec_pretty(&SidecarFile { recording, segment })
+            .map_err(std::io::Error::from)
+            .and_then(|json| std::fs::write(&path, json));
+        if let Err(e) = written {
+            error!("Unable to write {}: {e}", path.display());
+            emit(RecordingEvent::Error {
+                message: format!("Unable to write {}: {e}", path.display()),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader::event::{Resolution, SplitReason};
+    use crate::downloader::testing::{self, TempDir};
+    use chrono::TimeDelta;
+    use std::time::Duration;
+
+    #[test]
+    fn titles_while_recorded() -> anyhow::Result<()> {
+        let start = Local::now();
+        let change = |minutes, title: &str| TitleChange {
+            at: start + TimeDelta::minutes(minutes),
+            title: title.to_string(),
+        };
+        let sidecar = Sidecar(Arc::new(Mutex::new(Recording {
+            platform: "bilibili".to_string(),
+            room_id: "1".to_string(),
+            titles: vec![
+                change(-10, "before"),
+                change(-5, "opening"),
+                change(5, "playing"),
+                change(20, "after"),
+            ],
+            ..Default::default()
+        })));
+        let dir = TempDir::new("sidecar")?;
+        let segment = SegmentInfo {
+            started_at: start,
+            ended_at: start + TimeDelta::minutes(10),
+            reason: SplitReason::Policy,
+            video_codec: Some("avc1.64002a".to_string()),
+            resolution: Some(Resolution {
+                width: 1920,
+                height: 1080,
+            }),
+            ..testing::segment(dir.join("live.flv"), 3, Duration::from_secs(600))
+        };
+        sidecar.write(&segment, |event| panic!("{event:?}"));
+
+        let json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dir.join("live.flv.json"))?)?;
+        assert_eq!(json["platform"], "bilibili");
+        assert_eq!(json["duration"], 600.0);
+        assert_eq!(json["resolution"]["height"], 1080);
+        let titles: Vec<_> = json["titles"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|change| change["title"].as_str().unwrap())
+            .collect();
+        assert_eq!(titles, ["opening", "playing"]);
+        Ok(())
+    }
+}
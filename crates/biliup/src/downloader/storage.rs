@@ -0,0 +1,183 @@
+//! Keeps recordings from filling up the disk: [`DiskGuard`] refuses to start or continue
+//! a recording once the free space drops below a threshold, after deleting the oldest
+//! recordings its retention policy allows.
+
+use crate::downloader::event::{EventHandler, RecordingEvent};
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+/// Recordings that may be deleted to make room, such as those uploaded already.
+pub type Deletable = Arc<dyn Fn() -> Vec<PathBuf> + Send + Sync>;
+
+#[derive(Clone)]
+pub struct DiskGuard {
+    /// Free bytes below which recordings are refused.
+    pub min_free: u64,
+    /// Time between two checks of a running recording.
+    pub interval: Duration,
+    retention: Option<Deletable>,
+}
+
+impl Debug for DiskGuard {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DiskGuard")
+            .field("min_free", &self.min_free)
+            .field("interval", &self.interval)
+            .field("retention", &self.retention.is_some())
+            .finish()
+    }
+}
+
+impl DiskGuard {
+    pub fn new(min_free: u64) -> Self {
+        Self {
+            min_free,
+            interval: Duration::from_secs(10),
+            retention: None,
+        }
+    }
+
+    /// Deletes the oldest of the recordings `deletable` returns, until there is enough
+    /// space again, before refusing a recording.
+    pub fn with_retention(mut self, deletable: Deletable) -> Self {
+        self.retention = Some(deletable);
+        self
+    }
+
+    /// Makes sure [`DiskGuard::min_free`] bytes are available on the disk of `dir`,
+    /// failing with [`io::ErrorKind::StorageFull`] otherwise.
+    pub fn ensure(&self, dir: &Path, events: Option<&EventHandler>) -> io::Result<()> {
+        let emit = |event: RecordingEvent| {
+            if let Some(events) = events {
+                events(&event)
+            }
+        };
+        let mut available = available_space(dir)?;
+        if available >= self.min_free {
+            return Ok(());
+        }
+        if let Some(deletable) = &self.retention {
+            let mut recordings: Vec<_> = deletable()
+                .into_iter()
+                .filter_map(|path| {
+                    let metadata = path.metadata().ok()?;
+                    Some((metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH), path))
+                })
+                .collect();
+            recordings.sort();
+            for (_, path) in recordings {
+                if available >= self.min_free {
+                    break;
+                }
+                let size = path.metadata().map_or(0, |m| m.len());
+                match std::fs::remove_file(&path) {
+                    Ok(()) => {
+                        info!("Deleted {} to free disk space", path.display());
+                        emit(RecordingEvent::RecordingDeleted { path, size });
+                    }
+                    Err(e) => warn!("Unable to delete {}: {e}", path.display()),
+                }
+                available = available_space(dir)?;
+            }
+        }
+        if available >= self.min_free {
+            return Ok(());
+        }
+        emit(RecordingEvent::LowDiskSpace {
+            path: dir.to_path_buf(),
+            available,
+            min_free: self.min_free,
+        });
+        Err(io::Error::new(
+            io::ErrorKind::StorageFull,
+            format!(
+                "Only {available} bytes free in {}, at least {} required",
+                dir.display(),
+                self.min_free
+            ),
+        ))
+    }
+}
+
+/// Bytes available to this process on the disk of `path`.
+#[cfg(unix)]
+pub fn available_space(path: &Path) -> io::Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is NUL terminated and `stat` is only read after it was filled in.
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let stat = unsafe { stat.assume_init() };
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Bytes available to this process on the disk of `path`.
+#[cfg(windows)]
+pub fn available_space(path: &Path) -> io::Result<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut available = 0;
+    // SAFETY: `path` is NUL terminated, the totals not asked for may be null.
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            path.as_ptr(),
+            &mut available,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(available)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn available_space(_path: &Path) -> io::Result<u64> {
+    Ok(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader::testing::{TempDir, collect_events};
+
+    #[test]
+    fn delete_oldest_before_refusing() -> anyhow::Result<()> {
+        let dir = TempDir::new("storage")?;
+        let old = dir.join("old.flv");
+        std::fs::write(&old, b"old")?;
+        let (handler, events) = collect_events();
+
+        assert!(DiskGuard::new(0).ensure(dir.path(), Some(&handler)).is_ok());
+        let deletable: Deletable = {
+            let old = old.clone();
+            Arc::new(move || vec![old.clone()])
+        };
+        let result = DiskGuard::new(u64::MAX)
+            .with_retention(deletable)
+            .ensure(dir.path(), Some(&handler));
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::StorageFull);
+        assert!(!old.exists());
+        let events = events.lock().unwrap();
+        assert!(matches!(
+            &events[..],
+            [
+                RecordingEvent::RecordingDeleted { size: 3, .. },
+                RecordingEvent::LowDiskSpace { .. }
+            ]
+        ));
+        Ok(())
+    }
+}
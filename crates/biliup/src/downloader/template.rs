@@ -0,0 +1,167 @@
+//! File name templates. `{placeholder}`s are filled in with [`render`], values taken from
+//! the stream being made safe for file names with [`sanitize`]. [`LifecycleFile`] then
+//! expands `{seq}` and the strftime specifiers such as `%Y` whenever it creates a file.
+//!
+//! [`LifecycleFile`]: super::util::LifecycleFile
+
+use std::path::Path;
+
+/// Characters kept of a single substituted value.
+const MAX_VALUE_CHARS: usize = 80;
+
+/// Device names Windows doesn't allow as file names, with or without an extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Bytes kept of the last path component, leaving room for a counter, the extension and
+/// `.part` below the 255 bytes most file systems allow.
+const MAX_FILE_NAME_BYTES: usize = 200;
+
+/// Replaces each `{name}` in `template` with `value(name)`. Substitution is a single pass,
+/// so placeholders appearing in the substituted values are left alone. Unknown
+/// placeholders are kept as they are.
+pub fn render(template: &str, value: impl Fn(&str) -> Option<String>) -> String {
+    expand(template, value, false)
+}
+
+/// Like [`render`], but also turns `{{` and `}}` into literal braces. This is the last
+/// pass over a template that earlier passes filled with values [`escape`]d for it.
+pub fn render_escaped(template: &str, value: impl Fn(&str) -> Option<String>) -> String {
+    expand(template, value, true)
+}
+
+/// Doubles the braces in `value`, so [`render_escaped`] keeps them literally.
+pub fn escape(value: &str) -> String {
+    value.replace('{', "{{").replace('}', "}}")
+}
+
+fn expand(template: &str, value: impl Fn(&str) -> Option<String>, unescape: bool) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if unescape && (rest.starts_with("{{") || rest.starts_with("}}")) {
+            rendered.push_str(&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+        let substituted = rest
+            .strip_prefix('{')
+            .and_then(|name| name.find('}'))
+            .and_then(|end| Some((end + 1, value(&rest[1..end + 1])?)));
+        match substituted {
+            Some((end, value)) => {
+                rendered.push_str(&value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                rendered.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Makes `value` safe to use as (part of) a file name: path separators, characters not
+/// allowed on Windows and control characters become `_`, long values are cut short, then
+/// surrounding whitespace and trailing dots are removed. Device names Windows reserves,
+/// such as `CON` or `nul.txt`, get a `_` after the name.
+pub fn sanitize(value: &str) -> String {
+    let value: String = value.trim().chars().take(MAX_VALUE_CHARS).collect();
+    let mut value: String = value
+        .trim_end_matches(|c: char| c == '.' || c.is_whitespace())
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let name = value.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| name.eq_ignore_ascii_case(reserved))
+    {
+        value.insert(name.len(), '_');
+    }
+    value
+}
+
+/// Cuts the last component of `path` to [`MAX_FILE_NAME_BYTES`], at a character boundary.
+pub fn limit_file_name(path: &str) -> &str {
+    let start = path.rfind(['/', '\\']).map_or(0, |i| i + 1);
+    let mut end = path.len().min(start + MAX_FILE_NAME_BYTES);
+    while !path.is_char_boundary(end) {
+        end -= 1;
+    }
+    &path[..end]
+}
+
+/// `{stem}.{extension}`, or `{stem}-1.{extension}`, `{stem}-2.{extension}`... when a
+/// finished or unfinished recording of that name exists already.
+pub fn unique_file_name(stem: &str, extension: &str) -> String {
+    let taken =
+        |name: &str| Path::new(name).exists() || Path::new(&format!("{name}.part")).exists();
+    let mut file_name = format!("{stem}.{extension}");
+    let mut counter = 0;
+    while taken(&file_name) {
+        counter += 1;
+        file_name = format!("{stem}-{counter}.{extension}");
+    }
+    file_name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader::testing::TempDir;
+
+    #[test]
+    fn render_file_names() -> anyhow::Result<()> {
+        let value = |name: &str| match name {
+            "title" => Some(sanitize(" a/b: {streamer}.. ")),
+            "streamer" => Some("s".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            render("./{streamer}/{title}{seq}{", value),
+            "./s/a_b_ {streamer}{seq}{"
+        );
+        assert_eq!(
+            render_escaped(&format!("{{seq}}_{}", escape("{seq}}")), |name| {
+                (name == "seq").then(|| "1".to_string())
+            }),
+            "1_{seq}}"
+        );
+        assert_eq!(sanitize(".."), "");
+        assert_eq!(sanitize(&"长".repeat(100)).chars().count(), MAX_VALUE_CHARS);
+
+        let long = format!("dir/{}", "长".repeat(100));
+        let limited = limit_file_name(&long);
+        assert!(limited.len() <= "dir/".len() + MAX_FILE_NAME_BYTES);
+        assert!(limited.starts_with("dir/长"));
+
+        let dir = TempDir::new("template")?;
+        let stem = dir.join("live").to_string_lossy().into_owned();
+        std::fs::write(format!("{stem}.flv"), b"")?;
+        std::fs::write(format!("{stem}-1.flv.part"), b"")?;
+        assert_eq!(unique_file_name(&stem, "flv"), format!("{stem}-2.flv"));
+        Ok(())
+    }
+
+    #[test]
+    fn sanitize_for_windows() {
+        let cut = format!("{}. x", "a".repeat(MAX_VALUE_CHARS - 2));
+        assert_eq!(sanitize(&cut), "a".repeat(MAX_VALUE_CHARS - 2));
+        assert_eq!(sanitize("con"), "con_");
+        assert_eq!(sanitize("NUL.txt"), "NUL_.txt");
+        assert_eq!(sanitize("LPT1 "), "LPT1_");
+        assert_eq!(sanitize("CONSOLE"), "CONSOLE");
+        assert_eq!(sanitize("COM10"), "COM10");
+    }
+}
@@ -0,0 +1,58 @@
+//! Scaffolding shared by the tests of the downloader.
+
+use crate::downloader::event::{EventHandler, RecordingEvent, SegmentInfo, SplitReason};
+use chrono::Local;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Directory in the system temp dir, removed with everything in it on drop.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> std::io::Result<Self> {
+        let dir = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self(dir))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Segment at `path` that ended just now.
+pub fn segment(path: PathBuf, size: u64, duration: Duration) -> SegmentInfo {
+    let now = Local::now();
+    SegmentInfo {
+        path,
+        size,
+        started_at: now,
+        ended_at: now,
+        duration,
+        reason: SplitReason::End,
+        video_codec: None,
+        audio_codec: None,
+        resolution: None,
+    }
+}
+
+/// Handler keeping every event it receives.
+pub fn collect_events() -> (EventHandler, Arc<Mutex<Vec<RecordingEvent>>>) {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let handler: EventHandler = {
+        let events = events.clone();
+        Arc::new(move |event| events.lock().unwrap().push(event.clone()))
+    };
+    (handler, events)
+}
@@ -1,11 +1,18 @@
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
-use std::time::Duration;
-use tracing::{error, info};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
 
-use super::extractor::CallbackFn;
+use super::event::{EventHandler, RecordingEvent, Resolution, SegmentInfo, SplitReason};
+use super::filter::SegmentFilter;
+use super::postprocess::PostProcessor;
+use super::sidecar::Sidecar;
+use super::storage::DiskGuard;
+use super::template;
+use std::fmt::Write;
 
 #[derive(Debug)]
 pub enum Segment {
@@ -13,46 +20,167 @@ pub enum Segment {
     Size(u64, u64),
     Never,
 }
+/// When [`Segmentable`] starts a new file. Policies are combined with [`SegmentPolicy::Any`],
+/// whichever comes first, and [`SegmentPolicy::All`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SegmentPolicy {
+    /// Stream duration of the file.
+    Time(Duration),
+    /// Bytes written to the file.
+    Size(u64),
+    /// Wall-clock boundaries every `interval` since local midnight, moved by `offset`,
+    /// e.g. every full hour, or once a day at midnight.
+    Clock {
+        interval: Duration,
+        offset: Duration,
+    },
+    Any(Vec<SegmentPolicy>),
+    All(Vec<SegmentPolicy>),
+}
+
+impl SegmentPolicy {
+    /// Joins `policies` with [`SegmentPolicy::All`] if `all`, else with [`SegmentPolicy::Any`].
+    /// `None` without any policy.
+    pub fn combine(policies: Vec<SegmentPolicy>, all: bool) -> Option<SegmentPolicy> {
+        match (policies.is_empty(), all) {
+            (true, _) => None,
+            (false, true) => Some(SegmentPolicy::All(policies)),
+            (false, false) => Some(SegmentPolicy::Any(policies)),
+        }
+    }
+
+    fn needed(&self, segment: &Segmentable, now: DateTime<Local>) -> bool {
+        match self {
+            SegmentPolicy::Time(expected) => segment.duration() >= *expected,
+            SegmentPolicy::Size(expected) => segment.size.current > *expected,
+            SegmentPolicy::Clock { interval, offset } => {
+                next_boundary(segment.started_at, *interval, *offset).is_some_and(|b| now >= b)
+            }
+            SegmentPolicy::Any(policies) => policies.iter().any(|p| p.needed(segment, now)),
+            SegmentPolicy::All(policies) => {
+                !policies.is_empty() && policies.iter().all(|p| p.needed(segment, now))
+            }
+        }
+    }
+}
+
+/// The first wall-clock boundary after `after`.
+fn next_boundary<Tz: TimeZone>(
+    after: DateTime<Tz>,
+    interval: Duration,
+    offset: Duration,
+) -> Option<DateTime<Tz>> {
+    let interval = chrono::Duration::from_std(interval).ok()?;
+    if interval <= chrono::Duration::zero() {
+        return None;
+    }
+    let midnight = local_time(&after.timezone(), after.date_naive().and_hms_opt(0, 0, 0)?)?;
+    let mut anchor = midnight + chrono::Duration::from_std(offset).ok()?;
+    while anchor > after {
+        anchor -= chrono::Duration::days(1);
+    }
+    let elapsed = (after.clone() - anchor.clone()).num_milliseconds() / interval.num_milliseconds();
+    Some(anchor + interval * (elapsed as i32 + 1))
+}
+
+/// `time` in `tz`, or the first valid time after it when a DST change skips it, e.g. a
+/// midnight that does not exist.
+fn local_time<Tz: TimeZone>(tz: &Tz, time: NaiveDateTime) -> Option<DateTime<Tz>> {
+    (0..=24 * 60).find_map(|minutes| {
+        tz.from_local_datetime(&(time + chrono::Duration::minutes(minutes)))
+            .earliest()
+    })
+}
+
+/// Requests a new file from outside the recorder, see [`Segmentable::split_handle`].
+#[derive(Debug, Clone, Default)]
+pub struct SplitHandle(Arc<Mutex<Option<String>>>);
+
+impl SplitHandle {
+    /// Starts a new file at the next opportunity, named after `fmt_file_name`.
+    pub fn start_new(&self, fmt_file_name: String) {
+        *self.0.lock().unwrap() = Some(fmt_file_name);
+    }
+}
+
 #[derive(Debug)]
 pub struct Segmentable {
+    policy: Option<SegmentPolicy>,
+    filter: Option<SegmentFilter>,
+    split: SplitHandle,
     time: Time,
     size: Size,
+    /// Wall-clock time the current file was started at.
+    started_at: DateTime<Local>,
 }
 #[derive(Debug)]
 struct Time {
-    expected: Option<Duration>,
     start: Duration,
     current: Duration,
 }
 #[derive(Debug)]
 struct Size {
-    expected: Option<u64>,
     current: u64,
 }
 
 impl Segmentable {
+    /// Splits after `expected_time` or `expected_size`, whichever comes first.
     pub fn new(expected_time: Option<Duration>, expected_size: Option<u64>) -> Self {
+        let policies: Vec<SegmentPolicy> = expected_time
+            .map(SegmentPolicy::Time)
+            .into_iter()
+            .chain(expected_size.map(SegmentPolicy::Size))
+            .collect();
+        SegmentPolicy::combine(policies, false)
+            .map(Self::with_policy)
+            .unwrap_or_default()
+    }
+
+    pub fn with_policy(policy: SegmentPolicy) -> Self {
         Self {
-            time: Time {
-                expected: expected_time,
-                start: Duration::ZERO,
-                current: Duration::ZERO,
-            },
-            size: Size {
-                expected: expected_size,
-                current: 0,
-            },
+            policy: Some(policy),
+            ..Default::default()
         }
     }
 
+    /// Checks the finished files with `filter`, see [`LifecycleFile::filter`].
+    pub fn with_filter(mut self, filter: SegmentFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    pub fn filter(&self) -> Option<&SegmentFilter> {
+        self.filter.as_ref()
+    }
+
     pub fn needed(&self) -> bool {
-        if let Some(expected_time) = self.time.expected {
-            return (self.time.current - self.time.start) >= expected_time;
-        }
-        if let Some(expected_size) = self.size.expected {
-            return self.size.current > expected_size;
+        self.needed_at(Local::now())
+    }
+
+    fn needed_at(&self, now: DateTime<Local>) -> bool {
+        self.split.0.lock().unwrap().is_some()
+            || self
+                .policy
+                .as_ref()
+                .is_some_and(|policy| policy.needed(self, now))
+    }
+
+    pub fn split_handle(&self) -> SplitHandle {
+        self.split.clone()
+    }
+
+    /// Applies the file name of a pending [`SplitHandle::start_new`] to `file`, which
+    /// is about to start a new file.
+    pub fn rename_next(&mut self, file: &mut LifecycleFile) {
+        if let Some(fmt_file_name) = self.split.0.lock().unwrap().take() {
+            file.fmt_file_name = fmt_file_name;
+            file.split_reason = SplitReason::Metadata;
         }
-        false
+    }
+
+    /// Stream time of the current file.
+    pub fn duration(&self) -> Duration {
+        self.time.current.saturating_sub(self.time.start)
     }
 
     pub fn increase_time(&mut self, number: Duration) {
@@ -63,8 +191,10 @@ impl Segmentable {
         self.time.current = number
     }
 
+    /// Marks the start of a new file at stream time `number`.
     pub fn set_start_time(&mut self, number: Duration) {
-        self.time.start = number
+        self.time.start = number;
+        self.started_at = Local::now();
     }
 
     pub fn increase_size(&mut self, number: u64) {
@@ -75,24 +205,26 @@ impl Segmentable {
         self.size.current = number
     }
 
+    /// Starts counting for a new file.
     pub fn reset(&mut self) {
         self.size.current = 0;
         self.time.current = Duration::ZERO;
+        self.started_at = Local::now();
     }
 }
 
 impl Default for Segmentable {
     fn default() -> Self {
         Segmentable {
+            policy: None,
+            filter: None,
+            split: SplitHandle::default(),
             time: Time {
-                expected: None,
                 start: Duration::ZERO,
                 current: Duration::ZERO,
             },
-            size: Size {
-                expected: None,
-                current: 0,
-            },
+            size: Size { current: 0 },
+            started_at: Local::now(),
         }
     }
 }
@@ -101,64 +233,339 @@ pub struct LifecycleFile {
     pub fmt_file_name: String,
     pub file_name: String,
     pub path: PathBuf,
-    pub hook: CallbackFn,
+    pub events: Option<EventHandler>,
     pub extension: &'static str,
+    opened_at: DateTime<Local>,
+    /// Stream time written to the current file, kept up to date by the recorder.
+    pub duration: Duration,
+    /// Why the current file gets closed, set by the recorder before starting a new one.
+    pub split_reason: SplitReason,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub resolution: Option<Resolution>,
+    /// Number of files created so far, substituted for `{seq}`.
+    pub seq: u32,
+    /// Refuses new files and stops writing when the disk runs full.
+    pub guard: Option<DiskGuard>,
+    space_checked: Option<Instant>,
+    /// Decides whether a finished file is reported at all.
+    pub filter: Option<SegmentFilter>,
+    /// Runs on every finished file before it is reported.
+    pub postprocess: Option<PostProcessor>,
+    /// Describes every finished file in a JSON file next to it.
+    pub sidecar: Option<Sidecar>,
 }
 
 impl LifecycleFile {
-    pub fn new(fmt_file_name: &str, extension: &'static str, hook: Option<CallbackFn>) -> Self {
-        let hook: Box<dyn Fn(&str) + Send> = match hook {
-            Some(hook) => hook,
-            _ => Box::new(|_| {}),
-        };
+    pub fn new(fmt_file_name: &str, extension: &'static str, events: Option<EventHandler>) -> Self {
         Self {
             fmt_file_name: fmt_file_name.to_string(),
             file_name: "".to_string(),
             path: Default::default(),
-            hook,
+            events,
             extension,
+            opened_at: Local::now(),
+            duration: Duration::ZERO,
+            split_reason: SplitReason::default(),
+            video_codec: None,
+            audio_codec: None,
+            resolution: None,
+            seq: 0,
+            guard: None,
+            space_checked: None,
+            filter: None,
+            postprocess: None,
+            sidecar: None,
         }
     }
 
+    pub fn emit(&self, event: RecordingEvent) {
+        if let Some(events) = &self.events {
+            events(&event)
+        }
+    }
+
+    /// Creates the directories of the next file, named after `fmt_file_name` with `{seq}`,
+    /// the escaped braces and the strftime specifiers expanded, and returns its temporary path.
     pub fn create(&mut self) -> Result<&Path, std::io::Error> {
-        self.file_name = format!(
-            "{}.{}",
-            format_filename(&self.fmt_file_name),
-            self.extension
-        );
+        self.seq += 1;
+        let fmt_file_name = template::render_escaped(&self.fmt_file_name, |name| {
+            (name == "seq").then(|| self.seq.to_string())
+        });
+        let stem = format_filename(&fmt_file_name);
+        self.file_name =
+            template::unique_file_name(template::limit_file_name(&stem), self.extension);
         self.path = PathBuf::from(&self.file_name);
         if let Some(parent) = self.path.parent() {
             fs::create_dir_all(parent)?
         }
+        self.space_checked = None;
+        self.check_space()?;
         // path.set_extension(&self.extension);
         self.path.set_extension(format!("{}.part", self.extension));
         info!("Save to {}", self.path.display());
+        self.opened_at = Local::now();
+        self.duration = Duration::ZERO;
+        self.split_reason = SplitReason::default();
+        self.emit(RecordingEvent::FileOpened {
+            path: self.path.clone(),
+        });
         Ok(self.path.as_path())
     }
 
-    pub fn rename(&self) {
+    /// Replaces the current file, which mustn't have been written to, with one named
+    /// with `extension`.
+    pub fn reopen_as(&mut self, extension: &'static str) -> Result<&Path, std::io::Error> {
+        fs::remove_file(&self.path)?;
+        self.extension = extension;
+        self.seq -= 1;
+        self.create()
+    }
+
+    /// Checks the free space on the disk of the current file with `guard`, at most
+    /// every [`DiskGuard::interval`].
+    pub fn check_space(&mut self) -> std::io::Result<()> {
+        let Some(guard) = &self.guard else {
+            return Ok(());
+        };
+        if self
+            .space_checked
+            .is_some_and(|checked| checked.elapsed() < guard.interval)
+        {
+            return Ok(());
+        }
+        self.space_checked = Some(Instant::now());
+        let dir = match Path::new(&self.file_name).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        guard.ensure(dir, self.events.as_ref())
+    }
+
+    /// Renames the current file to its final name and reports it.
+    pub fn rename(&mut self) {
+        // Merging runs ffmpeg, which mustn't hold up the download.
+        if self.postprocess.is_none() && self.filter.as_ref().is_some_and(SegmentFilter::merges) {
+            self.postprocess = Some(PostProcessor::new(Vec::new()));
+        }
         match fs::rename(&self.path, &self.file_name) {
-            Ok(_) => (self.hook)(&self.file_name),
+            Ok(_) => {
+                let segment = SegmentInfo {
+                    path: PathBuf::from(&self.file_name),
+                    size: fs::metadata(&self.file_name).map_or(0, |m| m.len()),
+                    started_at: self.opened_at,
+                    ended_at: Local::now(),
+                    duration: self.duration,
+                    reason: self.split_reason,
+                    video_codec: self.video_codec.clone(),
+                    audio_codec: self.audio_codec.clone(),
+                    resolution: self.resolution,
+                };
+                match &self.postprocess {
+                    Some(postprocess) => postprocess.process(
+                        segment,
+                        self.filter.clone(),
+                        self.events.clone(),
+                        self.sidecar.clone(),
+                    ),
+                    None => match &self.filter {
+                        Some(filter) => filter
+                            .apply(segment, |event| self.emit(event))
+                            .into_iter()
+                            .for_each(|segment| self.report(segment)),
+                        None => self.report(segment),
+                    },
+                }
+            }
             Err(e) => {
-                error!("drop {} {e}", self.path.display())
+                error!("drop {} {e}", self.path.display());
+                self.emit(RecordingEvent::Error {
+                    message: format!("Unable to rename {}: {e}", self.path.display()),
+                });
+            }
+        }
+    }
+
+    /// Renames the last file of the recording and reports the file held back for
+    /// merging, which has nothing left to be merged into.
+    pub fn close(&mut self) {
+        self.rename();
+        self.flush_filter()
+    }
+
+    /// Reports the file held back for merging, for recordings whose last file was
+    /// discarded rather than renamed.
+    pub fn flush_filter(&mut self) {
+        let Some(filter) = &self.filter else {
+            return;
+        };
+        match &self.postprocess {
+            Some(postprocess) => {
+                postprocess.flush(filter.clone(), self.events.clone(), self.sidecar.clone())
+            }
+            None => {
+                if let Some(segment) = filter.flush() {
+                    self.report(segment)
+                }
             }
         }
     }
+
+    fn report(&self, segment: SegmentInfo) {
+        if let Some(sidecar) = &self.sidecar {
+            sidecar.write(&segment, |event| self.emit(event));
+        }
+        self.emit(RecordingEvent::SegmentClosed(segment))
+    }
 }
 
+/// Expands the strftime specifiers in `file_name`, which is used as is if they're invalid.
 pub fn format_filename(file_name: &str) -> String {
     let local: DateTime<Local> = Local::now();
-    // let time_str = local.format("%Y-%m-%dT%H_%M_%S");
-    let time_str = local.format(file_name);
-    // format!("{file_name}{time_str}")
-    time_str.to_string()
+    let mut time_str = String::new();
+    match write!(time_str, "{}", local.format(file_name)) {
+        Ok(()) => time_str,
+        Err(_) => {
+            warn!("Invalid time format in {file_name}");
+            file_name.to_string()
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
     use anyhow::Result;
+    use chrono::{FixedOffset, MappedLocalTime, NaiveDate};
     use std::path::{Path, PathBuf};
 
+    #[test]
+    fn combined_policies() {
+        let mut segment = Segmentable::new(Some(Duration::from_secs(60)), Some(100));
+        segment.increase_size(101);
+        assert!(segment.needed());
+        segment.reset();
+        segment.increase_time(Duration::from_secs(60));
+        assert!(segment.needed());
+
+        let mut segment = Segmentable::with_policy(SegmentPolicy::All(vec![
+            SegmentPolicy::Time(Duration::from_secs(60)),
+            SegmentPolicy::Size(100),
+        ]));
+        segment.increase_size(101);
+        assert!(!segment.needed());
+        segment.increase_time(Duration::from_secs(60));
+        assert!(segment.needed());
+        assert!(!Segmentable::default().needed());
+    }
+
+    #[test]
+    fn split_on_request() {
+        let mut segment = Segmentable::default();
+        segment.split_handle().start_new("new title".to_string());
+        assert!(segment.needed());
+        let mut file = LifecycleFile::new("old title", "flv", None);
+        segment.rename_next(&mut file);
+        assert_eq!(file.fmt_file_name, "new title");
+        assert!(!segment.needed());
+    }
+
+    #[test]
+    fn invalid_time_format() {
+        assert_eq!(format_filename("50%% off"), "50% off");
+        assert_eq!(format_filename("50% off"), "50% off");
+    }
+
+    #[test]
+    fn clock_boundaries() {
+        let at = |h, m| Local.with_ymd_and_hms(2024, 5, 1, h, m, 0).unwrap();
+        let hour = Duration::from_secs(3600);
+        assert_eq!(
+            next_boundary(at(12, 30), hour, Duration::ZERO),
+            Some(at(13, 0))
+        );
+        assert_eq!(
+            next_boundary(at(13, 0), hour, Duration::ZERO),
+            Some(at(14, 0))
+        );
+        assert_eq!(next_boundary(at(2, 0), 24 * hour, 4 * hour), Some(at(4, 0)));
+        assert_eq!(
+            next_boundary(
+                at(12, 30),
+                30 * Duration::from_secs(60),
+                Duration::from_secs(600)
+            ),
+            Some(at(12, 40))
+        );
+
+        let mut segment = Segmentable::with_policy(SegmentPolicy::Clock {
+            interval: hour,
+            offset: Duration::ZERO,
+        });
+        segment.started_at = at(12, 30);
+        assert!(!segment.needed_at(at(12, 59)));
+        assert!(segment.needed_at(at(13, 0)));
+    }
+
+    /// UTC until 2024-03-31, then UTC+1 from a midnight that does not exist.
+    #[derive(Debug, Clone)]
+    struct MidnightGap;
+
+    impl MidnightGap {
+        fn change() -> NaiveDateTime {
+            NaiveDate::from_ymd_opt(2024, 3, 31)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+        }
+    }
+
+    impl TimeZone for MidnightGap {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            MidnightGap
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> MappedLocalTime<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(
+            &self,
+            local: &NaiveDateTime,
+        ) -> MappedLocalTime<FixedOffset> {
+            let change = Self::change();
+            if *local < change {
+                MappedLocalTime::Single(FixedOffset::east_opt(0).unwrap())
+            } else if *local < change + chrono::Duration::hours(1) {
+                MappedLocalTime::None
+            } else {
+                MappedLocalTime::Single(FixedOffset::east_opt(3600).unwrap())
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            let offset = if *utc < Self::change() { 0 } else { 3600 };
+            FixedOffset::east_opt(offset).unwrap()
+        }
+    }
+
+    #[test]
+    fn clock_boundaries_without_midnight() {
+        let at = |h, m| MidnightGap.with_ymd_and_hms(2024, 3, 31, h, m, 0).unwrap();
+        let hour = Duration::from_secs(3600);
+        assert_eq!(
+            next_boundary(at(1, 30), hour, Duration::ZERO),
+            Some(at(2, 0))
+        );
+        assert_eq!(next_boundary(at(2, 0), 24 * hour, 4 * hour), Some(at(5, 0)));
+    }
+
     #[test]
     fn it_works() -> Result<()> {
         let mut p = PathBuf::from("/feel/the");
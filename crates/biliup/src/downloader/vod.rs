@@ -0,0 +1,294 @@
+//! Downloads published bilibili videos, the DASH audio and video streams are fetched
+//! concurrently and muxed into a single MP4 with [`fmp4::mux`].
+
+use crate::downloader::error::{Error, Result};
+use crate::downloader::extractor::Codec;
+use crate::downloader::fmp4;
+use crate::downloader::template;
+use crate::uploader::bilibili::Vid;
+use reqwest::StatusCode;
+use reqwest::header::{RANGE, REFERER};
+use reqwest_middleware::ClientWithMiddleware;
+use serde_json::Value;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+const REFERER_URL: &str = "https://www.bilibili.com/";
+
+/// Stream selection for [`download`].
+#[derive(Debug, Clone, Default)]
+pub struct VodOptions {
+    /// 1-based page (分P) of the video.
+    pub page: usize,
+    /// Target qn, the highest available quality is used when it isn't offered.
+    /// Qualities above 480P require a logged-in client.
+    pub quality: Option<u32>,
+    pub codec: Option<Codec>,
+}
+
+/// Downloads page `options.page` of `vid` to `<output>.mp4`, where `output` may contain
+/// the `{title}` and `{part}` placeholders. Interrupted downloads are resumed from the
+/// `.m4s` files left next to it, unless they're of another quality or codec.
+///
+/// Pass the client of a logged-in [`BiliBili`](crate::bilibili::BiliBili) for the qualities
+/// only available to members.
+pub async fn download(
+    client: &ClientWithMiddleware,
+    vid: &Vid,
+    options: &VodOptions,
+    output: &str,
+) -> Result<PathBuf> {
+    let view: Value = client
+        .get(format!(
+            "https://api.bilibili.com/x/web-interface/view?{vid}"
+        ))
+        .header(REFERER, REFERER_URL)
+        .send()
+        .await?
+        .json()
+        .await?;
+    if view["code"] != 0 {
+        return Err(Error::Custom(format!("{}", view["message"])));
+    }
+    let page = &view["data"]["pages"][options.page.max(1) - 1];
+    let Some(cid) = page["cid"].as_u64() else {
+        return Err(Error::Custom(format!("No page {} in {vid}", options.page)));
+    };
+    let output = template::render(output, |name| {
+        let value = match name {
+            "title" => &view["data"]["title"],
+            "part" => &page["part"],
+            _ => return None,
+        };
+        Some(template::sanitize(value.as_str().unwrap_or_default()))
+    });
+
+    let mut play_url: Value = client
+        .get(format!(
+            "https://api.bilibili.com/x/player/playurl?{vid}&cid={cid}&qn={}&fnval=4048&fourk=1",
+            options.quality.unwrap_or(127)
+        ))
+        .header(REFERER, REFERER_URL)
+        .send()
+        .await?
+        .json()
+        .await?;
+    if play_url["code"] != 0 {
+        return Err(Error::Custom(format!("{}", play_url["message"])));
+    }
+    let dash = play_url["data"]["dash"].take();
+    let (video, audio) = select_streams(&dash, options)
+        .ok_or_else(|| Error::Custom(format!("No DASH streams: {dash}")))?;
+    info!(
+        "Downloading {vid} P{} qn={} codecid={}",
+        options.page.max(1),
+        video["id"],
+        video["codecid"]
+    );
+
+    let video_path = PathBuf::from(format!("{output}.video.m4s"));
+    let audio_path = PathBuf::from(format!("{output}.audio.m4s"));
+    futures::future::try_join(
+        fetch(client, &stream_urls(video), &video_path),
+        fetch(client, &stream_urls(audio), &audio_path),
+    )
+    .await?;
+
+    let path = PathBuf::from(format!("{output}.mp4"));
+    fmp4::mux(&video_path, &audio_path, &path)?;
+    std::fs::remove_file(&video_path)?;
+    std::fs::remove_file(&audio_path)?;
+    info!("Saved {}", path.display());
+    Ok(path)
+}
+
+/// Picks the video stream with the target qn, or the highest one, preferring
+/// `options.codec` (AVC by default), and the audio stream with the highest bandwidth.
+fn select_streams<'a>(dash: &'a Value, options: &VodOptions) -> Option<(&'a Value, &'a Value)> {
+    let videos = dash["video"].as_array()?;
+    let qn = videos
+        .iter()
+        .filter_map(|v| v["id"].as_u64())
+        .find(|id| options.quality.is_some_and(|qn| *id == qn as u64))
+        .or_else(|| videos.iter().filter_map(|v| v["id"].as_u64()).max())?;
+    let codec_id = match options.codec.unwrap_or(Codec::Avc) {
+        Codec::Avc => 7,
+        Codec::Hevc => 12,
+    };
+    let video = videos
+        .iter()
+        .filter(|v| v["id"] == qn)
+        .max_by_key(|v| (v["codecid"] == codec_id, v["bandwidth"].as_u64()))?;
+    let audio = dash["audio"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .chain(Some(&dash["flac"]["audio"]).filter(|flac| flac.is_object()))
+        .max_by_key(|a| a["bandwidth"].as_u64())?;
+    Some((video, audio))
+}
+
+fn stream_urls(stream: &Value) -> Vec<String> {
+    ["baseUrl", "base_url"]
+        .iter()
+        .filter_map(|key| stream[key].as_str())
+        .take(1)
+        .chain(
+            stream["backupUrl"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|url| url.as_str()),
+        )
+        .map(str::to_string)
+        .collect()
+}
+
+/// Downloads the first working url to `path`, continuing after the bytes already there.
+async fn fetch(client: &ClientWithMiddleware, urls: &[String], path: &Path) -> Result<()> {
+    let mut last_err = Error::Custom(format!("No url for {}", path.display()));
+    for url in urls {
+        match fetch_url(client, url, path).await {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                warn!("Unable to download {url}: {e}");
+                last_err = e;
+            }
+        }
+    }
+    Err(last_err)
+}
+
+async fn fetch_url(client: &ClientWithMiddleware, url: &str, path: &Path) -> Result<()> {
+    let mut downloaded = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    if downloaded > 0 && !same_stream(client, url, path).await? {
+        warn!(
+            "{} was left by another quality or codec, starting over",
+            path.display()
+        );
+        downloaded = 0;
+    }
+    let mut response = client
+        .get(url)
+        .header(REFERER, REFERER_URL)
+        .header(RANGE, format!("bytes={downloaded}-"))
+        .send()
+        .await?;
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        info!("{} is already complete", path.display());
+        return Ok(());
+    }
+    response.error_for_status_ref()?;
+    let resume = downloaded > 0 && response.status() == StatusCode::PARTIAL_CONTENT;
+    if resume {
+        info!("Resuming {} from {downloaded} bytes", path.display());
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resume)
+        .truncate(!resume)
+        .open(path)?;
+    let mut writer = BufWriter::new(file);
+    while let Some(chunk) = response.chunk().await? {
+        writer.write_all(&chunk)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Whether the file at `path` starts with the initialization section of the stream at
+/// `url`, which differs between qualities and codecs.
+async fn same_stream(client: &ClientWithMiddleware, url: &str, path: &Path) -> Result<bool> {
+    let mut head = Vec::new();
+    File::open(path)?.take(1 << 20).read_to_end(&mut head)?;
+    let Some(length) = init_length(&head) else {
+        return Ok(false);
+    };
+    let mut response = client
+        .get(url)
+        .header(REFERER, REFERER_URL)
+        .header(RANGE, format!("bytes=0-{}", length - 1))
+        .send()
+        .await?;
+    response.error_for_status_ref()?;
+    let mut remote = Vec::with_capacity(length);
+    while remote.len() < length
+        && let Some(chunk) = response.chunk().await?
+    {
+        remote.extend_from_slice(&chunk);
+    }
+    Ok(remote.get(..length) == Some(&head[..length]))
+}
+
+/// Length of the initialization section at the start of `data`, the boxes up to and
+/// including `moov`.
+fn init_length(data: &[u8]) -> Option<usize> {
+    let mut offset = 0;
+    while let Some(header) = data.get(offset..offset + 8) {
+        let size = u32::from_be_bytes(header[..4].try_into().ok()?) as usize;
+        if size < 8 {
+            return None;
+        }
+        offset += size;
+        if &header[4..] == b"moov" {
+            return (offset <= data.len()).then_some(offset);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn select_dash_streams() {
+        let dash = json!({
+            "video": [
+                {"id": 80, "codecid": 12, "bandwidth": 1000, "baseUrl": "80-hevc"},
+                {"id": 80, "codecid": 7, "bandwidth": 2000, "baseUrl": "80-avc", "backupUrl": ["80-avc-backup"]},
+                {"id": 64, "codecid": 7, "bandwidth": 900, "baseUrl": "64-avc"},
+            ],
+            "audio": [
+                {"id": 30216, "bandwidth": 60000, "baseUrl": "64k"},
+                {"id": 30280, "bandwidth": 190000, "baseUrl": "192k"},
+            ],
+        });
+        let (video, audio) = select_streams(&dash, &VodOptions::default()).unwrap();
+        assert_eq!(stream_urls(video), ["80-avc", "80-avc-backup"]);
+        assert_eq!(stream_urls(audio), ["192k"]);
+
+        let options = VodOptions {
+            quality: Some(64),
+            codec: Some(Codec::Hevc),
+            ..Default::default()
+        };
+        let (video, _) = select_streams(&dash, &options).unwrap();
+        assert_eq!(stream_urls(video), ["64-avc"]);
+        let options = VodOptions {
+            codec: Some(Codec::Hevc),
+            ..Default::default()
+        };
+        let (video, _) = select_streams(&dash, &options).unwrap();
+        assert_eq!(stream_urls(video), ["80-hevc"]);
+    }
+
+    #[test]
+    fn init_section() {
+        let boxed = |kind: &[u8], size: u32| {
+            let mut data = size.to_be_bytes().to_vec();
+            data.extend_from_slice(kind);
+            data.resize(size as usize, 0);
+            data
+        };
+        let mut data = [boxed(b"ftyp", 24), boxed(b"moov", 100), boxed(b"sidx", 40)].concat();
+        assert_eq!(init_length(&data), Some(124));
+        data.truncate(100);
+        assert_eq!(init_length(&data), None);
+        assert_eq!(init_length(&boxed(b"mdat", 16)), None);
+    }
+}
@@ -42,17 +42,24 @@ where
 }
 
 trait ReqwestClientBuilderExt {
-    fn proxy_builder<U: reqwest::IntoUrl>(proxy: Option<U>) -> reqwest::ClientBuilder;
+    fn proxy_builder(
+        proxy: Option<&str>,
+        purpose: client::proxy::Purpose,
+    ) -> error::Result<reqwest::ClientBuilder>;
 }
 
 impl ReqwestClientBuilderExt for reqwest::Client {
-    fn proxy_builder<U: reqwest::IntoUrl>(proxy: Option<U>) -> reqwest::ClientBuilder {
+    /// A builder routing the requests of a `purpose` client by the
+    /// [`ProxyRoutes`](client::proxy::ProxyRoutes) in `proxy`.
+    fn proxy_builder(
+        proxy: Option<&str>,
+        purpose: client::proxy::Purpose,
+    ) -> error::Result<reqwest::ClientBuilder> {
         match proxy {
             Some(proxy) => {
-                tracing::debug!("使用代理: {}", proxy.as_str());
-                Self::builder().proxy(reqwest::Proxy::all(proxy).unwrap())
+                client::proxy::ProxyRoutes::parse(proxy)?.apply(purpose, Self::builder())
             }
-            None => Self::builder(),
+            None => Ok(Self::builder()),
         }
     }
 }
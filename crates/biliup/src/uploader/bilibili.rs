@@ -1,6 +1,8 @@
-use crate::ReqwestClientBuilderExt;
+use crate::client::configured_builder;
+use crate::client::proxy::Purpose;
 use crate::error::{Kind, Result};
 use crate::uploader::credential::LoginInfo;
+use reqwest_middleware::ClientWithMiddleware;
 use serde::ser::Error;
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
@@ -232,6 +234,8 @@ impl Display for Vid {
 #[derive(Clone, Debug)]
 pub struct BiliBili {
     pub client: reqwest::Client,
+    /// `client` with the [`Cassette`](crate::client::cassette::Cassette), if any.
+    pub http: ClientWithMiddleware,
     pub login_info: LoginInfo,
 }
 
@@ -271,7 +275,7 @@ impl BiliBili {
             payload
         };
 
-        let ret: ResponseData = reqwest::Client::proxy_builder(proxy)
+        let ret: ResponseData = configured_builder(proxy, Purpose::Upload)?
             .user_agent("Mozilla/5.0 BiliDroid/7.80.0 (bbcallen@gmail.com) os/android model/MI 6 mobi_app/android build/7800300 channel/bili innerVer/7800310 osVer/13 network/2")
             .timeout(Duration::new(60, 0))
             .build()?
@@ -303,7 +307,7 @@ impl BiliBili {
             .unwrap()
             .as_millis();
         let ret: serde_json::Value = self
-            .client
+            .http
             .post(format!(
                 "https://member.bilibili.com/x/vu/web/edit?t={ts}&csrf={}",
                 self.get_csrf()?
@@ -324,7 +328,7 @@ impl BiliBili {
 
     /// 查询视频的 json 信息
     pub async fn video_data(&self, vid: &Vid, proxy: Option<&str>) -> Result<Value> {
-        let res: ResponseData = reqwest::Client::proxy_builder(proxy)
+        let res: ResponseData = configured_builder(proxy, Purpose::Upload)?
             .user_agent("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 Chrome/63.0.3239.108")
             .timeout(Duration::new(60, 0))
             .build()?
@@ -371,7 +375,7 @@ impl BiliBili {
 
     pub async fn my_info(&self) -> Result<Value> {
         Ok(self
-            .client
+            .http
             .get("https://api.bilibili.com/x/space/myinfo")
             .send()
             .await?
@@ -381,7 +385,7 @@ impl BiliBili {
 
     pub async fn archive_pre(&self) -> Result<Value> {
         Ok(self
-            .client
+            .http
             .get("https://member.bilibili.com/x/vupre/web/archive/pre")
             .send()
             .await?
@@ -391,7 +395,7 @@ impl BiliBili {
 
     pub async fn recommend_tag(&self, subtype_id: u16, title: &str, key: &str) -> Result<Value> {
         let result: ResponseData = self
-            .client
+            .http
             .get(format!("https://member.bilibili.com/x/vupre/web/tag/recommend?upload_id=&subtype_id={subtype_id}&title={title}&filename={key}&description=&cover_url=&t="))
             .send()
             .await?
@@ -422,7 +426,7 @@ impl BiliBili {
 
     pub async fn cover_up(&self, input: &[u8]) -> Result<String> {
         let response = self
-            .client
+            .http
             .post("https://member.bilibili.com/x/vu/web/cover/up")
             .form(&json!({
                 "cover": format!("data:image/jpeg;base64,{}", base64::Engine::encode(&base64::engine::general_purpose::STANDARD, input)),
@@ -58,11 +58,12 @@ pub fn bilibili_from_cookies(file: impl AsRef<Path>, proxy: Option<&str>) -> Res
 }
 
 pub fn bilibili_from_info(login_info: LoginInfo, proxy: Option<&str>) -> Result<BiliBili> {
-    let client = Credential::new(proxy);
+    let client = Credential::new(proxy)?;
     client.set_cookie(&login_info.cookie_info);
     info!("通过cookie登录");
     Ok(BiliBili {
         client: client.0.client,
+        http: client.0.http,
         login_info,
     })
 }
@@ -72,7 +73,7 @@ pub async fn login_by_cookies(file: impl AsRef<Path>, proxy: Option<&str>) -> Re
     let mut file = std::fs::File::options().read(true).write(true).open(file)?;
     let login_info: LoginInfo = serde_json::from_reader(std::io::BufReader::new(&file))?;
 
-    let client: Credential = Credential::new(proxy);
+    let client: Credential = Credential::new(proxy)?;
     let need_refresh = client.validate_tokens(&login_info).await?;
 
     if need_refresh {
@@ -126,13 +127,13 @@ pub struct OAuthInfo {
 pub struct Credential(StatefulClient);
 
 impl Credential {
-    pub fn new(proxy: Option<&str>) -> Self {
+    pub fn new(proxy: Option<&str>) -> Result<Self> {
         let mut headers = header::HeaderMap::new();
         headers.insert(
             "Referer",
             header::HeaderValue::from_static("https://www.bilibili.com/"),
         );
-        Self(StatefulClient::new(headers, proxy))
+        Ok(Self(StatefulClient::new(headers, proxy)?))
     }
 
     pub async fn validate_tokens(&self, login_info: &LoginInfo) -> Result<bool> {
@@ -152,7 +153,7 @@ impl Credential {
 
         let response = self
             .0
-            .client
+            .http
             .get("https://passport.bilibili.com/x/passport-login/oauth2/info")
             .query(&payload)
             .send()
@@ -198,7 +199,7 @@ impl Credential {
         };
         let response: ResponseData<ResponseValue> = self
             .0
-            .client
+            .http
             .post("https://passport.bilibili.com/x/passport-login/oauth2/refresh_token")
             .form(&payload)
             .send()
@@ -250,7 +251,7 @@ impl Credential {
         payload["sign"] = Value::from(sign);
         let response: ResponseData<ResponseValue> = self
             .0
-            .client
+            .http
             .post("https://passport.bilibili.com/x/passport-login/oauth2/login")
             .form(&payload)
             .send()
@@ -281,7 +282,7 @@ impl Credential {
         payload["sign"] = Value::from(sign);
         let res: ResponseData<ResponseValue> = self
             .0
-            .client
+            .http
             .post("https://passport.bilibili.com/x/passport-login/login/sms")
             .form(&payload)
             .send()
@@ -387,7 +388,7 @@ impl Credential {
         // form["sign"] = Value::from(sign);
         let res: ResponseData<ResponseValue> = self
             .0
-            .client
+            .http
             .post("https://passport.bilibili.com/x/passport-login/sms/send")
             .header("content-type", "application/x-www-form-urlencoded")
             .body(urlencoded)
@@ -430,7 +431,7 @@ impl Credential {
             tokio::time::sleep(Duration::from_secs(1)).await;
             let raw = self
                 .0
-                .client
+                .http
                 .post("https://passport.bilibili.com/x/passport-tv-login/qrcode/poll")
                 .form(&form)
                 .send()
@@ -471,7 +472,7 @@ impl Credential {
     pub async fn get_web_buvid(&self) -> Result<(String, String)> {
         let res: ResponseData<Value> = self
             .0
-            .client
+            .http
             .get("https://api.bilibili.com/x/frontend/finger/spi")
             .send()
             .await?
@@ -498,7 +499,7 @@ impl Credential {
         form["sign"] = Value::from(sign);
         Ok(self
             .0
-            .client
+            .http
             .post("https://passport.bilibili.com/x/passport-tv-login/qrcode/auth_code")
             .form(&form)
             .send()
@@ -514,7 +515,7 @@ impl Credential {
         });
         let response: Value = self
             .0
-            .client
+            .http
             .get("https://passport.bilibili.com/x/passport-login/web/key")
             .json(&payload)
             .send()
@@ -541,7 +542,7 @@ impl Credential {
         dede_user_id: &str,
     ) -> Result<LoginInfo> {
         info!("login_by_web_qrcode");
-        let qrcode: Value = self.0.client
+        let qrcode: Value = self.0.http
             .get("https://passport.bilibili.com/qrcode/getLoginUrl")
             .header(USER_AGENT, "Mozilla/5.0 (X11; Linux x86_64; rv:38.0) Gecko/20100101 Firefox/38.0 Iceweasel/38.2.1 BiliApp")
             .send()
@@ -550,7 +551,7 @@ impl Credential {
             .await?;
         let oauth_key = qrcode["data"]["oauthKey"].as_str();
         let cookies = format!("SESSDATA={sess_data}; DedeUserID={dede_user_id}");
-        self.0.client
+        self.0.http
             .post("https://passport.bilibili.com/qrcode/login/confirm")
             .header(USER_AGENT, "Mozilla/5.0 (X11; Linux x86_64; rv:38.0) Gecko/20100101 Firefox/38.0 Iceweasel/38.2.1 BiliApp")
             .header(COOKIE, cookies)
@@ -559,7 +560,7 @@ impl Credential {
             .form(&[("oauthKey", oauth_key)])
             .send()
             .await?.error_for_status()?;
-        self.0.client
+        self.0.http
             .post("https://passport.bilibili.com/qrcode/getLoginInfo")
             .header(USER_AGENT, "Mozilla/5.0 (X11; Linux x86_64; rv:38.0) Gecko/20100101 Firefox/38.0 Iceweasel/38.2.1 BiliApp")
             .form(&[("oauthKey", oauth_key)])
@@ -593,7 +594,7 @@ impl Credential {
         });
         let cookies = format!("SESSDATA={sess_data}; bili_jct={bili_jct}");
         info!("自动确认二维码");
-        let response = self.0.client
+        let response = self.0.http
             .post("https://passport.bilibili.com/x/passport-tv-login/h5/qrcode/confirm")
             .header("Cookie", cookies)
             // .header("native_api_from", "h5")
@@ -649,6 +650,7 @@ impl Credential {
 
 impl Default for Credential {
     fn default() -> Self {
-        Self::new(None)
+        // Without a proxy, only a broken TLS backend could make this fail.
+        Self::new(None).expect("unable to build the HTTP client")
     }
 }
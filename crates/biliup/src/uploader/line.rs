@@ -182,7 +182,7 @@ impl Line {
         });
         info!("pre_upload: {}", params);
         let response = bili
-            .client
+            .http
             .get(format!(
                 "https://member.bilibili.com/preupload?{}",
                 self.query
@@ -46,7 +46,7 @@ impl Cos {
         let chunk_size = 10485760;
         let _chunks_num = (total_size as f64 / chunk_size as f64).ceil() as u32; // 获取分块数量
 
-        let client = &self.client.client;
+        let client = &self.client.http;
         let temp;
         let url = if enable_internal {
             temp = self
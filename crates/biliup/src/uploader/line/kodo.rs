@@ -46,7 +46,7 @@ impl Kodo {
         let _chunk_size = 4194304;
         let mut parts = Vec::new();
         // let parts_cell = &RefCell::new(parts);
-        let client = &self.client.client;
+        let client = &self.client.http;
         let url = &self.url;
         let uptoken = &format!("UpToken {}", &self.bucket.uptoken);
         // let stream = read_chunk(file, chunk_size, process)
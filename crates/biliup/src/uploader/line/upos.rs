@@ -102,7 +102,7 @@ impl Upos {
         // 获取分块数量
         let chunks_num = (total_size as f64 / chunk_size as f64).ceil() as usize;
         // let file = tokio::io::BufReader::with_capacity(chunk_size, file);
-        let client = &self.client.client;
+        let client = &self.client.http;
         let url = &self.url;
         let upload_id = &*self.upload_id;
         let stream = stream
@@ -7,13 +7,15 @@ use uploader::{PyCredit, StudioPre};
 
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 
 use crate::uploader::UploadLine;
+use biliup::client::{ClientConfig, HttpVersion, parse_headers};
 use biliup::credential::Credential;
 use biliup::downloader::construct_headers;
-use biliup::downloader::extractor::CallbackFn;
-use biliup::downloader::util::Segmentable;
+use biliup::downloader::event::{EventHandler, RecordingEvent};
+use biliup::downloader::util::{SegmentPolicy, Segmentable};
 use tracing_subscriber::layer::SubscriberExt;
 
 #[derive(FromPyObject)]
@@ -26,6 +28,38 @@ pub enum PySegment {
         #[pyo3(attribute("size"))]
         size: u64,
     },
+    Clock {
+        #[pyo3(attribute("clock"))]
+        clock: u64,
+        #[pyo3(attribute("clock_offset"))]
+        clock_offset: u64,
+    },
+    Any {
+        #[pyo3(attribute("any"))]
+        any: Vec<PySegment>,
+    },
+    All {
+        #[pyo3(attribute("all"))]
+        all: Vec<PySegment>,
+    },
+}
+
+impl From<PySegment> for SegmentPolicy {
+    fn from(segment: PySegment) -> Self {
+        match segment {
+            PySegment::Time { time } => SegmentPolicy::Time(Duration::from_secs(time)),
+            PySegment::Size { size } => SegmentPolicy::Size(size),
+            PySegment::Clock {
+                clock,
+                clock_offset,
+            } => SegmentPolicy::Clock {
+                interval: Duration::from_secs(clock),
+                offset: Duration::from_secs(clock_offset),
+            },
+            PySegment::Any { any } => SegmentPolicy::Any(any.into_iter().map(Into::into).collect()),
+            PySegment::All { all } => SegmentPolicy::All(all.into_iter().map(Into::into).collect()),
+        }
+    }
 }
 
 #[pyfunction]
@@ -38,11 +72,12 @@ fn download(
     segment: PySegment,
     proxy: Option<String>,
 ) -> PyResult<()> {
-    download_with_callback(py, url, header_map, file_name, segment, None, proxy)
+    download_with_callback(py, url, header_map, file_name, segment, None, proxy, None)
 }
 
 #[pyfunction]
-#[pyo3(signature = (url,header_map,file_name,segment,file_name_callback_fn = None,proxy = None))]
+#[pyo3(signature = (url,header_map,file_name,segment,file_name_callback_fn = None,proxy = None,event_callback_fn = None))]
+#[allow(clippy::too_many_arguments)]
 fn download_with_callback(
     py: Python<'_>,
     url: &str,
@@ -51,6 +86,7 @@ fn download_with_callback(
     segment: PySegment,
     file_name_callback_fn: Option<PyObject>,
     proxy: Option<String>,
+    event_callback_fn: Option<PyObject>,
 ) -> PyResult<()> {
     py.allow_threads(|| {
         let map = construct_headers(header_map);
@@ -74,18 +110,27 @@ fn download_with_callback(
             .with_timer(local_time)
             .with_writer(non_blocking);
 
-        let segment = match segment {
-            PySegment::Time { time } => Segmentable::new(Some(Duration::from_secs(time)), None),
-            PySegment::Size { size } => Segmentable::new(None, Some(size)),
-        };
+        let segment = Segmentable::with_policy(segment.into());
 
-        let file_name_hook = file_name_callback_fn.map(|callback_fn| -> CallbackFn {
-            Box::new(move |fmt_file_name| {
-                Python::with_gil(|py| match callback_fn.call1(py, (fmt_file_name,)) {
-                    Ok(_) => {}
-                    Err(_) => {
+        let events: Option<EventHandler> = (file_name_callback_fn.is_some()
+            || event_callback_fn.is_some())
+        .then(|| -> EventHandler {
+            Arc::new(move |event| {
+                Python::with_gil(|py| {
+                    if let (Some(callback_fn), RecordingEvent::SegmentClosed(segment)) =
+                        (&file_name_callback_fn, event)
+                        && callback_fn
+                            .call1(py, (segment.path.to_string_lossy(),))
+                            .is_err()
+                    {
                         tracing::error!("Unable to invoke the callback function.")
                     }
+                    if let Some(callback_fn) = &event_callback_fn
+                        && let Ok(json) = serde_json::to_string(event)
+                        && callback_fn.call1(py, (json,)).is_err()
+                    {
+                        tracing::error!("Unable to invoke the event callback function.")
+                    }
                 })
             })
         });
@@ -97,7 +142,7 @@ fn download_with_callback(
                 map,
                 file_name,
                 segment,
-                file_name_hook,
+                events,
                 proxy.as_deref(),
             ) {
                 Ok(res) => Ok(res),
@@ -166,7 +211,7 @@ fn get_qrcode(proxy: Option<String>) -> PyResult<String> {
 fn login_by_qrcode(ret: String, proxy: Option<String>) -> PyResult<String> {
     let rt = tokio::runtime::Runtime::new().unwrap();
     rt.block_on(async {
-        let info = Credential::new(proxy.as_deref())
+        let info = Credential::new(proxy.as_deref())?
             .login_by_qrcode(serde_json::from_str(&ret).unwrap())
             .await?;
         let res = serde_json::to_string_pretty(&info)?;
@@ -393,6 +438,39 @@ fn upload_by_app(
     })
 }
 
+/// Settings of the HTTP clients created afterwards, timeouts in seconds.
+#[pyfunction]
+#[pyo3(signature = (user_agent=None, connect_timeout=60.0, read_timeout=None, headers=HashMap::new(), max_retries=5, min_retry_interval=1.0, max_retry_interval=1800.0, ca_certificates=vec![], http_version="auto"))]
+#[allow(clippy::too_many_arguments)]
+fn set_client_config(
+    user_agent: Option<String>,
+    connect_timeout: f64,
+    read_timeout: Option<f64>,
+    headers: HashMap<String, String>,
+    max_retries: u32,
+    min_retry_interval: f64,
+    max_retry_interval: f64,
+    ca_certificates: Vec<PathBuf>,
+    http_version: &str,
+) -> PyResult<()> {
+    let value_error = |err: String| pyo3::exceptions::PyValueError::new_err(err);
+    let seconds =
+        |secs: f64| Duration::try_from_secs_f64(secs).map_err(|e| value_error(e.to_string()));
+    let config = ClientConfig {
+        user_agent,
+        connect_timeout: seconds(connect_timeout)?,
+        read_timeout: read_timeout.map(seconds).transpose()?,
+        headers: parse_headers(headers).map_err(|e| value_error(e.to_string()))?,
+        max_retries,
+        min_retry_interval: seconds(min_retry_interval)?,
+        max_retry_interval: seconds(max_retry_interval)?,
+        ca_certificates,
+        http_version: http_version.parse::<HttpVersion>().map_err(value_error)?,
+    };
+    biliup::client::set_client_config(config)
+        .map_err(|err| pyo3::exceptions::PyRuntimeError::new_err(format!("{err:#?}")))
+}
+
 /// A Python module implemented in Rust.
 #[pymodule]
 fn stream_gears(m: &Bound<'_, PyModule>) -> PyResult<()> {
@@ -412,6 +490,7 @@ fn stream_gears(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(login_by_sms, m)?)?;
     m.add_function(wrap_pyfunction!(login_by_web_cookies, m)?)?;
     m.add_function(wrap_pyfunction!(login_by_web_qrcode, m)?)?;
+    m.add_function(wrap_pyfunction!(set_client_config, m)?)?;
     m.add_class::<UploadLine>()?;
     Ok(())
 }
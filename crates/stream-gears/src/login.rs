@@ -12,19 +12,21 @@ pub async fn send_sms(
     phone: u64,
     proxy: Option<&str>,
 ) -> Result<serde_json::Value> {
-    let ret = Credential::new(proxy).send_sms(phone, country_code).await?;
+    let ret = Credential::new(proxy)?
+        .send_sms(phone, country_code)
+        .await?;
     Ok(ret)
 }
 
 pub async fn login_by_sms(code: u32, res: serde_json::Value, proxy: Option<&str>) -> Result<bool> {
-    let info = Credential::new(proxy).login_by_sms(code, res).await?;
+    let info = Credential::new(proxy)?.login_by_sms(code, res).await?;
     let file = std::fs::File::create("cookies.json")?;
     serde_json::to_writer_pretty(&file, &info)?;
     Ok(true)
 }
 
 pub async fn get_qrcode(proxy: Option<&str>) -> Result<serde_json::Value> {
-    let qrcode = Credential::new(proxy).get_qrcode().await?;
+    let qrcode = Credential::new(proxy)?.get_qrcode().await?;
     Ok(qrcode)
 }
 
@@ -33,7 +35,7 @@ pub async fn login_by_web_cookies(
     bili_jct: &str,
     proxy: Option<&str>,
 ) -> Result<bool> {
-    let info = Credential::new(proxy)
+    let info = Credential::new(proxy)?
         .login_by_web_cookies(sess_data, bili_jct)
         .await?;
     let file = std::fs::File::create("cookies.json")?;
@@ -46,7 +48,7 @@ pub async fn login_by_web_qrcode(
     dede_user_id: &str,
     proxy: Option<&str>,
 ) -> Result<bool> {
-    let info = Credential::new(proxy)
+    let info = Credential::new(proxy)?
         .login_by_web_qrcode(sess_data, dede_user_id)
         .await?;
     let file = std::fs::File::create("cookies.json")?;
@@ -105,7 +105,7 @@ pub async fn upload(studio_pre: StudioPre, proxy: Option<&str>) -> Result<Respon
         _ => bilibili?,
     };
 
-    let client = StatelessClient::default();
+    let client = StatelessClient::for_upload(proxy)?;
     let mut videos = Vec::new();
     let line = match line {
         Some(UploadLine::Bda2) => line::bda2(),
@@ -234,7 +234,7 @@ pub async fn upload_by_app(studio_pre: StudioPre, proxy: Option<&str>) -> Result
         _ => bilibili?,
     };
 
-    let client = StatelessClient::default();
+    let client = StatelessClient::for_upload(proxy)?;
     let mut videos = Vec::new();
     let line = match line {
         Some(UploadLine::Bda2) => line::bda2(),
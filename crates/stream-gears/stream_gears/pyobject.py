@@ -28,6 +28,44 @@ class Segment:
         segment.size = size
         return segment
 
+    @staticmethod
+    def by_clock(interval: int, offset: int = 0) -> 'Segment':
+        """
+        按整点时刻分段, 如 3600 为每个整点, 86400 为每天零点
+
+        :param int interval: 分段间隔, 单位为秒, 从当地零点起算
+        :param int offset: 分段时刻相对零点的偏移, 单位为秒
+        :return: 视频分段设置
+        """
+        segment = Segment()
+        segment.clock = interval
+        segment.clock_offset = offset
+        return segment
+
+    @staticmethod
+    def any_of(*segments: 'Segment') -> 'Segment':
+        """
+        任一分段条件满足时分段
+
+        :param Segment segments: 分段条件
+        :return: 视频分段设置
+        """
+        segment = Segment()
+        segment.any = list(segments)
+        return segment
+
+    @staticmethod
+    def all_of(*segments: 'Segment') -> 'Segment':
+        """
+        所有分段条件都满足时分段
+
+        :param Segment segments: 分段条件
+        :return: 视频分段设置
+        """
+        segment = Segment()
+        segment.all = list(segments)
+        return segment
+
 
 class Credit:
     # FIXME: docstring
@@ -25,7 +25,8 @@ def download_with_callback(url: str,
                file_name: str,
                segment: Segment,
                file_name_callback_fn: Callable[[str], None],
-               proxy: Optional[str]) -> None:
+               proxy: Optional[str],
+               event_callback_fn: Optional[Callable[[str], None]] = None) -> None:
     """
     下载视频
 
@@ -35,6 +36,7 @@ def download_with_callback(url: str,
     :param Segment segment: 视频分段设置
     :param Callable[[str], None] file_name_callback_fn: 回调已下载完成文件名
     :param Optional[str] proxy: 代理
+    :param Optional[Callable[[str], None]] event_callback_fn: 回调录制事件(JSON), 如文件创建、分段完成、录制结束及错误
     """
 
 
@@ -110,6 +112,30 @@ def login_by_web_qrcode(sess_data: str, dede_user_id: str, proxy: Optional[str])
     """
 
 
+def set_client_config(user_agent: Optional[str] = None,
+                      connect_timeout: float = 60,
+                      read_timeout: Optional[float] = None,
+                      headers: Dict[str, str] = {},
+                      max_retries: int = 5,
+                      min_retry_interval: float = 1,
+                      max_retry_interval: float = 1800,
+                      ca_certificates: List[str] = [],
+                      http_version: str = "auto") -> None:
+    """
+    设置之后创建的HTTP客户端
+
+    :param Optional[str] user_agent: 替换默认的User-Agent
+    :param float connect_timeout: 连接超时秒数
+    :param Optional[float] read_timeout: 读取超时秒数, 默认不限制
+    :param Dict[str, str] headers: 附加到每个请求的请求头
+    :param int max_retries: 失败请求的最大重试次数
+    :param float min_retry_interval: 最短重试间隔秒数
+    :param float max_retry_interval: 最长重试间隔秒数
+    :param List[str] ca_certificates: 额外信任的PEM格式CA证书文件
+    :param str http_version: auto, http1 或 http2
+    """
+
+
 class UploadLine(Enum):
     """上传线路"""
 